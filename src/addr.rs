@@ -47,9 +47,9 @@ impl Addr6502 for CPU {
 
         // the IMP addressing mode function fetches the accumulator
         self.fetched = if INSTRUCTIONS[opcode].mode == AddrMode::Imp {
-            self.read(self.eff_addr)
-        } else {
             self.acc
+        } else {
+            self.read(self.eff_addr)
         };
     }
 
@@ -61,60 +61,60 @@ impl Addr6502 for CPU {
 
     fn imm(&mut self) -> bool {
         self.eff_addr = self.pc;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         return false;
     }
 
     fn zero(&mut self) -> bool {
         self.eff_addr = self.read(self.pc) as u16 & 0x00FF;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         return false;
     }
 
     fn zero_x(&mut self) -> bool {
         let addr: u16 = self.read(self.pc).into();
         self.eff_addr = (addr + self.x as u16) & 0x00FF;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         return false;
     }
 
     fn zero_y(&mut self) -> bool {
         let addr: u16 = self.read(self.pc).into();
         self.eff_addr = (addr + self.y as u16) & 0x00FF;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         return false;
     }
 
     fn abs(&mut self) -> bool {
-        let lo: u16 = self.read(self.pc + 0).into();
-        let hi: u16 = self.read(self.pc + 1).into();
+        let lo: u16 = self.read(self.pc).into();
+        let hi: u16 = self.read(self.pc.wrapping_add(1)).into();
         self.eff_addr = hi << 8 | lo;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         return false;
     }
 
     fn abs_x(&mut self) -> bool {
-        let lo: u16 = self.read(self.pc + 0).into();
-        let hi: u16 = self.read(self.pc + 1).into();
-        self.eff_addr = (hi << 8 | lo) + self.x as u16;
-        self.pc += 2;
+        let lo: u16 = self.read(self.pc).into();
+        let hi: u16 = self.read(self.pc.wrapping_add(1)).into();
+        self.eff_addr = (hi << 8 | lo).wrapping_add(self.x as u16);
+        self.pc = self.pc.wrapping_add(2);
         return (self.eff_addr & 0xFF00) != (hi << 8);
     }
 
     fn abs_y(&mut self) -> bool {
-        let lo: u16 = self.read(self.pc + 0).into();
-        let hi: u16 = self.read(self.pc + 1).into();
-        self.eff_addr = (hi << 8 | lo) + self.y as u16;
-        self.pc += 2;
+        let lo: u16 = self.read(self.pc).into();
+        let hi: u16 = self.read(self.pc.wrapping_add(1)).into();
+        self.eff_addr = (hi << 8 | lo).wrapping_add(self.y as u16);
+        self.pc = self.pc.wrapping_add(2);
         return (self.eff_addr & 0xFF00) != (hi << 8);
     }
 
     // ($C000)
     fn ind(&mut self) -> bool {
-        let ind_lo: u16 = self.read(self.pc + 0).into();
-        let ind_hi: u16 = self.read(self.pc + 1).into();
+        let ind_lo: u16 = self.read(self.pc).into();
+        let ind_hi: u16 = self.read(self.pc.wrapping_add(1)).into();
         let ind_hilo = ind_hi << 8 | ind_lo;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
 
         // https://www.nesdev.com/6502bugs.txt
         // *An indirect JMP (xxFF) will fail because the MSB will be fetched from
@@ -123,7 +123,7 @@ impl Addr6502 for CPU {
             if ind_lo == 0x00FF {
                 (self.read(ind_hilo).into(), self.read(ind_hi << 8).into())
             } else {
-                (self.read(ind_hilo).into(), self.read(ind_hilo + 1).into())
+                (self.read(ind_hilo).into(), self.read(ind_hilo.wrapping_add(1)).into())
             };
 
         self.eff_addr = hi << 8 | lo;
@@ -133,7 +133,7 @@ impl Addr6502 for CPU {
     // ($C0, X)
     fn ind_x(&mut self) -> bool {
         let zero_offset: u16 = self.read(self.pc).into();
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
 
         let lo: u16 = self.read((zero_offset + self.x as u16 + 0) & 0x00FF).into();
         let hi: u16 = self.read((zero_offset + self.x as u16 + 1) & 0x00FF).into();
@@ -145,11 +145,11 @@ impl Addr6502 for CPU {
     // ($C0), Y
     fn ind_y(&mut self) -> bool {
         let zero_addr: u16 = self.read(self.pc).into();
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
 
         let ind_lo: u16 = self.read((zero_addr + 0) & 0x00FF).into();
         let ind_hi: u16 = self.read((zero_addr + 1) & 0x00FF).into();
-        self.eff_addr = (ind_hi << 8 | ind_lo) + self.y as u16;
+        self.eff_addr = (ind_hi << 8 | ind_lo).wrapping_add(self.y as u16);
 
         return (self.eff_addr & 0xFF00) != ind_hi << 8;
     }
@@ -157,7 +157,7 @@ impl Addr6502 for CPU {
     fn rel(&mut self) -> bool {
         // the address is 16 bits, so make the offset 16 bits as well
         let mut offset: u16 = self.read(self.pc).into();
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
 
         // if sign bit is set, make the 16 bit offset negative
         if offset & 0x80 != 0 {
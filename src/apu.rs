@@ -0,0 +1,1263 @@
+use crate::audio::{AudioOutput, Channel, Mixer};
+use crate::region::Region;
+use crate::savestate::Savestate;
+
+// values loaded into the length counters, indexed by bits 3-7 of $4003/$4007/$400B/$400F
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// 12.5%, 25%, 50% and 25% negated, indexed by the sequencer step,
+// which counts down, so each waveform plays from right to left
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 1, 0, 0],
+];
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// Silences a channel once its note has played for long enough.
+// Counts down on half frames unless halted.
+// https://wiki.nesdev.org/w/index.php/APU_Length_Counter
+#[derive(Default)]
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.counter > 0
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.bool(&mut self.enabled);
+        state.bool(&mut self.halt);
+        state.u8(&mut self.counter);
+    }
+}
+
+// Either a constant volume or a sawtooth decaying from 15 to 0,
+// clocked on quarter frames.
+// https://wiki.nesdev.org/w/index.php/APU_Envelope
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // the constant volume, or the period of the divider
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // bits 0-5 of $4000/$4004/$400C: --lc vvvv
+    fn write(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant { self.volume } else { self.decay }
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        }
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.bool(&mut self.start);
+        state.bool(&mut self.looping);
+        state.bool(&mut self.constant);
+        state.u8(&mut self.volume);
+        state.u8(&mut self.divider);
+        state.u8(&mut self.decay);
+    }
+}
+
+/**
+ * $4000/$4004: DDLC VVVV - duty, length counter halt, constant volume, volume/envelope period
+ * $4001/$4005: EPPP NSSS - sweep enable, period, negate, shift
+ * $4002/$4006: LLLL LLLL - timer low
+ * $4003/$4007: llll lHHH - length counter load, timer high
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_Pulse
+ * https://wiki.nesdev.org/w/index.php/APU_Sweep
+ */
+#[derive(Default)]
+pub struct Pulse {
+    // pulse 1 negates its sweep with ones' complement, pulse 2 with two's complement
+    ones_complement: bool,
+
+    duty: u8,
+    step: u8,
+
+    period: u16,
+    timer: u16,
+
+    envelope: Envelope,
+    length: LengthCounter,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse { ones_complement, ..Pulse::default() }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.halt = value & 0x20 != 0;
+                self.envelope.write(value);
+            },
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 0x07;
+                self.sweep_reload = true;
+            },
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.load(value);
+                self.step = 0;
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // the period the sweep unit is heading for. It is computed
+    // all the time, and mutes the channel when out of range
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    // clocked every other CPU cycle. The sequencer counts down
+    // through the duty table, so it starts at step 0, then 7, 6...
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 7) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.muted() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.u8(&mut self.duty);
+        state.u8(&mut self.step);
+        state.u16(&mut self.period);
+        state.u16(&mut self.timer);
+        self.envelope.savestate(state);
+        self.length.savestate(state);
+        state.bool(&mut self.sweep_enabled);
+        state.u8(&mut self.sweep_period);
+        state.bool(&mut self.sweep_negate);
+        state.u8(&mut self.sweep_shift);
+        state.u8(&mut self.sweep_divider);
+        state.bool(&mut self.sweep_reload);
+    }
+}
+
+/**
+ * $4008: CRRR RRRR - length counter halt / linear counter control, linear counter reload
+ * $400A: LLLL LLLL - timer low
+ * $400B: llll lHHH - length counter load, timer high
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_Triangle
+ */
+#[derive(Default)]
+pub struct Triangle {
+    step: u8,
+
+    period: u16,
+    timer: u16,
+
+    length: LengthCounter,
+
+    // the linear counter gives the triangle a finer grained note length
+    linear_control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    linear_counter: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.linear_control = value & 0x80 != 0;
+                self.length.halt = self.linear_control;
+                self.linear_reload_value = value & 0x7F;
+            },
+            1 => {},
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.load(value);
+                self.linear_reload = true;
+            },
+        }
+    }
+
+    // clocked every CPU cycle. The sequencer only moves while
+    // both counters are non-zero, so the output holds its level
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.linear_control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.u8(&mut self.step);
+        state.u16(&mut self.period);
+        state.u16(&mut self.timer);
+        self.length.savestate(state);
+        state.bool(&mut self.linear_control);
+        state.u8(&mut self.linear_reload_value);
+        state.bool(&mut self.linear_reload);
+        state.u8(&mut self.linear_counter);
+    }
+}
+
+/**
+ * $400C: --LC VVVV - length counter halt, constant volume, volume/envelope period
+ * $400E: M--- PPPP - mode, period
+ * $400F: llll l--- - length counter load
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_Noise
+ */
+pub struct Noise {
+    // 15-bit linear feedback shift register
+    shift: u16,
+    // short mode takes the feedback from bit 6 instead of bit 1,
+    // which repeats after 93 or 31 steps and sounds metallic
+    short_mode: bool,
+
+    period: u16,
+    timer: u16,
+
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    fn new(region: Region) -> Self {
+        Noise {
+            shift: 1,
+            short_mode: false,
+            period: region.noise_periods()[0],
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8, region: Region) {
+        match register {
+            0 => {
+                self.length.halt = value & 0x20 != 0;
+                self.envelope.write(value);
+            },
+            1 => {},
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                self.period = region.noise_periods()[(value & 0x0F) as usize];
+            },
+            _ => {
+                self.length.load(value);
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // clocked every CPU cycle, the periods are in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period.saturating_sub(1);
+
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.u16(&mut self.shift);
+        state.bool(&mut self.short_mode);
+        state.u16(&mut self.period);
+        state.u16(&mut self.timer);
+        self.envelope.savestate(state);
+        self.length.savestate(state);
+    }
+}
+
+/**
+ * $4010: IL-- RRRR - IRQ enable, loop, rate
+ * $4011: -DDD DDDD - direct load of the output level
+ * $4012: AAAA AAAA - sample address, $C000 + A * 64
+ * $4013: LLLL LLLL - sample length, L * 16 + 1 bytes
+ *
+ * Plays 1-bit delta encoded samples, moving a 7-bit output level up or
+ * down by 2 for each bit. The sample bytes are fetched from CPU memory
+ * through the DMA unit, which stalls the CPU for each one.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_DMC
+ */
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+
+    period: u16,
+    timer: u16,
+
+    // 7-bit output level
+    level: u8,
+
+    sample_addr: u16,
+    sample_length: u16,
+
+    // memory reader
+    addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    // a fetch was asked of the DMA unit and hasn't arrived yet
+    dma_pending: bool,
+
+    // output unit
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    pub irq: bool,
+}
+
+impl Dmc {
+    fn new(region: Region) -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            period: region.dmc_rates()[0],
+            timer: 0,
+            level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            dma_pending: false,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8, region: Region) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.looping = value & 0x40 != 0;
+                self.period = region.dmc_rates()[(value & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            },
+            1 => self.level = value & 0x7F,
+            2 => self.sample_addr = 0xC000 | (value as u16) << 6,
+            _ => self.sample_length = (value as u16) << 4 | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    // the address of the next sample byte, once the buffer has room for it
+    pub fn dma_request(&mut self) -> Option<u16> {
+        if self.buffer.is_some() || self.bytes_remaining == 0 || self.dma_pending {
+            return None;
+        }
+        self.dma_pending = true;
+        Some(self.addr)
+    }
+
+    // the byte fetched by the DMA unit
+    pub fn load_sample(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.dma_pending = false;
+
+        // the address wraps around to $8000
+        self.addr = if self.addr == 0xFFFF { 0x8000 } else { self.addr + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle, the rates are in CPU cycles
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(value) => {
+                    self.shift = value;
+                    self.silence = false;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.bool(&mut self.irq_enabled);
+        state.bool(&mut self.looping);
+        state.u16(&mut self.period);
+        state.u16(&mut self.timer);
+        state.u8(&mut self.level);
+        state.u16(&mut self.sample_addr);
+        state.u16(&mut self.sample_length);
+        state.u16(&mut self.addr);
+        state.u16(&mut self.bytes_remaining);
+        state.option_u8(&mut self.buffer);
+        state.bool(&mut self.dma_pending);
+        state.u8(&mut self.shift);
+        state.u8(&mut self.bits_remaining);
+        state.bool(&mut self.silence);
+        state.bool(&mut self.irq);
+    }
+}
+
+/**
+ * $4017: MI-- ---- - mode (0: 4-step, 1: 5-step), IRQ inhibit
+ *
+ * Divides the CPU clock into the quarter and half frame clocks of the other
+ * units. The 4-step sequence raises the frame IRQ at its end, unless inhibited.
+ * A write only takes effect 3 or 4 CPU cycles later, depending on whether it
+ * lands on an APU cycle, and starting the 5-step sequence clocks everything
+ * right away.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_Frame_Counter
+ */
+#[derive(Default)]
+pub struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+
+    // read through bit 6 of $4015, which clears it
+    pub irq: bool,
+
+    // CPU cycles into the sequence and the next step to reach
+    cycle: u32,
+    step: usize,
+
+    // a $4017 write and the CPU cycles left until it applies
+    pending_write: Option<(u8, u8)>,
+}
+
+// what each step of the sequences clocks; half frames clock the quarter frame units too
+#[derive(Clone,Copy,PartialEq,Eq)]
+enum FrameStep {
+    Quarter,
+    Half,
+    None,
+}
+
+const FRAME_STEPS: [FrameStep; 6] = [
+    FrameStep::Quarter,
+    FrameStep::Half,
+    FrameStep::Quarter,
+    FrameStep::None,
+    FrameStep::Half,
+    FrameStep::None,
+];
+
+impl FrameCounter {
+    // `cycle` is the CPU cycle the write lands on, `cycles_ahead` cycles
+    // after the one the APU is clocking now
+    fn write(&mut self, value: u8, cycle: u64, cycles_ahead: u8) {
+        self.irq_inhibit = value & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+
+        let delay = if cycle % 2 == 1 { 4 } else { 3 };
+        self.pending_write = Some((value, delay + cycles_ahead));
+    }
+
+    // one CPU cycle, returns whether the quarter and half frame units are clocked
+    fn clock(&mut self, region: Region) -> (bool, bool) {
+        if let Some((value, delay)) = self.pending_write {
+            if delay > 1 {
+                self.pending_write = Some((value, delay - 1));
+            } else {
+                self.pending_write = None;
+                self.five_step = value & 0x80 != 0;
+                self.cycle = 0;
+                self.step = 0;
+                if self.five_step {
+                    return (true, true);
+                }
+                return (false, false);
+            }
+        }
+
+        self.cycle += 1;
+
+        let steps = region.frame_counter_steps(self.five_step);
+        if self.cycle != steps[self.step] {
+            return (false, false);
+        }
+
+        // the IRQ flag is set on the last three cycles of the 4-step sequence
+        if !self.five_step && self.step >= 3 && !self.irq_inhibit {
+            self.irq = true;
+        }
+
+        let clocked = FRAME_STEPS[self.step];
+
+        self.step += 1;
+        if self.step == steps.len() {
+            self.step = 0;
+            self.cycle = 0;
+        }
+
+        match clocked {
+            FrameStep::Quarter => (true, false),
+            FrameStep::Half => (true, true),
+            FrameStep::None => (false, false),
+        }
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) {
+        state.bool(&mut self.five_step);
+        state.bool(&mut self.irq_inhibit);
+        state.bool(&mut self.irq);
+        state.u32(&mut self.cycle);
+        state.usize(&mut self.step);
+
+        let mut pending = self.pending_write.is_some();
+        let (mut value, mut delay) = self.pending_write.unwrap_or((0, 0));
+        state.bool(&mut pending);
+        state.u8(&mut value);
+        state.u8(&mut delay);
+        self.pending_write = Some((value, delay)).filter(|_| pending);
+    }
+}
+
+/**
+ * $4000-$4003: Pulse 1
+ * $4004-$4007: Pulse 2
+ * $4008-$400B: Triangle
+ * $400C-$400F: Noise
+ * $4010-$4013: DMC
+ * $4015:       Channel enable (write) / status (read)
+ * $4017:       Frame counter
+ *
+ * The pulse channels' timers run at half the CPU clock, all
+ * other units of the APU are clocked every CPU cycle.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU
+ * https://wiki.nesdev.org/w/index.php/APU_registers
+ */
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+
+    pub frame_counter: FrameCounter,
+
+    // the noise and DMC periods and frame counter steps depend on the region
+    pub region: Region,
+
+    // CPU cycles since power on
+    pub cycle: u64,
+
+    // per channel volume, mute and solo
+    pub mixer: Mixer,
+
+    // the mixed output, resampled for the host
+    pub output: AudioOutput,
+
+    // each channel on its own, in `Channel` order, while stems are recorded
+    pub stems: Vec<AudioOutput>,
+
+    // CPU cycles since the last end of frame
+    frame_cycle: u32,
+}
+
+impl APU {
+    pub fn new(region: Region) -> Self {
+        APU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            frame_counter: FrameCounter::default(),
+            region,
+            cycle: 0,
+            mixer: Mixer::new(),
+            output: AudioOutput::new(region.cpu_clock_rate(), DEFAULT_SAMPLE_RATE),
+            stems: Vec::new(),
+            frame_cycle: 0,
+        }
+    }
+
+    // Power cycle: every channel and the frame counter start over,
+    // while the host side of the audio carries on
+    pub fn power(&mut self) {
+        let old = std::mem::replace(self, APU::new(self.region));
+        self.mixer = old.mixer;
+        self.output = old.output;
+        self.stems = old.stems;
+        self.frame_cycle = old.frame_cycle;
+    }
+
+    // start or stop producing a separate output for each channel
+    pub fn set_stems(&mut self, enabled: bool) {
+        self.stems = if enabled {
+            Channel::ALL.iter().map(|_| AudioOutput::new(self.output.clock_rate, self.output.sample_rate)).collect()
+        } else {
+            Vec::new()
+        };
+    }
+
+    // $4015: IF-D NT21 - DMC and frame IRQ, whether the DMC sample and
+    // the length counters are still running. Reading acknowledges the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let bit = |active: bool, n: u8| (active as u8) << n;
+
+        let status = bit(self.pulse1.length.active(), 0)
+            | bit(self.pulse2.length.active(), 1)
+            | bit(self.triangle.length.active(), 2)
+            | bit(self.noise.length.active(), 3)
+            | bit(self.dmc.active(), 4)
+            | bit(self.frame_counter.irq, 6)
+            | bit(self.dmc.irq, 7);
+
+        self.frame_counter.irq = false;
+        status
+    }
+
+    // the APU's IRQ line
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq || self.dmc.irq
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr & 0x03, value),
+            0x4004..=0x4007 => self.pulse2.write(addr & 0x03, value),
+            0x4008..=0x400B => self.triangle.write(addr & 0x03, value),
+            0x400C..=0x400F => self.noise.write(addr & 0x03, value, self.region),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, value, self.region),
+            0x4015 => {
+                self.pulse1.length.set_enabled(value & 0x01 != 0);
+                self.pulse2.length.set_enabled(value & 0x02 != 0);
+                self.triangle.length.set_enabled(value & 0x04 != 0);
+                self.noise.length.set_enabled(value & 0x08 != 0);
+                self.dmc.set_enabled(value & 0x10 != 0);
+            },
+            0x4017 => self.write_frame_counter(value, 0),
+            _ => {},
+        }
+    }
+
+    // A $4017 write made `cycles_ahead` CPU cycles from now. The CPU runs an
+    // instruction all at once on its first cycle, but the write happens on
+    // the last one, and whether that is an APU cycle decides the delay
+    pub fn write_frame_counter(&mut self, value: u8, cycles_ahead: u8) {
+        self.frame_counter.write(value, self.cycle + cycles_ahead as u64, cycles_ahead);
+    }
+
+    // envelopes and the triangle's linear counter
+    pub fn quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.clock_linear_counter();
+        self.noise.envelope.clock();
+    }
+
+    // length counters and sweeps
+    pub fn half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    // one CPU cycle
+    pub fn clock(&mut self) {
+        let (quarter, half) = self.frame_counter.clock(self.region);
+        if quarter {
+            self.quarter_frame();
+        }
+        if half {
+            self.half_frame();
+        }
+
+        if self.cycle.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        let levels = [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ];
+        let level = self.mixer.mix(levels[0], levels[1], levels[2], levels[3], levels[4]);
+        self.output.set_level(self.frame_cycle, level);
+
+        for (stem, (&channel, &level)) in self.stems.iter_mut().zip(Channel::ALL.iter().zip(levels.iter())) {
+            stem.set_level(self.frame_cycle, self.mixer.mix_alone(channel, level));
+        }
+
+        self.cycle += 1;
+        self.frame_cycle += 1;
+    }
+
+    // resample the audio generated since the last call
+    pub fn end_frame(&mut self) {
+        self.output.end_frame(self.frame_cycle);
+        for stem in self.stems.iter_mut() {
+            stem.end_frame(self.frame_cycle);
+        }
+        self.frame_cycle = 0;
+    }
+
+    // the channels and frame counter; the host side of the audio is left out
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        self.pulse1.savestate(state);
+        self.pulse2.savestate(state);
+        self.triangle.savestate(state);
+        self.noise.savestate(state);
+        self.dmc.savestate(state);
+        self.frame_counter.savestate(state);
+        state.u64(&mut self.cycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dmc, FrameCounter, Noise, Pulse, Triangle, APU};
+    use crate::region::Region;
+
+    // step the pulse sequencer once
+    fn next_step(pulse: &mut Pulse) {
+        let step = pulse.step;
+        while pulse.step == step {
+            pulse.clock_timer();
+        }
+    }
+
+    #[test]
+    fn pulse_duty_cycles() {
+        let expected = [
+            [0, 1, 0, 0, 0, 0, 0, 0],
+            [0, 1, 1, 0, 0, 0, 0, 0],
+            [0, 1, 1, 1, 1, 0, 0, 0],
+            [1, 0, 0, 1, 1, 1, 1, 1],
+        ];
+
+        for (duty, expected) in expected.iter().enumerate() {
+            let mut pulse = Pulse::new(true);
+            pulse.length.set_enabled(true);
+            pulse.write(0, (duty as u8) << 6 | 0x30 | 0x0F);
+            pulse.write(2, 0x10);
+            pulse.write(3, 0x08);
+
+            let mut waveform = [0; 8];
+            for (n, level) in waveform.iter_mut().enumerate() {
+                if n > 0 {
+                    next_step(&mut pulse);
+                }
+                *level = pulse.output() / 15;
+            }
+            assert_eq!(&waveform, expected, "duty {}", duty);
+        }
+    }
+
+    #[test]
+    fn sweep_negate() {
+        // shift 1, negate: pulse 1 subtracts one more than pulse 2
+        let mut pulse1 = Pulse::new(true);
+        let mut pulse2 = Pulse::new(false);
+        for pulse in [&mut pulse1, &mut pulse2] {
+            pulse.write(1, 0x80 | 0x08 | 0x01);
+            pulse.write(2, 0x00);
+            pulse.write(3, 0x01);
+        }
+        assert_eq!(pulse1.sweep_target(), 0x100 - 0x80 - 1);
+        assert_eq!(pulse2.sweep_target(), 0x100 - 0x80);
+
+        // with a sweep period of 0 every half frame changes the period
+        pulse1.clock_sweep();
+        assert_eq!(pulse1.period, 0x7F);
+        pulse1.clock_sweep();
+        assert_eq!(pulse1.period, 0x7F - 0x3F - 1);
+    }
+
+    #[test]
+    fn sweep_mutes() {
+        let mut pulse = Pulse::new(false);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0x30 | 0x0F);
+        pulse.write(3, 0x08);
+
+        // periods below 8 are muted
+        pulse.write(2, 0x07);
+        assert!(pulse.muted());
+
+        // so are targets past $7FF, even with the sweep disabled,
+        // which with a shift of 0 is any period from $400 up
+        pulse.write(1, 0x00);
+        pulse.write(2, 0xFF);
+        pulse.write(3, 0x08 | 0x03);
+        assert!(!pulse.muted());
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x08 | 0x04);
+        assert!(pulse.muted());
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut apu = APU::new(Region::Ntsc);
+
+        // ignored while the channel is disabled
+        apu.cpu_write(0x4003, 0x08);
+        assert_eq!(apu.pulse1.length.counter, 0);
+
+        apu.cpu_write(0x4015, 0x0F);
+        for (index, &length) in [10, 254, 20, 2, 40, 4, 80, 6].iter().enumerate() {
+            apu.cpu_write(0x4003, (index as u8) << 3);
+            assert_eq!(apu.pulse1.length.counter, length);
+        }
+        apu.cpu_write(0x4003, 0xF8);
+        assert_eq!(apu.pulse1.length.counter, 30);
+        assert_eq!(apu.read_status() & 0x0F, 0x01);
+
+        apu.half_frame();
+        assert_eq!(apu.pulse1.length.counter, 29);
+
+        // halted
+        apu.cpu_write(0x4000, 0x20);
+        apu.half_frame();
+        assert_eq!(apu.pulse1.length.counter, 29);
+
+        // disabling the channel clears it
+        apu.cpu_write(0x4015, 0x00);
+        assert_eq!(apu.pulse1.length.counter, 0);
+        assert_eq!(apu.read_status() & 0x0F, 0x00);
+    }
+
+    #[test]
+    fn triangle_linear_counter() {
+        let mut triangle = Triangle::default();
+        triangle.length.set_enabled(true);
+
+        // control clear: reloads once, then counts down
+        triangle.write(0, 0x03);
+        triangle.write(3, 0x08);
+        triangle.clock_linear_counter();
+        assert_eq!(triangle.linear_counter, 3);
+        triangle.clock_linear_counter();
+        triangle.clock_linear_counter();
+        triangle.clock_linear_counter();
+        assert_eq!(triangle.linear_counter, 0);
+
+        // the sequencer stops with the linear counter at 0
+        triangle.write(2, 0x00);
+        let step = triangle.step;
+        for _ in 0..4 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.step, step);
+
+        // control set: reloads on every quarter frame
+        triangle.write(0, 0x80 | 0x03);
+        triangle.write(3, 0x08);
+        for _ in 0..3 {
+            triangle.clock_linear_counter();
+            assert_eq!(triangle.linear_counter, 3);
+        }
+
+        triangle.clock_timer();
+        assert_eq!(triangle.step, step + 1);
+    }
+
+    // steps until the shift register comes back to its first value
+    fn noise_period(short_mode: bool) -> usize {
+        let mut noise = Noise::new(Region::Ntsc);
+        noise.period = 1;
+        noise.short_mode = short_mode;
+
+        let start = noise.shift;
+        let mut steps = 0;
+        loop {
+            noise.clock_timer();
+            steps += 1;
+            if noise.shift == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn noise_modes() {
+        // feedback from bit 1 runs through all 32767 non-zero states,
+        // from bit 6 it repeats after 93 steps
+        assert_eq!(noise_period(false), 32767);
+        assert_eq!(noise_period(true), 93);
+
+        let mut noise = Noise::new(Region::Ntsc);
+        noise.write(2, 0x80 | 0x0F, Region::Ntsc);
+        assert!(noise.short_mode);
+        assert_eq!(noise.period, 4068);
+        noise.write(2, 0x0F, Region::Pal);
+        assert!(!noise.short_mode);
+        assert_eq!(noise.period, 3778);
+    }
+
+    // the cycles, counted from the write, on which the frame counter
+    // clocks the quarter and the half frame units
+    fn frame_clocks(counter: &mut FrameCounter, cycles: u32) -> (Vec<u32>, Vec<u32>) {
+        let mut quarters = Vec::new();
+        let mut halves = Vec::new();
+        for cycle in 1..=cycles {
+            let (quarter, half) = counter.clock(Region::Ntsc);
+            if quarter {
+                quarters.push(cycle);
+            }
+            if half {
+                halves.push(cycle);
+            }
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn four_step_sequence() {
+        let mut counter = FrameCounter::default();
+        let (quarters, halves) = frame_clocks(&mut counter, 2 * 29830);
+        assert_eq!(quarters, [7457, 14913, 22371, 29829, 29830 + 7457, 29830 + 14913, 29830 + 22371, 29830 + 29829]);
+        assert_eq!(halves, [14913, 29829, 29830 + 14913, 29830 + 29829]);
+    }
+
+    #[test]
+    fn five_step_sequence() {
+        // written on an even cycle, the mode changes 3 cycles later
+        // and clocks both units at once
+        let mut counter = FrameCounter::default();
+        counter.write(0x80, 0, 0);
+        let (quarters, halves) = frame_clocks(&mut counter, 3 + 37282);
+        assert_eq!(quarters, [3, 3 + 7457, 3 + 14913, 3 + 22371, 3 + 37281]);
+        assert_eq!(halves, [3, 3 + 14913, 3 + 37281]);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn write_delay() {
+        for &(cycle, cycles_ahead, delay) in &[(0, 0, 3), (1, 0, 4), (2, 0, 3), (3, 0, 4), (3, 2, 6)] {
+            let mut counter = FrameCounter::default();
+            counter.write(0x80, cycle, cycles_ahead);
+            let (quarters, _) = frame_clocks(&mut counter, 10);
+            assert_eq!(quarters[0], delay as u32, "write on cycle {}", cycle);
+        }
+
+        // the APU counts the cycle it is clocking, the CPU gives how far
+        // into the instruction the write lands
+        let mut apu = APU::new(Region::Ntsc);
+        apu.cycle = 10;
+        apu.write_frame_counter(0x80, 1);
+        let (quarters, _) = frame_clocks(&mut apu.frame_counter, 10);
+        assert_eq!(quarters[0], 1 + 4);
+    }
+
+    #[test]
+    fn frame_irq() {
+        let mut apu = APU::new(Region::Ntsc);
+        for _ in 0..29827 {
+            apu.clock();
+        }
+        assert!(!apu.irq());
+
+        // raised on the last 3 cycles of the sequence
+        apu.clock();
+        assert!(apu.irq());
+
+        // reading $4015 reports and clears it
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq());
+        apu.clock();
+        assert!(apu.irq());
+        apu.clock();
+        apu.read_status();
+        assert!(!apu.irq());
+
+        // setting the inhibit flag clears it and keeps it clear
+        for _ in 0..29830 {
+            apu.clock();
+        }
+        assert!(apu.irq());
+        apu.cpu_write(0x4017, 0x40);
+        assert!(!apu.irq());
+        for _ in 0..2 * 29830 {
+            apu.clock();
+            assert!(!apu.irq());
+        }
+    }
+
+    #[test]
+    fn dmc_rates() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        for (index, &rate) in [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54].iter().enumerate() {
+            dmc.write(0, index as u8, Region::Ntsc);
+            assert_eq!(dmc.period, rate);
+        }
+
+        dmc.write(0, 0x0F, Region::Pal);
+        assert_eq!(dmc.period, 50);
+
+        // the output level changes once every `period` cycles
+        dmc.write(0, 0x0F, Region::Ntsc);
+        dmc.write(1, 0x40, Region::Ntsc);
+        dmc.timer = 0;
+        dmc.silence = false;
+        dmc.shift = 0xFF;
+        dmc.clock_timer();
+        assert_eq!(dmc.output(), 0x42);
+        for _ in 0..53 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 0x42);
+        dmc.clock_timer();
+        assert_eq!(dmc.output(), 0x44);
+    }
+
+    #[test]
+    fn dmc_sample_address() {
+        let mut apu = APU::new(Region::Ntsc);
+        apu.cpu_write(0x4012, 0xFF);
+        apu.cpu_write(0x4013, 0x01);
+        apu.cpu_write(0x4015, 0x10);
+        assert_eq!(apu.dmc.addr, 0xFFC0);
+        assert_eq!(apu.dmc.bytes_remaining, 17);
+
+        // one fetch at a time, and only while the buffer is empty
+        assert_eq!(apu.dmc.dma_request(), Some(0xFFC0));
+        assert_eq!(apu.dmc.dma_request(), None);
+        apu.dmc.load_sample(0x00);
+        assert_eq!(apu.dmc.dma_request(), None);
+        apu.dmc.buffer = None;
+
+        // the address wraps around to $8000
+        apu.dmc.addr = 0xFFFF;
+        assert_eq!(apu.dmc.dma_request(), Some(0xFFFF));
+        apu.dmc.load_sample(0x00);
+        assert_eq!(apu.dmc.addr, 0x8000);
+        assert_eq!(apu.dmc.bytes_remaining, 15);
+    }
+
+    #[test]
+    fn dmc_loop_and_irq() {
+        let mut apu = APU::new(Region::Ntsc);
+
+        // looping samples start over without an IRQ
+        apu.cpu_write(0x4010, 0x80 | 0x40);
+        apu.cpu_write(0x4012, 0x01);
+        apu.cpu_write(0x4013, 0x00);
+        apu.cpu_write(0x4015, 0x10);
+        apu.dmc.load_sample(0x00);
+        assert_eq!(apu.dmc.addr, 0xC040);
+        assert_eq!(apu.dmc.bytes_remaining, 1);
+        assert!(!apu.irq());
+
+        // otherwise the end of the sample raises the IRQ
+        apu.cpu_write(0x4010, 0x80);
+        apu.dmc.load_sample(0x00);
+        assert_eq!(apu.dmc.bytes_remaining, 0);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0x90, 0x80);
+
+        // cleared by writing $4015 or by disabling the IRQ
+        apu.cpu_write(0x4015, 0x00);
+        assert!(!apu.irq());
+        apu.dmc.irq = true;
+        apu.cpu_write(0x4010, 0x00);
+        assert!(!apu.irq());
+    }
+}
@@ -0,0 +1,372 @@
+use std::f64::consts::PI;
+
+// Band-limited step synthesis. The APU output is a sum of square waves
+// that only changes level now and then, so instead of filtering 1.79 million
+// samples a second, each change of level is drawn into the output as a step
+// whose bandwidth already fits the output rate, like blargg's blip_buf.
+//
+// References:
+// http://slack.net/~ant/bl-synth/
+// https://wiki.nesdev.org/w/index.php/APU_Mixer
+
+// sub-sample positions a step can start at, and the width of a step in samples
+const PHASES: usize = 32;
+const TAPS: usize = 16;
+
+pub struct BlipBuffer {
+    // output samples per input clock
+    factor: f64,
+
+    // where the first clock of the current frame falls, in samples
+    offset: f64,
+
+    // changes of level spread over the samples they affect;
+    // the output is their running sum
+    deltas: Vec<f32>,
+    integrator: f32,
+
+    // band-limited impulse at each phase
+    kernel: Vec<[f32; TAPS]>,
+
+    level: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        // a Blackman windowed sinc, cut off a little below the output's Nyquist
+        // frequency so that the steps don't alias
+        const CUTOFF: f64 = 0.9;
+
+        let kernel = (0..PHASES).map(|phase| {
+            let mut taps = [0.0f32; TAPS];
+            for (tap, value) in taps.iter_mut().enumerate() {
+                let t = tap as f64 - (TAPS / 2) as f64 + 1.0 - phase as f64 / PHASES as f64;
+                let x = CUTOFF * PI * t;
+                let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+
+                let n = (t + TAPS as f64 / 2.0) / TAPS as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+                *value = (sinc * window) as f32;
+            }
+
+            // each step must add up to exactly its height
+            let sum: f32 = taps.iter().sum();
+            for value in taps.iter_mut() {
+                *value /= sum;
+            }
+            taps
+        }).collect();
+
+        BlipBuffer {
+            factor: sample_rate / clock_rate,
+            offset: 0.0,
+            deltas: Vec::new(),
+            integrator: 0.0,
+            kernel,
+            level: 0.0,
+        }
+    }
+
+    // the level changes to `level` at `clock` clocks into the frame
+    pub fn set_level(&mut self, clock: u32, level: f32) {
+        let delta = level - self.level;
+        if delta == 0.0 {
+            return;
+        }
+        self.level = level;
+
+        let position = self.offset + clock as f64 * self.factor;
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64) as usize;
+
+        if self.deltas.len() < index + TAPS {
+            self.deltas.resize(index + TAPS, 0.0);
+        }
+        for (sample, weight) in self.deltas[index..].iter_mut().zip(self.kernel[phase].iter()) {
+            *sample += delta * weight;
+        }
+    }
+
+    // end a frame `clocks` long, adding the samples that are complete to `out`
+    pub fn end_frame(&mut self, clocks: u32, out: &mut Vec<f32>) {
+        self.offset += clocks as f64 * self.factor;
+
+        let count = self.offset as usize;
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.offset -= count as f64;
+    }
+}
+
+// first order RC filter
+struct Filter {
+    high_pass: bool,
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl Filter {
+    fn new(high_pass: bool, cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = if high_pass { rc / (rc + dt) } else { dt / (rc + dt) };
+
+        Filter {
+            high_pass,
+            alpha: alpha as f32,
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = if self.high_pass {
+            self.alpha * (self.prev_out + input - self.prev_in)
+        } else {
+            self.prev_out + self.alpha * (input - self.prev_out)
+        };
+        self.prev_in = input;
+        self.prev_out = output;
+        output
+    }
+}
+
+/**
+ * The APU's analog output, resampled to `sample_rate`.
+ * The console filters its output with two high-pass filters at 90 Hz and
+ * 440 Hz, which remove the DC offset, and a low-pass filter at 14 kHz.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_Mixer
+ */
+pub struct AudioOutput {
+    pub clock_rate: f64,
+    pub sample_rate: u32,
+
+    blip: BlipBuffer,
+    filters: [Filter; 3],
+
+    // the filtered samples of the last frame. Nothing drains them, so
+    // they are read after each frame and replaced by the next one
+    samples: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        AudioOutput {
+            clock_rate,
+            sample_rate,
+            blip: BlipBuffer::new(clock_rate, rate),
+            filters: [
+                Filter::new(true, 90.0, rate),
+                Filter::new(true, 440.0, rate),
+                Filter::new(false, 14000.0, rate),
+            ],
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_level(&mut self, clock: u32, level: f32) {
+        self.blip.set_level(clock, level);
+    }
+
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.samples.clear();
+        self.blip.end_frame(clocks, &mut self.samples);
+
+        for sample in self.samples.iter_mut() {
+            for filter in self.filters.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+    }
+
+    // the samples of the last frame
+    pub fn last_frame(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Channel> {
+        Channel::ALL.iter().copied().find(|channel| channel.name() == name)
+    }
+}
+
+/**
+ * The channels are mixed by a resistor network that isn't linear: the
+ * louder the other channels in a group, the less a channel adds. The two
+ * pulse channels form one group and triangle, noise and DMC the other.
+ *
+ * pulse_out = 95.52 / (8128 / (pulse1 + pulse2) + 100)
+ * tnd_out   = 163.67 / (24329 / (3 * triangle + 2 * noise + dmc) + 100)
+ *
+ * the second being an approximation of the network that only needs the
+ * weighted sum, and both are kept in lookup tables.
+ *
+ * Each channel can be muted, soloed or given its own volume, for ripping
+ * music and tracking down sound bugs. Any of these switch the mixer from
+ * the tables to the same formulas with scaled channel levels, so a volume
+ * of 1.0 sounds exactly like the tables.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/APU_Mixer#Lookup_Table
+ */
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+
+    // indexed by `Channel`
+    volumes: [f32; 5],
+    muted: [bool; 5],
+    solo: [bool; 5],
+
+    // what each channel's level is multiplied by, None if all are 1.0
+    gains: Option<[f32; 5]>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, value) in pulse_table.iter_mut().enumerate() {
+            *value = pulse_out(n as f32);
+        }
+
+        let mut tnd_table = [0.0; 203];
+        for (n, value) in tnd_table.iter_mut().enumerate() {
+            *value = tnd_out(n as f32);
+        }
+
+        Mixer {
+            pulse_table,
+            tnd_table,
+            volumes: [1.0; 5],
+            muted: [false; 5],
+            solo: [false; 5],
+            gains: None,
+        }
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volumes[channel as usize]
+    }
+
+    // 1.0 is the console's own balance
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel as usize] = volume.max(0.0);
+        self.update_gains();
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+        self.update_gains();
+    }
+
+    pub fn solo(&self, channel: Channel) -> bool {
+        self.solo[channel as usize]
+    }
+
+    // while any channel is soloed, only soloed channels are heard
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.solo[channel as usize] = solo;
+        self.update_gains();
+    }
+
+    fn update_gains(&mut self) {
+        let any_solo = self.solo.contains(&true);
+
+        let mut gains = [0.0; 5];
+        for (index, gain) in gains.iter_mut().enumerate() {
+            let audible = !self.muted[index] && (!any_solo || self.solo[index]);
+            *gain = if audible { self.volumes[index] } else { 0.0 };
+        }
+
+        self.gains = if gains == [1.0; 5] { None } else { Some(gains) };
+    }
+
+    // a channel as it would sound with the others silent, for
+    // recording it on its own. Volume, mute and solo don't apply
+    pub fn mix_alone(&self, channel: Channel, level: u8) -> f32 {
+        let level = level as usize;
+        match channel {
+            Channel::Pulse1 | Channel::Pulse2 => self.pulse_table[level],
+            Channel::Triangle => self.tnd_table[3 * level],
+            Channel::Noise => self.tnd_table[2 * level],
+            Channel::Dmc => self.tnd_table[level],
+        }
+    }
+
+    // output between 0.0 and about 1.0
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let gains = match self.gains {
+            None => {
+                let pulse = self.pulse_table[(pulse1 + pulse2) as usize];
+                let tnd = self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+                return pulse + tnd;
+            },
+            Some(gains) => gains,
+        };
+
+        let level = |channel: Channel, value: u8| value as f32 * gains[channel as usize];
+
+        let pulse_sum = level(Channel::Pulse1, pulse1) + level(Channel::Pulse2, pulse2);
+        let tnd_sum = 3.0 * level(Channel::Triangle, triangle)
+            + 2.0 * level(Channel::Noise, noise)
+            + level(Channel::Dmc, dmc);
+
+        pulse_out(pulse_sum) + tnd_out(tnd_sum)
+    }
+}
+
+// pulse1 + pulse2
+fn pulse_out(sum: f32) -> f32 {
+    if sum > 0.0 { 95.52 / (8128.0 / sum + 100.0) } else { 0.0 }
+}
+
+// 3 * triangle + 2 * noise + dmc
+fn tnd_out(sum: f32) -> f32 {
+    if sum > 0.0 { 163.67 / (24329.0 / sum + 100.0) } else { 0.0 }
+}
+
+// mono samples to interleaved frames of `channels` samples
+pub fn interleave(samples: &[f32], channels: usize) -> Vec<f32> {
+    samples.iter().flat_map(|&sample| std::iter::repeat_n(sample, channels)).collect()
+}
+
+pub fn to_i16(samples: &[f32]) -> Vec<i16> {
+    samples.iter().map(|&sample| (sample * 32767.0).clamp(-32768.0, 32767.0) as i16).collect()
+}
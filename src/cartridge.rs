@@ -0,0 +1,563 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::eeprom::{Eeprom, EepromKind};
+use crate::flash::Flash;
+use crate::hash;
+use crate::nametable::Mirroring;
+use crate::patch;
+use crate::ppu::PpuModel;
+use crate::region::Region;
+use crate::savestate::Savestate;
+use crate::vs::VsProtection;
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Console {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+}
+
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+
+    // CHR RAM is used when the header reports no CHR ROM
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+
+    pub mapper: u8,
+
+    // mappers may change this at any time
+    pub mirroring: Mirroring,
+
+    // nametable memory on the cartridge, e.g. the extra 2KB of four-screen boards
+    pub nametable_ram: Vec<u8>,
+
+    // the cartridge contents survive power off, either
+    // through battery-backed PRG RAM or a save chip below
+    pub battery: bool,
+
+    // 8 KB work RAM at $6000-$7FFF
+    pub prg_ram: Vec<u8>,
+
+    // set whenever battery-backed PRG RAM changes, cleared once it has been saved
+    pub prg_ram_dirty: bool,
+
+    // boards that save to a serial EEPROM or to flash instead of PRG RAM
+    pub eeprom: Option<Eeprom>,
+    pub flash: Option<Flash>,
+
+    // 512 byte trainer, loaded at $7000-$71FF
+    pub trainer: Option<Vec<u8>>,
+
+    // the console timing given by a NES 2.0 header,
+    // None for iNES headers and multi-region games
+    pub region: Option<Region>,
+
+    // arcade boards, and the PPU and protection hardware
+    // of Vs. System games when a NES 2.0 header gives them
+    pub console: Console,
+    pub ppu_model: Option<PpuModel>,
+    pub vs_protection: VsProtection,
+
+    // what the game expects in the controller and expansion ports,
+    // byte 15 of a NES 2.0 header, 0 when unknown
+    pub input_device: u8,
+
+    // mapper 99: CHR bank (and PRG bank of 40 KB games) selected through $4016
+    pub vs_bank: u8,
+
+    // mappers 16, 157, 159 and 30: the 16 KB PRG bank at $8000-$BFFF,
+    // $C000-$FFFF being fixed to the last bank
+    pub prg_bank: u8,
+
+    // 1 KB CHR banks on Bandai boards, one 8 KB bank on UNROM 512
+    pub chr_banks: [u8; 8],
+
+    // Bandai boards count CPU cycles down to an IRQ
+    pub irq_counter: u16,
+    pub irq_latch: u16,
+    pub irq_enabled: bool,
+    pub irq_pending: bool,
+}
+
+impl Cartridge {
+    /**
+     * iNES header layout
+     * 0-3: "NES" followed by MS-DOS end-of-file ($1A)
+     * 4:   Size of PRG ROM in 16 KB units
+     * 5:   Size of CHR ROM in 8 KB units (0 means the board uses CHR RAM)
+     * 6:   Flags 6 - Mapper (low nybble), four-screen, trainer, battery, mirroring
+     * 7:   Flags 7 - Mapper (high nybble), NES 2.0, PlayChoice-10, Vs. UniSystem
+     * 8-15: Unused in plain iNES
+     *
+     * NES 2.0 headers have bits 2-3 of flags 7 set to 10 and use bytes 8-15:
+     * 12:  CPU/PPU timing - 0: NTSC, 1: PAL, 2: multi-region, 3: Dendy
+     * 13:  Vs. System - hardware type (high nybble), PPU type (low nybble)
+     * 15:  Default expansion device, e.g. 2: Four Score
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/INES
+     * https://wiki.nesdev.org/w/index.php/NES_2.0
+     */
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg);
+
+        if data.len() < 16 || &data[0..4] != b"NES\x1A" {
+            return Err(invalid("not an iNES file"));
+        }
+
+        let prg_size = data[4] as usize * 0x4000;
+        let chr_size = data[5] as usize * 0x2000;
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let mapper = (flags7 & 0xF0) | (flags6 >> 4);
+
+        if prg_size == 0 {
+            return Err(invalid("no PRG ROM"));
+        }
+
+        // UNROM 512 boards with only bit 3 set switch between single-screen
+        // pages, with both bits set they have four-screen RAM
+        let mirroring = if mapper == 30 && flags6 & 0x09 == 0x08 {
+            Mirroring::SingleScreenA
+        } else if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut offset = 16;
+
+        let trainer = if flags6 & 0x04 != 0 {
+            let trainer = data.get(offset..offset + 512).ok_or_else(|| invalid("truncated trainer"))?;
+            offset += 512;
+            Some(trainer.to_vec())
+        } else {
+            None
+        };
+
+        let prg_rom = data.get(offset..offset + prg_size).ok_or_else(|| invalid("truncated PRG ROM"))?.to_vec();
+        offset += prg_size;
+
+        let (chr, chr_is_ram) = if chr_size == 0 {
+            // UNROM 512 has 32 KB of CHR RAM
+            (vec![0; if mapper == 30 { 0x8000 } else { 0x2000 }], true)
+        } else {
+            (data.get(offset..offset + chr_size).ok_or_else(|| invalid("truncated CHR ROM"))?.to_vec(), false)
+        };
+
+        let nametable_ram = if mirroring == Mirroring::FourScreen { vec![0; 0x800] } else { Vec::new() };
+
+        let battery = flags6 & 0x02 != 0;
+
+        let nes2 = flags7 & 0x0C == 0x08;
+        let region = match data[12] & 0x03 {
+            _ if !nes2 => None,
+            0 => Some(Region::Ntsc),
+            1 => Some(Region::Pal),
+            3 => Some(Region::Dendy),
+            _ => None,
+        };
+
+        let console = match flags7 & 0x03 {
+            1 => Console::VsSystem,
+            2 => Console::PlayChoice10,
+            _ => Console::Nes,
+        };
+        let (ppu_model, vs_protection) = match console {
+            Console::VsSystem if nes2 => (PpuModel::from_nes2(data[13] & 0x0F), VsProtection::from_nes2(data[13] >> 4)),
+            Console::PlayChoice10 => (Some(PpuModel::Rp2C03), VsProtection::None),
+            _ => (None, VsProtection::None),
+        };
+
+        let input_device = if nes2 { data[15] & 0x3F } else { 0 };
+
+        let mut prg_ram = vec![0; 0x2000];
+        if let Some(trainer) = &trainer {
+            prg_ram[0x1000..0x1200].copy_from_slice(trainer);
+        }
+
+        // https://wiki.nesdev.org/w/index.php/Bandai_FCG_board
+        // https://wiki.nesdev.org/w/index.php/UNROM_512
+        let eeprom = match mapper {
+            16 | 157 => Some(Eeprom::new(EepromKind::C24C02)),
+            159 => Some(Eeprom::new(EepromKind::X24C01)),
+            _ => None,
+        };
+        let flash = match mapper {
+            30 if battery => Some(Flash::new(prg_rom.clone())),
+            _ => None,
+        };
+
+        Ok(Cartridge {
+            prg_rom,
+            chr,
+            chr_is_ram,
+            mapper,
+            mirroring,
+            nametable_ram,
+            battery,
+            prg_ram,
+            prg_ram_dirty: false,
+            eeprom,
+            flash,
+            trainer,
+            region,
+            console,
+            ppu_model,
+            vs_protection,
+            input_device,
+            vs_bank: 0,
+            prg_bank: 0,
+            chr_banks: [0; 8],
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        })
+    }
+
+    // Load a ROM from disk, applying `patch` before the header is parsed.
+    // When no patch is given, a patch with the same name as the ROM
+    // (e.g. game.ips for game.nes) is picked up if one exists next to it.
+    pub fn load(path: &Path, patch: Option<&Path>) -> Result<Self> {
+        let mut data = fs::read(path)?;
+
+        let patch_path = patch.map(Path::to_path_buf).or_else(|| patch::find_patch_for(path));
+        if let Some(patch_path) = patch_path {
+            let patch = fs::read(&patch_path)?;
+            data = patch::apply(&data, &patch)?;
+        }
+
+        Cartridge::from_bytes(&data)
+    }
+
+    // CRC-32 of the PRG and CHR ROM, used to look the game up in a ROM database
+    pub fn crc32(&self) -> u32 {
+        hash::crc32(&self.rom())
+    }
+
+    // MD5 of the PRG and CHR ROM, which FCEUX movies are tied to
+    pub fn md5(&self) -> [u8; 16] {
+        hash::md5(&self.rom())
+    }
+
+    // SHA-1 of the PRG and CHR ROM, which BizHawk and Mesen movies are tied to
+    pub fn sha1(&self) -> [u8; 20] {
+        hash::sha1(&self.rom())
+    }
+
+    // the PRG and CHR ROM without the header
+    fn rom(&self) -> Vec<u8> {
+        let mut rom = self.prg_rom.clone();
+        if !self.chr_is_ram {
+            rom.extend_from_slice(&self.chr);
+        }
+        rom
+    }
+
+    // mapper registers back to their power on state
+    pub fn power(&mut self) {
+        self.vs_bank = 0;
+        self.prg_bank = 0;
+        self.chr_banks = [0; 8];
+        self.irq_counter = 0;
+        self.irq_latch = 0;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+    }
+
+    // one CPU cycle
+    pub fn clock(&mut self) {
+        // the counter is checked before it is decremented
+        // https://wiki.nesdev.org/w/index.php/Bandai_FCG_board#IRQ_Control_($800A)
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    fn bandai(&self) -> bool {
+        matches!(self.mapper, 16 | 157 | 159)
+    }
+
+    // offset in PRG ROM of a $8000-$FFFF address on boards with a
+    // switchable bank at $8000 and the last bank fixed at $C000
+    fn banked_prg(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / 0x4000;
+        let bank = if addr < 0xC000 { self.prg_bank as usize % banks } else { banks - 1 };
+        bank * 0x4000 + (addr & 0x3FFF) as usize
+    }
+
+    // $4020-$FFFF as seen from the CPU
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // the EEPROM's data line is read back through bit 4
+            0x6000..=0x7FFF if self.bandai() => {
+                self.eeprom.as_ref().map_or(0, |eeprom| (eeprom.read() as u8) << 4)
+            },
+            0x6000..=0x7FFF => self.prg_ram[(addr & 0x1FFF) as usize],
+            // UNROM 512 runs from its flash, which the game may have rewritten
+            0x8000..=0xFFFF if self.mapper == 30 => {
+                let offset = self.banked_prg(addr);
+                match &self.flash {
+                    Some(flash) => flash.read(offset),
+                    None => self.prg_rom[offset],
+                }
+            },
+            0x8000..=0xFFFF if self.bandai() => self.prg_rom[self.banked_prg(addr)],
+            // 40 KB Vs. System games switch $8000-$9FFF to the fifth bank
+            0x8000..=0x9FFF if self.mapper == 99 && self.prg_rom.len() > 0x8000 => {
+                let offset = (addr & 0x1FFF) as usize;
+                self.prg_rom[self.vs_bank as usize * 0x8000 + offset]
+            },
+            // NROM: 16 KB images are mirrored into $C000-$FFFF
+            0x8000..=0xFFFF => {
+                let offset = (addr - 0x8000) as usize;
+                self.prg_rom[offset % self.prg_rom.len()]
+            },
+            _ => 0,
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            // FCG-1/2 boards (mapper 16) also decode their registers here
+            0x6000..=0x7FFF if self.mapper == 16 => self.bandai_write(addr, value),
+            0x6000..=0x7FFF => {
+                let offset = (addr & 0x1FFF) as usize;
+                if self.prg_ram[offset] != value {
+                    self.prg_ram[offset] = value;
+                    self.prg_ram_dirty |= self.battery;
+                }
+            },
+            0x8000..=0xFFFF if self.bandai() => self.bandai_write(addr, value),
+            // with flash, $8000-$BFFF writes go to the chip through the selected bank
+            0x8000..=0xBFFF if self.mapper == 30 && self.flash.is_some() => {
+                let offset = self.prg_bank as usize * 0x4000 + (addr & 0x3FFF) as usize;
+                if let Some(flash) = &mut self.flash {
+                    flash.write(offset, value);
+                }
+            },
+            0x8000..=0xFFFF if self.mapper == 30 => self.unrom512_write(value),
+            _ => {},
+        }
+    }
+
+    /**
+     * Bandai FCG / LZ93D50 registers, mirrored every 16 bytes
+     * $x0-$x7: 1 KB CHR bank at $0000, $0400, ... $1C00
+     * $x8: 16 KB PRG bank at $8000
+     * $x9: mirroring - 0: vertical, 1: horizontal, 2: single-screen A, 3: B
+     * $xA: bit 0 enables the IRQ, writing copies the latch to the counter
+     *      and acknowledges the IRQ
+     * $xB/$xC: IRQ latch low/high byte
+     * $xD: EEPROM - bit 5: SCL, bit 6: SDA
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/Bandai_FCG_board
+     */
+    fn bandai_write(&mut self, addr: u16, value: u8) {
+        match addr & 0x0F {
+            reg @ 0x0..=0x7 => self.chr_banks[reg as usize] = value,
+            0x8 => self.prg_bank = value & 0x0F,
+            0x9 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+            },
+            0xA => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_counter = self.irq_latch;
+                self.irq_pending = false;
+            },
+            0xB => self.irq_latch = (self.irq_latch & 0xFF00) | value as u16,
+            0xC => self.irq_latch = (self.irq_latch & 0x00FF) | (value as u16) << 8,
+            0xD => {
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /**
+     * UNROM 512 bank register, $C000-$FFFF on boards with flash
+     * 0-4: 16 KB PRG bank at $8000
+     * 5-6: 8 KB CHR RAM bank
+     * 7: single-screen page, on boards wired for it
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/UNROM_512
+     */
+    fn unrom512_write(&mut self, value: u8) {
+        self.prg_bank = value & 0x1F;
+        self.chr_banks[0] = (value >> 5) & 0x03;
+        if let Mirroring::SingleScreenA | Mirroring::SingleScreenB = self.mirroring {
+            self.mirroring = if value & 0x80 != 0 { Mirroring::SingleScreenB } else { Mirroring::SingleScreenA };
+        }
+    }
+
+    // offset in CHR memory of a $0000-$1FFF address
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+        let offset = match self.mapper {
+            99 => self.vs_bank as usize * 0x2000 + addr,
+            16 | 157 | 159 => self.chr_banks[addr / 0x400] as usize * 0x400 + addr % 0x400,
+            30 => self.chr_banks[0] as usize * 0x2000 + addr,
+            _ => addr,
+        };
+        offset % self.chr.len()
+    }
+
+    // $0000-$1FFF as seen from the PPU
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = value;
+        }
+    }
+
+    // `page` is a 1KB page of the cartridge's nametable memory
+    pub fn nametable_read(&mut self, page: u8, offset: usize) -> u8 {
+        if self.nametable_ram.is_empty() {
+            return 0;
+        }
+        let addr = (page as usize * 0x400 + offset) % self.nametable_ram.len();
+        self.nametable_ram[addr]
+    }
+
+    pub fn nametable_write(&mut self, page: u8, offset: usize, value: u8) {
+        if self.nametable_ram.is_empty() {
+            return;
+        }
+        let addr = (page as usize * 0x400 + offset) % self.nametable_ram.len();
+        self.nametable_ram[addr] = value;
+    }
+
+    // the data that must survive power off, if any
+    pub fn save_data(&self) -> Option<&[u8]> {
+        if let Some(eeprom) = &self.eeprom {
+            Some(&eeprom.data)
+        } else if let Some(flash) = &self.flash {
+            Some(&flash.data)
+        } else if self.battery {
+            Some(&self.prg_ram)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let dest: &mut [u8] = if let Some(eeprom) = &mut self.eeprom {
+            &mut eeprom.data
+        } else if let Some(flash) = &mut self.flash {
+            &mut flash.data
+        } else if self.battery {
+            &mut self.prg_ram
+        } else {
+            return;
+        };
+
+        // tolerate saves of a different size made by other emulators
+        let len = dest.len().min(data.len());
+        dest[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn save_dirty(&self) -> bool {
+        self.prg_ram_dirty
+            || self.eeprom.as_ref().is_some_and(|eeprom| eeprom.dirty)
+            || self.flash.as_ref().is_some_and(|flash| flash.dirty)
+    }
+
+    pub fn clear_save_dirty(&mut self) {
+        self.prg_ram_dirty = false;
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.dirty = false;
+        }
+        if let Some(flash) = &mut self.flash {
+            flash.dirty = false;
+        }
+    }
+
+    // the memory the game can write to and the mapper registers.
+    // Loaded saves count as changed, so the save file follows them
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        if self.chr_is_ram {
+            state.memory(&mut self.chr);
+        }
+
+        let mut mirroring = self.mirroring as u8;
+        state.u8(&mut mirroring);
+        self.mirroring = match mirroring {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::SingleScreenA,
+            3 => Mirroring::SingleScreenB,
+            _ => Mirroring::FourScreen,
+        };
+
+        state.memory(&mut self.nametable_ram);
+        state.memory(&mut self.prg_ram);
+        if state.loading() {
+            self.prg_ram_dirty = self.battery;
+        }
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.savestate(state);
+        }
+        if let Some(flash) = &mut self.flash {
+            flash.savestate(state);
+        }
+
+        state.u8(&mut self.vs_bank);
+        state.u8(&mut self.prg_bank);
+        state.bytes(&mut self.chr_banks);
+        state.u16(&mut self.irq_counter);
+        state.u16(&mut self.irq_latch);
+        state.bool(&mut self.irq_enabled);
+        state.bool(&mut self.irq_pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cartridge;
+    use crate::nametable::Mirroring;
+
+    fn header(mapper: u8, flags6: u8) -> Vec<u8> {
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, 0x01, 0x01, mapper << 4 | flags6, mapper & 0xF0];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        rom
+    }
+
+    #[test]
+    fn mirroring() {
+        let mirroring = |mapper, flags6| Cartridge::from_bytes(&header(mapper, flags6)).unwrap().mirroring;
+        assert_eq!(mirroring(0, 0x00), Mirroring::Horizontal);
+        assert_eq!(mirroring(0, 0x01), Mirroring::Vertical);
+        assert_eq!(mirroring(0, 0x08), Mirroring::FourScreen);
+        assert_eq!(mirroring(30, 0x08), Mirroring::SingleScreenA);
+        assert_eq!(mirroring(30, 0x09), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn rejects_missing_prg_rom() {
+        let mut rom = header(0, 0x00);
+        rom[4] = 0;
+        assert!(Cartridge::from_bytes(&rom).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::apu::DEFAULT_SAMPLE_RATE;
+use crate::audio::Channel;
+use crate::controller::{Button, Macro};
+use crate::input::{Adapter, Device};
+use crate::ntsc::NtscFilterParams;
+use crate::palette::NtscParams;
+use crate::ppu::PpuModel;
+use crate::region::Region;
+
+// Emulator settings. Every setting can be given in a config file
+// as `key = value` lines, or on the command line as `--key value`.
+// Boolean settings are switched with `--key` and `--no-key`.
+//
+// e.g.
+//   # nesrs.cfg
+//   palette = generated
+//   ntsc_hue = -5
+//   sprite_limit = false
+pub struct Config {
+    pub rom: Option<PathBuf>,
+    pub patch: Option<PathBuf>,
+
+    // run this many frames without a display, then exit
+    pub frames: u64,
+
+    pub sprite_limit: bool,
+
+    // console timing, None picks it from the ROM header or database
+    pub region: Option<Region>,
+
+    // ROM database file used to identify games with missing header info.
+    // There is no built-in database
+    pub database: Option<PathBuf>,
+
+    // Vs. System: PPU variant, None picks it from the ROM header or database
+    pub ppu: Option<PpuModel>,
+    pub dip_switches: u8,
+
+    // frames at which a coin is inserted
+    pub coins: Vec<u64>,
+
+    // four player adapter, None picks it from the ROM header or database
+    pub adapter: Option<Adapter>,
+
+    // buttons that autofire on every controller while held, and how
+    // many frames each press and release lasts
+    pub turbo: u8,
+    pub turbo_rate: u8,
+
+    // macros by name, the key and controller port (1-4) each is bound to,
+    // and the frames at which one is played: (frame, name, port)
+    pub macros: Vec<(String, Macro)>,
+    pub bindings: Vec<(String, String, usize)>,
+    pub run_macros: Vec<(u64, String, usize)>,
+
+    // keys pressed for a single frame, which play the macro bound to
+    // them or are held on the Family BASIC keyboard, and Power Pad
+    // buttons held for a single frame: (frame, key) and (frame, button)
+    pub key_presses: Vec<(u64, String)>,
+    pub mat_presses: Vec<(u64, u8)>,
+
+    // devices in the controller ports and the expansion port,
+    // None picks them from the ROM header or database
+    pub port1: Option<Device>,
+    pub port2: Option<Device>,
+    pub expansion: Option<Device>,
+
+    // name of a built-in palette, "generated", path to a .pal file,
+    // or "auto" for 2c02 or 2c03 depending on the PPU
+    pub palette: String,
+
+    // picture controls of the generated palette and the NTSC filter
+    pub ntsc: NtscParams,
+
+    // "none" or "ntsc"
+    pub filter: String,
+    pub ntsc_filter: NtscFilterParams,
+
+    // audio output rate in Hz
+    pub sample_rate: u32,
+
+    // channels left out of the mix, or the only ones in it, and their volumes
+    pub mute: Vec<Channel>,
+    pub solo: Vec<Channel>,
+    pub volumes: Vec<(Channel, f32)>,
+
+    // record the audio of frames `record_start` up to `record_stop` to a
+    // WAV file, and each channel to its own file with `record_stems`
+    pub record: Option<PathBuf>,
+    pub record_stems: bool,
+    pub record_start: u64,
+    pub record_stop: Option<u64>,
+
+    // FM2 input movie to record, from power on or from `state_load`,
+    // or to play back
+    pub movie_record: Option<PathBuf>,
+    pub movie_play: Option<PathBuf>,
+
+    // savestate to start from, and to write after the last frame
+    pub state_load: Option<PathBuf>,
+    pub state_save: Option<PathBuf>,
+
+    // write the last frame to this file as a PPM image
+    pub screenshot: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rom: None,
+            patch: None,
+            frames: 0,
+            sprite_limit: true,
+            region: None,
+            database: None,
+            ppu: None,
+            dip_switches: 0,
+            coins: Vec::new(),
+            adapter: None,
+            turbo: 0,
+            turbo_rate: 2,
+            macros: Vec::new(),
+            bindings: Vec::new(),
+            run_macros: Vec::new(),
+            key_presses: Vec::new(),
+            mat_presses: Vec::new(),
+            port1: None,
+            port2: None,
+            expansion: None,
+            palette: String::from("auto"),
+            ntsc: NtscParams::default(),
+            filter: String::from("none"),
+            ntsc_filter: NtscFilterParams::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            mute: Vec::new(),
+            solo: Vec::new(),
+            volumes: Vec::new(),
+            record: None,
+            record_stems: false,
+            record_start: 0,
+            record_stop: None,
+            movie_record: None,
+            movie_play: None,
+            state_load: None,
+            state_save: None,
+            screenshot: None,
+        }
+    }
+}
+
+// settings that take no value on the command line
+const FLAGS: [&str; 2] = ["sprite_limit", "record_stems"];
+
+fn parse_float(value: &str) -> Result<f32, String> {
+    value.parse().map_err(|_| format!("expected a number, got '{}'", value))
+}
+
+// e.g. "pulse1,noise"
+fn parse_channels(value: &str) -> Result<Vec<Channel>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Channel::parse(name).ok_or_else(|| format!("unknown channel '{}'", name)))
+        .collect()
+}
+
+fn parse_device(value: &str) -> Result<Option<Device>, String> {
+    match value {
+        "auto" => Ok(None),
+        _ => Device::parse(value).map(Some).ok_or_else(|| format!("unknown device '{}'", value)),
+    }
+}
+
+// 1 to 4, e.g. the "2" of "fire:2", 1 when left out
+fn parse_port(value: Option<&str>) -> Result<usize, String> {
+    match value.map(|port| port.trim().parse()) {
+        None => Ok(0),
+        Some(Ok(port @ 1..=4)) => Ok(port - 1),
+        Some(_) => Err(format!("invalid controller port '{}', expected 1 to 4", value.unwrap_or(""))),
+    }
+}
+
+// `frame:name` entries separated by commas
+fn parse_presses(value: &str) -> Result<Vec<(u64, String)>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (frame, name) = entry.split_once(':').ok_or_else(|| format!("expected frame:name, got '{}'", entry))?;
+            let frame = frame.trim().parse().map_err(|_| format!("invalid frame number '{}'", frame))?;
+            Ok((frame, name.trim().to_string()))
+        })
+        .collect()
+}
+
+// A # starts a comment at the start of a line or after whitespace,
+// so values such as paths can contain one
+pub fn strip_comment(line: &str) -> &str {
+    let mut after_space = true;
+    for (index, c) in line.char_indices() {
+        if c == '#' && after_space {
+            return &line[..index];
+        }
+        after_space = c.is_whitespace();
+    }
+    line
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("expected true or false, got '{}'", value)),
+    }
+}
+
+impl Config {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "rom" => self.rom = Some(PathBuf::from(value)),
+            "patch" => self.patch = Some(PathBuf::from(value)),
+            "frames" => self.frames = value.parse().map_err(|_| format!("invalid frame count '{}'", value))?,
+            "sprite_limit" => self.sprite_limit = parse_bool(value)?,
+            "region" => {
+                self.region = match value {
+                    "auto" => None,
+                    _ => Some(Region::parse(value).ok_or_else(|| format!("unknown region '{}', expected auto, ntsc, pal or dendy", value))?),
+                };
+            },
+            "database" => self.database = Some(PathBuf::from(value)),
+            "ppu" => {
+                self.ppu = match value {
+                    "auto" => None,
+                    _ => Some(PpuModel::parse(value).ok_or_else(|| format!("unknown PPU '{}'", value))?),
+                };
+            },
+            // given as binary, switch 1 last, e.g. 00000010 turns on switch 2
+            "dip_switches" => {
+                self.dip_switches = u8::from_str_radix(value, 2).map_err(|_| format!("expected up to 8 binary digits, got '{}'", value))?;
+            },
+            "coins" => {
+                self.coins = value.split(',')
+                    .map(|frame| frame.trim().parse().map_err(|_| format!("invalid frame number '{}'", frame)))
+                    .collect::<Result<_, _>>()?;
+            },
+            "adapter" => {
+                self.adapter = match value {
+                    "auto" => None,
+                    _ => Some(Adapter::parse(value).ok_or_else(|| format!("unknown adapter '{}', expected auto, none, fourscore, hori or simple", value))?),
+                };
+            },
+            "turbo" => {
+                self.turbo = 0;
+                for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    self.turbo |= Button::parse(name).ok_or_else(|| format!("unknown button '{}'", name))?.bit();
+                }
+            },
+            "turbo_rate" => {
+                self.turbo_rate = match value.parse() {
+                    Ok(rate) if (1..=60).contains(&rate) => rate,
+                    _ => return Err(format!("invalid turbo rate '{}', expected 1 to 60 frames", value)),
+                };
+            },
+            _ if key.starts_with("macro_") => {
+                let name = &key["macro_".len()..];
+                self.macros.retain(|(other, _)| other != name);
+                self.macros.push((name.to_string(), Macro::parse(value)?));
+            },
+            // e.g. bind_f1 = hadouken:2
+            _ if key.starts_with("bind_") => {
+                let key = &key["bind_".len()..];
+                let mut parts = value.split(':');
+                let name = parts.next().unwrap_or("").trim().to_string();
+                let port = parse_port(parts.next())?;
+                self.bindings.retain(|(other, _, _)| other != key);
+                self.bindings.push((key.to_string(), name, port));
+            },
+            // e.g. run_macro = 120:hadouken,300:jump:2
+            "run_macro" => {
+                self.run_macros = value.split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(|entry| {
+                        let mut parts = entry.split(':');
+                        let frame = parts.next().unwrap_or("");
+                        let frame = frame.trim().parse().map_err(|_| format!("invalid frame number '{}'", frame))?;
+                        let name = parts.next().ok_or_else(|| format!("expected frame:macro, got '{}'", entry))?.trim().to_string();
+                        Ok((frame, name, parse_port(parts.next())?))
+                    })
+                    .collect::<Result<_, String>>()?;
+            },
+            // e.g. press_keys = 120:f1,300:return
+            "press_keys" => self.key_presses = parse_presses(value)?,
+            // e.g. press_mat = 60:5,90:8
+            "press_mat" => {
+                self.mat_presses = parse_presses(value)?.into_iter()
+                    .map(|(frame, button)| Ok((frame, button.parse().map_err(|_| format!("invalid mat button '{}'", button))?)))
+                    .collect::<Result<_, String>>()?;
+            },
+            "port1" => self.port1 = parse_device(value)?,
+            "port2" => self.port2 = parse_device(value)?,
+            "expansion" => self.expansion = parse_device(value)?,
+            "palette" => self.palette = value.to_string(),
+            "ntsc_hue" => self.ntsc.hue = parse_float(value)?,
+            "ntsc_saturation" => self.ntsc.saturation = parse_float(value)?,
+            "ntsc_contrast" => self.ntsc.contrast = parse_float(value)?,
+            "ntsc_brightness" => self.ntsc.brightness = parse_float(value)?,
+            "ntsc_gamma" => self.ntsc.gamma = parse_float(value)?,
+            "filter" => match value {
+                "none" | "ntsc" => self.filter = value.to_string(),
+                _ => return Err(format!("unknown filter '{}', expected none or ntsc", value)),
+            },
+            "ntsc_sharpness" => self.ntsc_filter.sharpness = parse_float(value)?,
+            "ntsc_fringing" => self.ntsc_filter.fringing = parse_float(value)?,
+            "ntsc_bleed" => self.ntsc_filter.bleed = parse_float(value)?,
+            "sample_rate" => {
+                self.sample_rate = match value.parse() {
+                    Ok(rate) if (8000..=192000).contains(&rate) => rate,
+                    _ => return Err(format!("invalid sample rate '{}'", value)),
+                };
+            },
+            "mute" => self.mute = parse_channels(value)?,
+            "solo" => self.solo = parse_channels(value)?,
+            _ if key.starts_with("volume_") => {
+                let channel = Channel::parse(&key["volume_".len()..]).ok_or_else(|| format!("unknown setting '{}'", key))?;
+                self.volumes.retain(|&(other, _)| other != channel);
+                self.volumes.push((channel, parse_float(value)?));
+            },
+            "record" => self.record = Some(PathBuf::from(value)),
+            "record_stems" => self.record_stems = parse_bool(value)?,
+            "record_start" => self.record_start = value.parse().map_err(|_| format!("invalid frame number '{}'", value))?,
+            "record_stop" => self.record_stop = Some(value.parse().map_err(|_| format!("invalid frame number '{}'", value))?),
+            "movie_record" => self.movie_record = Some(PathBuf::from(value)),
+            "movie_play" => self.movie_play = Some(PathBuf::from(value)),
+            "state_load" => self.state_load = Some(PathBuf::from(value)),
+            "state_save" => self.state_save = Some(PathBuf::from(value)),
+            "screenshot" => self.screenshot = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| format!("{}:{}: expected 'key = value'", path, number + 1))?;
+            self.set(key.trim(), value.trim())
+                .map_err(|err| format!("{}:{}: {}", path, number + 1, err))?;
+        }
+
+        Ok(())
+    }
+
+    // settings are applied in order, so later arguments
+    // override earlier ones and the files given with --config
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = Config::default();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let option = match arg.strip_prefix("--") {
+                Some(option) => option.replace('-', "_"),
+                None => {
+                    config.rom = Some(PathBuf::from(arg));
+                    continue;
+                },
+            };
+
+            if option == "config" {
+                let path = iter.next().ok_or("--config needs a file")?;
+                config.load_file(path)?;
+            } else if FLAGS.contains(&option.as_str()) {
+                config.set(&option, "true")?;
+            } else if let Some(flag) = option.strip_prefix("no_").filter(|flag| FLAGS.contains(flag)) {
+                config.set(flag, "false")?;
+            } else {
+                let value = iter.next().ok_or_else(|| format!("--{} needs a value", arg.trim_start_matches('-')))?;
+                config.set(&option, value)?;
+            }
+        }
+
+        Ok(config)
+    }
+}
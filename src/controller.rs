@@ -0,0 +1,300 @@
+use crate::savestate::Savestate;
+
+// Buttons of the standard controller, in the order they are shifted out
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A, Button::B, Button::Select, Button::Start,
+        Button::Up, Button::Down, Button::Left, Button::Right,
+    ];
+
+    // the button's bit in a button state byte
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn parse(name: &str) -> Option<Button> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" => Some(Button::A),
+            "b" => Some(Button::B),
+            "select" => Some(Button::Select),
+            "start" => Some(Button::Start),
+            "up" => Some(Button::Up),
+            "down" => Some(Button::Down),
+            "left" => Some(Button::Left),
+            "right" => Some(Button::Right),
+            _ => None,
+        }
+    }
+}
+
+// Button states to play back through a controller, e.g. for a special move:
+//   down, down+right, right+a*3, none*10
+// Each step is the buttons held, joined with +, for one frame or for the
+// number of frames after a *
+#[derive(Debug,Clone,Default)]
+pub struct Macro {
+    // buttons, frames
+    pub steps: Vec<(u8, u32)>,
+}
+
+impl Macro {
+    pub fn parse(text: &str) -> Result<Macro, String> {
+        let mut steps = Vec::new();
+
+        for step in text.split(',').map(str::trim).filter(|step| !step.is_empty()) {
+            let (names, frames) = match step.split_once('*') {
+                Some((names, frames)) => {
+                    let frames = frames.trim().parse().map_err(|_| format!("invalid frame count in '{}'", step))?;
+                    (names.trim(), frames)
+                },
+                None => (step, 1),
+            };
+
+            let mut buttons = 0;
+            if names != "none" {
+                for name in names.split('+').map(str::trim) {
+                    let button = Button::parse(name).ok_or_else(|| format!("unknown button '{}'", name))?;
+                    buttons |= button.bit();
+                }
+            }
+            steps.push((buttons, frames));
+        }
+
+        Ok(Macro { steps })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.steps.iter().map(|&(_, frames)| frames).sum()
+    }
+
+    // the buttons held `frame` frames in, None once the macro is over
+    pub fn buttons_at(&self, frame: u32) -> Option<u8> {
+        let mut start = 0;
+        for &(buttons, frames) in &self.steps {
+            if frame < start + frames {
+                return Some(buttons);
+            }
+            start += frames;
+        }
+        None
+    }
+}
+
+/**
+ * Standard controller, a 4021 shift register loaded with the buttons.
+ *
+ * $4016 write:
+ *   0: strobe - while set, the register keeps reloading the buttons
+ * $4016/$4017 read:
+ *   7-5: open bus, usually $40 from the high byte of the address
+ *   0: the next button, A first. After all 8 have been read the
+ *      register has shifted in 1s, so an official controller reads 1
+ *
+ * Every read clocks the register, including the extra reads the CPU
+ * makes when a DMC fetch halts it on a read of $4016/$4017, which
+ * makes games that don't read the controller twice lose a button.
+ *
+ * On top of the buttons held, turbo buttons are pressed and released
+ * every `turbo_rate` frames while held, and a macro can press buttons
+ * for a number of frames. Both go through the shift register like
+ * real button presses.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Standard_controller
+ * https://wiki.nesdev.org/w/index.php/Controller_reading
+ * https://wiki.nesdev.org/w/index.php/DMA#DMC_DMA_during_register_reads
+ */
+pub struct Controller {
+    // bit n is set while `Button::ALL[n]` is held
+    pub buttons: u8,
+
+    // buttons that autofire while held, usually A and B,
+    // and how many frames each press and release lasts
+    pub turbo: u8,
+    pub turbo_rate: u8,
+    turbo_frame: u8,
+
+    // the macro playing and how many frames it has played
+    playing: Option<(Macro, u32)>,
+
+    shift: u8,
+    strobe: bool,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Controller {
+            buttons: 0,
+            turbo: 0,
+            turbo_rate: 2,
+            turbo_frame: 0,
+            playing: None,
+            shift: 0,
+            strobe: false,
+        }
+    }
+}
+
+impl Controller {
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.buttons |= button.bit();
+        } else {
+            self.buttons &= !button.bit();
+        }
+    }
+
+    // start playing `macro_`, replacing any macro still playing
+    pub fn play(&mut self, macro_: Macro) {
+        self.playing = Some((macro_, 0));
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = None;
+    }
+
+    // what the game sees this frame
+    pub fn state(&self) -> u8 {
+        let played = self.playing.as_ref()
+            .and_then(|(macro_, frame)| macro_.buttons_at(*frame))
+            .unwrap_or(0);
+        let buttons = self.buttons | played;
+
+        let released = self.turbo_frame >= self.turbo_rate.max(1);
+        if released {
+            buttons & !self.turbo
+        } else {
+            buttons
+        }
+    }
+
+    // move turbo and the macro on to the next frame
+    pub fn end_frame(&mut self) {
+        let period = self.turbo_rate.max(1) as u16 * 2;
+        self.turbo_frame = ((self.turbo_frame as u16 + 1) % period) as u8;
+
+        if let Some((macro_, frame)) = &mut self.playing {
+            *frame += 1;
+            if *frame >= macro_.frames() {
+                self.playing = None;
+            }
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.state();
+        }
+    }
+
+    // bit 0 of a read of the controller's port
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.shift = self.state();
+        }
+
+        let bit = self.shift & 0x01;
+        self.shift = 0x80 | self.shift >> 1;
+        bit
+    }
+
+    // power cycle: the shift register empties and turbo starts over
+    pub fn power(&mut self) {
+        self.turbo_frame = 0;
+        self.shift = 0;
+        self.strobe = false;
+    }
+
+    // the shift register and turbo timing; the buttons are the host's
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.u8(&mut self.turbo_frame);
+        state.u8(&mut self.shift);
+        state.bool(&mut self.strobe);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Button, Controller, Macro};
+
+    #[test]
+    fn parse_macro() {
+        let macro_ = Macro::parse("down, down+right , Right+A*3,none*10").unwrap();
+        let down = Button::Down.bit();
+        let right = Button::Right.bit();
+        assert_eq!(macro_.steps, [(down, 1), (down | right, 1), (right | Button::A.bit(), 3), (0, 10)]);
+        assert_eq!(macro_.frames(), 15);
+
+        assert_eq!(macro_.buttons_at(0), Some(down));
+        assert_eq!(macro_.buttons_at(4), Some(right | Button::A.bit()));
+        assert_eq!(macro_.buttons_at(5), Some(0));
+        assert_eq!(macro_.buttons_at(15), None);
+
+        assert!(Macro::parse("").unwrap().steps.is_empty());
+        assert!(Macro::parse("a+jump").is_err());
+        assert!(Macro::parse("a*x").is_err());
+    }
+
+    #[test]
+    fn turbo_alternates() {
+        for rate in 1..=3 {
+            let mut controller = Controller {
+                buttons: Button::A.bit() | Button::Up.bit(),
+                turbo: Button::A.bit() | Button::B.bit(),
+                turbo_rate: rate,
+                ..Controller::default()
+            };
+
+            // `rate` frames pressed, then `rate` frames released
+            for frame in 0..4 * rate {
+                let pressed = frame / rate % 2 == 0;
+                let expected = Button::Up.bit() | if pressed { Button::A.bit() } else { 0 };
+                assert_eq!(controller.state(), expected, "rate {} frame {}", rate, frame);
+                controller.end_frame();
+            }
+        }
+    }
+
+    #[test]
+    fn macro_playback() {
+        let mut controller = Controller { buttons: Button::Select.bit(), ..Controller::default() };
+        controller.play(Macro::parse("a*2,b").unwrap());
+
+        let mut states = Vec::new();
+        while controller.playing() {
+            states.push(controller.state());
+            controller.end_frame();
+        }
+
+        let select = Button::Select.bit();
+        assert_eq!(states, [select | Button::A.bit(), select | Button::A.bit(), select | Button::B.bit()]);
+        assert_eq!(controller.state(), select);
+    }
+
+    #[test]
+    fn shift_register() {
+        let mut controller = Controller { buttons: Button::A.bit() | Button::Start.bit(), ..Controller::default() };
+        controller.write(0x01);
+        controller.write(0x00);
+
+        let reads: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+        assert_eq!(reads, [1, 0, 0, 1, 0, 0, 0, 0, 1, 1]);
+    }
+}
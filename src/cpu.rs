@@ -1,5 +1,13 @@
-use crate::inst::INSTRUCTIONS;
-use crate::addr::Addr6502;
+use crate::inst::{INSTRUCTIONS, Inst6502};
+use crate::addr::{Addr6502, AddrMode};
+use crate::apu::APU;
+use crate::cartridge::Cartridge;
+use crate::input::Input;
+use crate::dma::{Dma2A03, DmaState};
+use crate::ppu::PPU;
+use crate::region::Region;
+use crate::savestate::Savestate;
+use crate::vs::VsSystem;
 
 pub enum Flags {
     N,
@@ -117,9 +125,53 @@ pub struct CPU {
     // how many cycles are left for the current instruction?
     // when this value reaches 0, then execute the next instruction
     pub cycles: u8,
+
+    // total CPU cycles since power on, including DMA stalls.
+    // DMA reads only happen on even cycles
+    pub cycle_count: u64,
+
+    pub dma: DmaState,
+
+    pub ppu: PPU,
+    pub apu: APU,
+    pub cartridge: Cartridge,
+
+    // controllers and adapters, read through $4016 and $4017
+    pub input: Input,
+
+    // coin slots, DIP switches and protection of Vs. System cabinets
+    pub vs: Option<VsSystem>,
 }
 
 impl CPU {
+    pub fn new(cartridge: Cartridge, region: Region) -> Self {
+        let mut ppu = PPU::new();
+        ppu.region = region;
+
+        CPU {
+            pc: 0,
+            // the reset sequence takes 3 off, leaving $FD
+            sp: 0x00,
+            acc: 0,
+            x: 0,
+            y: 0,
+            flags: CpuFlags { interrupt: true, ignored: true, ..CpuFlags::empty() },
+            ram: [0; 0x800],
+            fetched: 0,
+            eff_addr: 0,
+            jump_offset: 0,
+            opcode: 0,
+            cycles: 0,
+            cycle_count: 0,
+            dma: DmaState::default(),
+            ppu,
+            apu: APU::new(region),
+            cartridge,
+            input: Input::new(),
+            vs: None,
+        }
+    }
+
     /**
      * $0000-$07FF: 2KB internal RAM
      * $0800-$0FFF:
@@ -141,16 +193,77 @@ impl CPU {
             return self.ram[masked_addr];
         }
 
-        todo!("Reads to other areas of memory map must be implemented!");
+        if addr < 0x4000 {
+            return self.ppu.cpu_read(addr, &mut self.cartridge);
+        }
+
+        if addr == 0x4015 {
+            return self.apu.read_status();
+        }
+
+        if addr == 0x4016 || addr == 0x4017 {
+            let data = self.input.read((addr & 0x01) as usize, &self.ppu);
+            // Vs. System cabinets drive the other bits with their switches
+            return match &self.vs {
+                Some(vs) => vs.read_port(addr) | data,
+                None => (addr >> 8) as u8 & 0xE0 | data,
+            };
+        }
+
+        if addr >= 0x4020 {
+            if let Some(value) = self.vs.as_mut().and_then(|vs| vs.protection_read(addr)) {
+                return value;
+            }
+            return self.cartridge.cpu_read(addr);
+        }
+
+        // the APU registers are write-only and $4018-$401F is unused, so
+        // nothing drives the bus and the read returns what was last on it,
+        // usually the high byte of the address
+        (addr >> 8) as u8
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
         if addr < 0x2000 {
             let masked_addr = (addr & 0x07FF) as usize;
             self.ram[masked_addr] = value;
+            return;
+        }
+
+        if addr < 0x4000 {
+            self.ppu.cpu_write(addr, value, &mut self.cartridge);
+            return;
+        }
+
+        if addr == 0x4014 {
+            self.start_oam_dma(value);
+            return;
+        }
+
+        if addr == 0x4017 {
+            // the rest of the instruction's cycles come before the write
+            self.apu.write_frame_counter(value, self.cycles.saturating_sub(1));
+            return;
+        }
+
+        if addr <= 0x4013 || addr == 0x4015 {
+            self.apu.cpu_write(addr, value);
+            return;
+        }
+
+        if addr == 0x4016 {
+            self.input.write(value);
+            if self.vs.is_some() {
+                self.cartridge.vs_bank = (value >> 2) & 0x01;
+            }
+            return;
+        }
+
+        if addr >= 0x4020 {
+            self.cartridge.cpu_write(addr, value);
         }
 
-        todo!("Writes to other areas of memory map must be implemented!");
+        // $4018-$401F: only used in test mode, writes go nowhere
     }
 
     // push a value to the stack
@@ -158,15 +271,95 @@ impl CPU {
         let sp = self.sp as u16;
         self.write(0x100 + sp, value);
 
-        // stack grows downward
-        self.sp -= 1;
+        // stack grows downward, wrapping around within page 1
+        self.sp = self.sp.wrapping_sub(1);
     }
 
     // pull a value from the stack
     pub fn pull(&mut self) -> u8 {
-        let sp = self.sp as u16;
-        self.sp += 1;
-        self.read(0x100 + sp)
+        // sp points at the first empty slot, the value is just above it
+        self.sp = self.sp.wrapping_add(1);
+        self.read(0x100 + self.sp as u16)
+    }
+
+    pub fn read_word(&mut self, addr: u16) -> u16 {
+        let lo: u16 = self.read(addr).into();
+        let hi: u16 = self.read(addr + 1).into();
+        hi << 8 | lo
+    }
+
+    // https://wiki.nesdev.org/w/index.php/CPU_power_up_state
+    pub fn reset(&mut self) {
+        self.pc = self.read_word(0xFFFC);
+        self.sp = self.sp.wrapping_sub(3);
+        self.flags.set(Flags::I, true);
+        self.cycles = 8;
+    }
+
+    // non-maskable interrupt, raised by the PPU at the start of vblank
+    // https://wiki.nesdev.org/w/index.php?title=CPU_interrupts
+    pub fn nmi(&mut self) {
+        self.interrupt(0xFFFA);
+    }
+
+    // interrupt request, ignored while the I flag is set.
+    // The line stays asserted until the source is acknowledged
+    pub fn irq(&mut self) {
+        self.interrupt(0xFFFE);
+    }
+
+    fn irq_pending(&self) -> bool {
+        !self.flags.interrupt && (self.apu.irq() || self.cartridge.irq_pending)
+    }
+
+    fn interrupt(&mut self, vector: u16) {
+        self.push(((self.pc >> 8) & 0x00FF) as u8);
+        self.push((self.pc & 0x00FF) as u8);
+
+        // we're pushing the status flag in a H/W context
+        let flags = CpuFlags { brk: false, ignored: true, ..self.flags };
+        self.push(flags.to_byte());
+        self.flags.set(Flags::I, true);
+
+        self.pc = self.read_word(vector);
+        self.cycles = 7;
+    }
+
+    // one CPU cycle
+    pub fn clock(&mut self) {
+        // the APU keeps running while the CPU is halted
+        if let Some(value) = self.dma.dmc_sample.take() {
+            self.apu.dmc.load_sample(value);
+        }
+        self.apu.clock();
+        self.cartridge.clock();
+        if let Some(addr) = self.apu.dmc.dma_request() {
+            self.request_dmc_dma(addr);
+        }
+
+        // DMA halts the CPU once the current instruction has finished
+        if self.cycles == 0 && self.dma_active() {
+            self.clock_dma();
+            self.cycle_count += 1;
+            return;
+        }
+
+        if self.cycles == 0 {
+            // interrupts are polled between instructions
+            if self.ppu.nmi {
+                self.ppu.nmi = false;
+                self.nmi();
+            } else if self.irq_pending() {
+                self.irq();
+            } else {
+                self.opcode = self.read(self.pc);
+                self.pc = self.pc.wrapping_add(1);
+                self.execute();
+            }
+        }
+
+        self.cycles -= 1;
+        self.cycle_count += 1;
     }
 
     fn execute(&mut self) {
@@ -174,5 +367,57 @@ impl CPU {
         assert!((opcode as usize) < INSTRUCTIONS.len());
         let operation = &INSTRUCTIONS[opcode];
 
+        let page_crossed = match operation.mode {
+            AddrMode::Imp => self.imp(),
+            AddrMode::Imm => self.imm(),
+            AddrMode::Zero => self.zero(),
+            AddrMode::ZeroX => self.zero_x(),
+            AddrMode::ZeroY => self.zero_y(),
+            AddrMode::Abs => self.abs(),
+            AddrMode::AbsX => self.abs_x(),
+            AddrMode::AbsY => self.abs_y(),
+            AddrMode::Ind => self.ind(),
+            AddrMode::IndX => self.ind_x(),
+            AddrMode::IndY => self.ind_y(),
+            AddrMode::Rel => self.rel(),
+        };
+
+        self.cycles = operation.cycles;
+
+        // branches add their own cycles in `jump_if`
+        let extra_cycle = self.execute_op();
+        if page_crossed && extra_cycle {
+            self.cycles += 1;
+        }
+    }
+
+    // the registers, RAM and everything on the bus
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.u16(&mut self.pc);
+        state.u8(&mut self.sp);
+        state.u8(&mut self.acc);
+        state.u8(&mut self.x);
+        state.u8(&mut self.y);
+
+        let mut flags = self.flags.to_byte();
+        state.u8(&mut flags);
+        self.flags = CpuFlags::from_byte(flags);
+
+        state.bytes(&mut self.ram);
+        state.u8(&mut self.fetched);
+        state.u16(&mut self.eff_addr);
+        state.u16(&mut self.jump_offset);
+        state.u8(&mut self.opcode);
+        state.u8(&mut self.cycles);
+        state.u64(&mut self.cycle_count);
+
+        self.dma.savestate(state);
+        self.ppu.savestate(state);
+        self.apu.savestate(state);
+        self.cartridge.savestate(state);
+        self.input.savestate(state);
+        if let Some(vs) = &mut self.vs {
+            vs.savestate(state);
+        }
     }
 }
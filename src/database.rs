@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::config::strip_comment;
+use crate::input::{Adapter, Device};
+use crate::ppu::PpuModel;
+use crate::region::Region;
+use crate::vs::VsProtection;
+
+// What the database knows about a game that its iNES header may not tell
+#[derive(Debug,Default,Clone)]
+pub struct RomInfo {
+    pub region: Option<Region>,
+
+    // Vs. System boards
+    pub ppu: Option<PpuModel>,
+    pub protection: Option<VsProtection>,
+
+    // four player adapter
+    pub adapter: Option<Adapter>,
+
+    // what is plugged into the controller ports and the expansion port
+    pub port1: Option<Device>,
+    pub port2: Option<Device>,
+    pub expansion: Option<Device>,
+}
+
+/**
+ * ROM database, keyed by the CRC-32 of the PRG and CHR ROM without the
+ * header (the same key NesCartDB uses), so bad headers don't matter.
+ *
+ * One game per line, the CRC in hex followed by `key=value` pairs:
+ *   # Super Mario Bros. (Europe)
+ *   D445F698 region=pal
+ *
+ * Keys: region (ntsc, pal, dendy), ppu (2c02, 2c03, 2c04-0001 to 2c04-0004,
+ * 2c05-01 to 2c05-05), protection (none, rbi, tko, xevious) and
+ * adapter (none, fourscore, hori, simple) and the devices in port1, port2
+ * and expansion (none, controller, zapper, vaus, vaus_famicom, powerpad,
+ * family_trainer, keyboard).
+ *
+ * No database is built in: games are only looked up in the file given
+ * with --database, and without one only the ROM header is used.
+ */
+#[derive(Default)]
+pub struct RomDatabase {
+    entries: HashMap<u32, RomInfo>,
+}
+
+impl RomDatabase {
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = |number: usize, msg: String| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, msg));
+
+        let mut entries = HashMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            let mut fields = line.split_whitespace();
+            let crc = match fields.next() {
+                Some(crc) => u32::from_str_radix(crc, 16).map_err(|_| invalid(number, format!("invalid CRC '{}'", crc)))?,
+                None => continue,
+            };
+
+            let mut info = RomInfo::default();
+            for field in fields {
+                let (key, value) = field.split_once('=').ok_or_else(|| invalid(number, format!("expected key=value, got '{}'", field)))?;
+                let unknown = || invalid(number, format!("unknown {} '{}'", key, value));
+                match key {
+                    "region" => info.region = Some(Region::parse(value).ok_or_else(unknown)?),
+                    "ppu" => info.ppu = Some(PpuModel::parse(value).ok_or_else(unknown)?),
+                    "protection" => info.protection = Some(VsProtection::parse(value).ok_or_else(unknown)?),
+                    "adapter" => info.adapter = Some(Adapter::parse(value).ok_or_else(unknown)?),
+                    "port1" => info.port1 = Some(Device::parse(value).ok_or_else(unknown)?),
+                    "port2" => info.port2 = Some(Device::parse(value).ok_or_else(unknown)?),
+                    "expansion" => info.expansion = Some(Device::parse(value).ok_or_else(unknown)?),
+                    // keys this version doesn't know about are skipped
+                    _ => {},
+                }
+            }
+
+            entries.insert(crc, info);
+        }
+
+        Ok(RomDatabase { entries })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        RomDatabase::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn lookup(&self, crc: u32) -> Option<&RomInfo> {
+        self.entries.get(&crc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RomDatabase;
+    use crate::input::Adapter;
+    use crate::region::Region;
+
+    #[test]
+    fn parse() {
+        let database = RomDatabase::parse("\
+# Super Mario Bros. (Europe)
+D445F698 region=pal  # trailing comment
+  # indented comment
+
+12345678 adapter=fourscore future_key=1
+").unwrap();
+
+        assert_eq!(database.lookup(0xD445F698).unwrap().region, Some(Region::Pal));
+        assert_eq!(database.lookup(0x12345678).unwrap().adapter, Some(Adapter::FourScore));
+        assert!(database.lookup(0x00000000).is_none());
+
+        assert!(RomDatabase::parse("XYZ region=pal").is_err());
+        assert!(RomDatabase::parse("12345678 region").is_err());
+        assert!(RomDatabase::parse("12345678 region=mars").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::addr::AddrMode;
+use crate::cpu::CPU;
+use crate::inst::{INSTRUCTIONS, Mnemonic};
+use crate::savestate::Savestate;
+
+// The 2A03 has a DMA unit that takes over the bus from the CPU for two kinds
+// of transfers: OAM DMA, started by writing a page number to $4014, and
+// DMC DMA, which fetches the next delta modulation sample.
+//
+// The unit alternates between "get" (read) cycles on even CPU cycles and
+// "put" (write) cycles on odd ones. The CPU is first halted for one cycle,
+// and a transfer that would start on a put cycle waits one more to align.
+//
+// While halted, the CPU keeps repeating the read it was stopped on. When a
+// DMC fetch stops it on a read of $2007 or $4016/$4017, those extra reads
+// skip a byte of VRAM or a controller bit, which games like Super Mario
+// Bros. 3 work around by reading the controllers until two reads agree.
+//
+// References:
+// https://wiki.nesdev.org/w/index.php/DMA
+// https://wiki.nesdev.org/w/index.php/APU_DMC#Likely_internal_implementation_of_the_read
+#[derive(Default)]
+pub struct DmaState {
+    // the CPU has been halted and the DMA unit owns the bus
+    halted: bool,
+
+    // OAM DMA: source page and number of bytes copied so far
+    oam_page: Option<u8>,
+    oam_index: u16,
+    oam_latch: Option<u8>,
+
+    // DMC DMA: address of the requested sample byte
+    dmc_addr: Option<u16>,
+    dmc_dummy: bool,
+
+    // the read the CPU was halted on, repeated on every cycle the DMC waits
+    repeat_read: Option<u16>,
+
+    // the sample byte fetched for the DMC, waiting to be picked up
+    pub dmc_sample: Option<u8>,
+}
+
+impl DmaState {
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.bool(&mut self.halted);
+        state.option_u8(&mut self.oam_page);
+        state.u16(&mut self.oam_index);
+        state.option_u8(&mut self.oam_latch);
+        state.option_u16(&mut self.dmc_addr);
+        state.bool(&mut self.dmc_dummy);
+        state.option_u16(&mut self.repeat_read);
+        state.option_u8(&mut self.dmc_sample);
+    }
+}
+
+pub trait Dma2A03 {
+    // whether the DMA unit currently has work and stalls the CPU
+    fn dma_active(&self) -> bool;
+
+    // writing $4014 copies $XX00-$XXFF to OAM through $2004
+    fn start_oam_dma(&mut self, page: u8);
+
+    // the DMC asks for the next sample byte
+    fn request_dmc_dma(&mut self, addr: u16);
+
+    // one CPU cycle spent on DMA instead of the current instruction
+    fn clock_dma(&mut self);
+}
+
+impl Dma2A03 for CPU {
+    fn dma_active(&self) -> bool {
+        self.dma.oam_page.is_some() || self.dma.dmc_addr.is_some()
+    }
+
+    fn start_oam_dma(&mut self, page: u8) {
+        self.dma.oam_page = Some(page);
+        self.dma.oam_index = 0;
+        self.dma.oam_latch = None;
+    }
+
+    fn request_dmc_dma(&mut self, addr: u16) {
+        self.dma.dmc_addr = Some(addr);
+
+        // a DMC DMA on its own needs a dummy cycle after the halt;
+        // in the middle of an OAM DMA it simply takes over the next get cycle
+        self.dma.dmc_dummy = !self.dma.halted;
+
+        // Instructions run all at once here, so the halt is taken to land on
+        // the last cycle of the current instruction when that is still ahead.
+        // Only register reads have side effects worth repeating.
+        let inst = &INSTRUCTIONS[self.opcode as usize];
+        let reads_operand = matches!(inst.mnemonic,
+            Mnemonic::LDA | Mnemonic::LDX | Mnemonic::LDY | Mnemonic::ADC | Mnemonic::SBC
+            | Mnemonic::AND | Mnemonic::ORA | Mnemonic::EOR | Mnemonic::CMP | Mnemonic::CPX
+            | Mnemonic::CPY | Mnemonic::BIT)
+            && !matches!(inst.mode, AddrMode::Imp | AddrMode::Imm | AddrMode::Rel);
+        let register = matches!(self.eff_addr, 0x2000..=0x3FFF | 0x4016..=0x4017);
+
+        self.dma.repeat_read = if !self.dma.halted && self.cycles == 2 && reads_operand && register {
+            Some(self.eff_addr)
+        } else {
+            None
+        };
+    }
+
+    fn clock_dma(&mut self) {
+        let get_cycle = self.cycle_count.is_multiple_of(2);
+
+        if !self.dma.halted {
+            self.dma.halted = true;
+            self.repeat_halted_read();
+            return;
+        }
+
+        if let Some(addr) = self.dma.dmc_addr {
+            if self.dma.dmc_dummy {
+                self.dma.dmc_dummy = false;
+                self.repeat_halted_read();
+                return;
+            }
+
+            if get_cycle {
+                // the DMC has priority over OAM DMA for the bus
+                self.dma.dmc_sample = Some(self.read(addr));
+                self.dma.dmc_addr = None;
+                self.dma.repeat_read = None;
+                self.dma.halted = self.dma_active();
+                return;
+            }
+
+            if self.dma.oam_page.is_none() {
+                // alignment cycle
+                self.repeat_halted_read();
+                return;
+            }
+        }
+
+        if let Some(page) = self.dma.oam_page {
+            if get_cycle {
+                let addr = (page as u16) << 8 | self.dma.oam_index;
+                self.dma.oam_latch = Some(self.read(addr));
+            } else if let Some(value) = self.dma.oam_latch.take() {
+                self.ppu.cpu_write(0x2004, value, &mut self.cartridge);
+                self.dma.oam_index += 1;
+                if self.dma.oam_index == 0x100 {
+                    self.dma.oam_page = None;
+                }
+            }
+            // a put cycle without a byte to write is an alignment cycle
+        }
+
+        self.dma.halted = self.dma_active();
+    }
+}
+
+impl CPU {
+    fn repeat_halted_read(&mut self) {
+        if let Some(addr) = self.dma.repeat_read {
+            self.read(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dma2A03;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::CPU;
+    use crate::region::Region;
+
+    // NROM with $C000-$FFFF filled with its low address byte
+    fn new_cpu() -> CPU {
+        let mut rom = b"NES\x1A\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        rom.extend((0..0x4000).map(|n| n as u8));
+        CPU::new(Cartridge::from_bytes(&rom).unwrap(), Region::Ntsc)
+    }
+
+    // CPU cycles spent on DMA before the CPU runs again
+    fn stall(cpu: &mut CPU) -> u32 {
+        let mut cycles = 0;
+        while cpu.dma_active() {
+            cpu.clock();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn oam_dma() {
+        for &(cycle, expected) in &[(0, 514), (1, 513)] {
+            let mut cpu = new_cpu();
+            for n in 0..0x100 {
+                cpu.ram[0x200 + n] = n as u8 ^ 0xA5;
+            }
+
+            // halted on an even cycle, the first get cycle is one further
+            cpu.cycle_count = cycle;
+            cpu.start_oam_dma(0x02);
+            assert_eq!(stall(&mut cpu), expected);
+
+            for n in 0..0x100 {
+                assert_eq!(cpu.ppu.oam[n], n as u8 ^ 0xA5);
+            }
+        }
+    }
+
+    #[test]
+    fn dmc_dma() {
+        // halt, dummy, then the get cycle or an alignment cycle before it
+        for &(cycle, expected) in &[(0, 3), (1, 4)] {
+            let mut cpu = new_cpu();
+            cpu.cycle_count = cycle;
+            cpu.request_dmc_dma(0xC012);
+            assert_eq!(stall(&mut cpu), expected);
+            assert_eq!(cpu.dma.dmc_sample, Some(0x12));
+        }
+    }
+
+    #[test]
+    fn dmc_dma_during_oam_dma() {
+        // the DMC takes a get cycle, and OAM DMA realigns after it
+        let mut cpu = new_cpu();
+        cpu.cycle_count = 1;
+        cpu.start_oam_dma(0x02);
+        for _ in 0..100 {
+            cpu.clock();
+        }
+
+        // a one byte sample at $C040
+        cpu.apu.cpu_write(0x4012, 0x01);
+        cpu.apu.cpu_write(0x4013, 0x00);
+        cpu.apu.cpu_write(0x4015, 0x10);
+        assert_eq!(100 + stall(&mut cpu), 513 + 2);
+        assert_eq!(cpu.apu.read_status() & 0x10, 0x00);
+    }
+
+    #[test]
+    fn dmc_dma_repeats_register_reads() {
+        let mut cpu = new_cpu();
+        cpu.write(0x2006, 0x20);
+        cpu.write(0x2006, 0x00);
+        for value in [0x11, 0x22, 0x33] {
+            cpu.write(0x2007, value);
+        }
+        cpu.write(0x2006, 0x20);
+        cpu.write(0x2006, 0x00);
+
+        // LDA $2007 halted on its last cycle reads it again on the
+        // halt and dummy cycles, moving the VRAM address on twice
+        cpu.opcode = 0xAD;
+        cpu.eff_addr = 0x2007;
+        cpu.cycles = 2;
+        cpu.request_dmc_dma(0xC000);
+        cpu.cycles = 0;
+        stall(&mut cpu);
+        assert_eq!(cpu.read(0x2007), 0x22);
+        assert_eq!(cpu.read(0x2007), 0x33);
+    }
+}
@@ -0,0 +1,397 @@
+// Serial EEPROMs used for saves by some Bandai boards (mappers 16, 157 and 159)
+// instead of battery-backed RAM. The mapper bit-bangs the I2C lines through
+// its registers and reads the data line back.
+//
+// References:
+// https://wiki.nesdev.org/w/index.php/Bandai_FCG_board#Serial_EEPROM
+// https://www.nesdev.org/wiki/24C02
+
+use crate::savestate::Savestate;
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum EepromKind {
+    // 128 bytes, no device address, bits sent LSB first
+    X24C01,
+    // 256 bytes, standard I2C with a device address, bits sent MSB first
+    C24C02,
+}
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+enum Mode {
+    Idle,
+    ChipAddress,
+    Address,
+    Read,
+    Write,
+    SendAck,
+    WaitAck,
+}
+
+pub struct Eeprom {
+    pub kind: EepromKind,
+    pub data: Vec<u8>,
+
+    // set whenever the contents change, cleared once they have been saved
+    pub dirty: bool,
+
+    mode: Mode,
+    next_mode: Mode,
+    chip_address: u8,
+    address: u8,
+    shift: u8,
+    counter: u8,
+    output: bool,
+
+    prev_scl: bool,
+    prev_sda: bool,
+}
+
+impl Eeprom {
+    pub fn new(kind: EepromKind) -> Self {
+        let size = match kind {
+            EepromKind::X24C01 => 128,
+            EepromKind::C24C02 => 256,
+        };
+
+        Eeprom {
+            kind,
+            data: vec![0xFF; size],
+            dirty: false,
+            mode: Mode::Idle,
+            next_mode: Mode::Idle,
+            chip_address: 0,
+            address: 0,
+            shift: 0,
+            counter: 0,
+            output: true,
+            prev_scl: false,
+            prev_sda: false,
+        }
+    }
+
+    // state of the SDA line as driven by the EEPROM
+    pub fn read(&self) -> bool {
+        self.output
+    }
+
+    fn bit_position(&self) -> u8 {
+        match self.kind {
+            EepromKind::X24C01 => self.counter,
+            EepromKind::C24C02 => 7 - self.counter,
+        }
+    }
+
+    fn shift_in(&mut self, sda: bool) -> u8 {
+        if self.counter < 8 {
+            let bit = self.bit_position();
+            self.shift = (self.shift & !(1 << bit)) | ((sda as u8) << bit);
+            self.counter += 1;
+        }
+        self.shift
+    }
+
+    fn shift_out(&mut self) {
+        if self.counter < 8 {
+            let bit = self.bit_position();
+            self.output = self.shift & (1 << bit) != 0;
+            self.counter += 1;
+        }
+    }
+
+    fn mask(&self) -> u8 {
+        (self.data.len() - 1) as u8
+    }
+
+    fn start_read(&mut self) {
+        self.shift = self.data[self.address as usize];
+    }
+
+    // update the SCL (clock) and SDA (data) lines
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.prev_scl && scl && self.prev_sda && !sda {
+            // start condition: SDA falls while SCL is high
+            self.mode = match self.kind {
+                EepromKind::X24C01 => Mode::Address,
+                EepromKind::C24C02 => Mode::ChipAddress,
+            };
+            self.counter = 0;
+            self.output = true;
+        } else if self.prev_scl && scl && !self.prev_sda && sda {
+            // stop condition: SDA rises while SCL is high
+            self.mode = Mode::Idle;
+            self.output = true;
+        } else if !self.prev_scl && scl {
+            // rising edge of the clock: sample or present a bit
+            match self.mode {
+                Mode::ChipAddress => self.chip_address = self.shift_in(sda),
+                Mode::Address => self.address = self.shift_in(sda),
+                Mode::Write => { self.shift_in(sda); },
+                Mode::Read => self.shift_out(),
+                Mode::SendAck => self.output = false,
+                Mode::WaitAck => {
+                    // the host acknowledges to continue a sequential read
+                    if !sda {
+                        self.next_mode = Mode::Read;
+                        self.start_read();
+                    } else {
+                        self.next_mode = Mode::Idle;
+                    }
+                },
+                Mode::Idle => {},
+            }
+        } else if self.prev_scl && !scl {
+            // falling edge of the clock: advance the state machine
+            self.on_falling_edge();
+        }
+
+        self.prev_scl = scl;
+        self.prev_sda = sda;
+    }
+
+    fn on_falling_edge(&mut self) {
+        match self.mode {
+            Mode::ChipAddress if self.counter == 8 => {
+                self.counter = 0;
+                self.output = true;
+                // 1010xxx is the device code of I2C EEPROMs
+                if self.chip_address & 0xF0 == 0xA0 {
+                    self.mode = Mode::SendAck;
+                    if self.chip_address & 0x01 != 0 {
+                        self.next_mode = Mode::Read;
+                        self.start_read();
+                    } else {
+                        self.next_mode = Mode::Address;
+                    }
+                } else {
+                    self.mode = Mode::Idle;
+                }
+            },
+            Mode::Address if self.counter == 8 => {
+                self.counter = 0;
+                self.output = true;
+                self.mode = Mode::SendAck;
+                match self.kind {
+                    // the eighth bit of the X24C01 address byte selects read or write
+                    EepromKind::X24C01 => {
+                        let read = self.address & 0x80 != 0;
+                        self.address &= 0x7F;
+                        if read {
+                            self.next_mode = Mode::Read;
+                            self.start_read();
+                        } else {
+                            self.next_mode = Mode::Write;
+                        }
+                    },
+                    EepromKind::C24C02 => self.next_mode = Mode::Write,
+                }
+            },
+            Mode::Read if self.counter == 8 => {
+                self.mode = Mode::WaitAck;
+                self.address = self.address.wrapping_add(1) & self.mask();
+            },
+            Mode::Write if self.counter == 8 => {
+                self.counter = 0;
+                self.mode = Mode::SendAck;
+                self.next_mode = Mode::Write;
+                self.data[self.address as usize] = self.shift;
+                self.dirty = true;
+
+                // writes wrap around within a 4 byte page on the X24C01
+                // and within an 8 byte page on the 24C02
+                let page = match self.kind {
+                    EepromKind::X24C01 => 0x03,
+                    EepromKind::C24C02 => 0x07,
+                };
+                self.address = (self.address & !page) | (self.address.wrapping_add(1) & page);
+            },
+            Mode::SendAck | Mode::WaitAck => {
+                self.mode = self.next_mode;
+                self.counter = 0;
+                self.output = true;
+            },
+            _ => {},
+        }
+    }
+
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.memory(&mut self.data);
+        if state.loading() {
+            self.dirty = true;
+        }
+
+        for mode in [&mut self.mode, &mut self.next_mode] {
+            let mut value = *mode as u8;
+            state.u8(&mut value);
+            *mode = match value {
+                1 => Mode::ChipAddress,
+                2 => Mode::Address,
+                3 => Mode::Read,
+                4 => Mode::Write,
+                5 => Mode::SendAck,
+                6 => Mode::WaitAck,
+                _ => Mode::Idle,
+            };
+        }
+
+        state.u8(&mut self.chip_address);
+        state.u8(&mut self.address);
+        state.u8(&mut self.shift);
+        state.u8(&mut self.counter);
+        state.bool(&mut self.output);
+        state.bool(&mut self.prev_scl);
+        state.bool(&mut self.prev_sda);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Eeprom, EepromKind};
+
+    // drives the SCL and SDA lines like the mapper registers do
+    struct Bus {
+        eeprom: Eeprom,
+        scl: bool,
+        sda: bool,
+    }
+
+    impl Bus {
+        fn new(kind: EepromKind) -> Self {
+            let mut eeprom = Eeprom::new(kind);
+            eeprom.write(true, true);
+            Bus { eeprom, scl: true, sda: true }
+        }
+
+        fn set(&mut self, scl: bool, sda: bool) {
+            self.scl = scl;
+            self.sda = sda;
+            self.eeprom.write(scl, sda);
+        }
+
+        // SDA falls while SCL is high
+        fn start(&mut self) {
+            self.set(false, self.sda);
+            self.set(false, true);
+            self.set(true, true);
+            self.set(true, false);
+            self.set(false, false);
+        }
+
+        // SDA rises while SCL is high
+        fn stop(&mut self) {
+            self.set(false, false);
+            self.set(true, false);
+            self.set(true, true);
+        }
+
+        // one clock with SDA at `sda`, returning what the EEPROM drives
+        fn clock(&mut self, sda: bool) -> bool {
+            self.set(false, sda);
+            self.set(true, sda);
+            let bit = self.eeprom.read();
+            self.set(false, sda);
+            bit
+        }
+
+        fn lsb_first(&self) -> bool {
+            self.eeprom.kind == EepromKind::X24C01
+        }
+
+        // true if the EEPROM acknowledged
+        fn send(&mut self, byte: u8) -> bool {
+            for n in 0..8 {
+                let bit = if self.lsb_first() { n } else { 7 - n };
+                self.clock(byte & (1 << bit) != 0);
+            }
+            !self.clock(true)
+        }
+
+        fn receive(&mut self, ack: bool) -> u8 {
+            let mut byte = 0;
+            for n in 0..8 {
+                let bit = if self.lsb_first() { n } else { 7 - n };
+                byte |= (self.clock(true) as u8) << bit;
+            }
+            self.clock(!ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn c24c02_write_and_read() {
+        let mut bus = Bus::new(EepromKind::C24C02);
+        bus.start();
+        assert!(bus.send(0xA0));
+        assert!(bus.send(0x10));
+        assert!(bus.send(0x12));
+        assert!(bus.send(0x34));
+        bus.stop();
+
+        assert_eq!(bus.eeprom.data[0x10..0x12], [0x12, 0x34]);
+        assert!(bus.eeprom.dirty);
+
+        // a dummy write sets the address, then a repeated start reads
+        bus.start();
+        assert!(bus.send(0xA0));
+        assert!(bus.send(0x10));
+        bus.start();
+        assert!(bus.send(0xA1));
+        assert_eq!(bus.receive(true), 0x12);
+        assert_eq!(bus.receive(false), 0x34);
+        bus.stop();
+
+        // reading goes on from where it stopped
+        bus.start();
+        assert!(bus.send(0xA1));
+        assert_eq!(bus.receive(false), 0xFF);
+        bus.stop();
+    }
+
+    #[test]
+    fn c24c02_page_wrap() {
+        let mut bus = Bus::new(EepromKind::C24C02);
+        bus.start();
+        bus.send(0xA0);
+        bus.send(0x06);
+        for value in 1..=3 {
+            bus.send(value);
+        }
+        bus.stop();
+
+        assert_eq!(bus.eeprom.data[0x00], 3);
+        assert_eq!(bus.eeprom.data[0x06..0x09], [1, 2, 0xFF]);
+    }
+
+    #[test]
+    fn c24c02_ignores_other_devices() {
+        let mut bus = Bus::new(EepromKind::C24C02);
+        bus.start();
+        assert!(!bus.send(0x50));
+        assert!(!bus.send(0x00));
+        bus.stop();
+        assert!(!bus.eeprom.dirty);
+    }
+
+    #[test]
+    fn x24c01_write_and_read() {
+        let mut bus = Bus::new(EepromKind::X24C01);
+
+        // 7 bit address, then the read bit
+        bus.start();
+        assert!(bus.send(0x7E));
+        assert!(bus.send(0xAB));
+        assert!(bus.send(0xCD));
+        assert!(bus.send(0xEF));
+        bus.stop();
+
+        // writes wrap within a 4 byte page
+        assert_eq!(bus.eeprom.data[0x7C..0x80], [0xEF, 0xFF, 0xAB, 0xCD]);
+
+        bus.start();
+        assert!(bus.send(0x80 | 0x7E));
+        assert_eq!(bus.receive(true), 0xAB);
+        assert_eq!(bus.receive(true), 0xCD);
+        // sequential reads wrap around the whole chip
+        assert_eq!(bus.receive(false), 0xFF);
+        bus.stop();
+    }
+}
@@ -0,0 +1,201 @@
+// SST39SF040 flash memory, used as self-writable PRG ROM by UNROM 512 (mapper 30)
+// boards so homebrew games can save without a battery.
+//
+// Commands are issued by writing magic values to magic addresses:
+// $5555 <- $AA, $2AAA <- $55, then $5555 <- command
+//
+// References:
+// https://wiki.nesdev.org/w/index.php/UNROM_512#Flash_Memory
+// http://ww1.microchip.com/downloads/en/DeviceDoc/20005022C.pdf
+
+use crate::savestate::Savestate;
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+enum Mode {
+    Read,
+    SoftwareId,
+    Program,
+}
+
+pub struct Flash {
+    pub data: Vec<u8>,
+
+    // set whenever the contents change, cleared once they have been saved
+    pub dirty: bool,
+
+    mode: Mode,
+
+    // how far into an unlock sequence we are
+    cycle: u8,
+
+    // the erase command is a second unlock sequence after $80
+    erase_pending: bool,
+}
+
+const SECTOR_SIZE: usize = 0x1000;
+
+impl Flash {
+    pub fn new(data: Vec<u8>) -> Self {
+        Flash {
+            data,
+            dirty: false,
+            mode: Mode::Read,
+            cycle: 0,
+            erase_pending: false,
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        match self.mode {
+            // manufacturer (SST) and device (SST39SF040) ids
+            Mode::SoftwareId => if addr & 0x01 == 0 { 0xBF } else { 0xB7 },
+            _ => self.data[addr % self.data.len()],
+        }
+    }
+
+    // `addr` is the address on the chip, already translated by the mapper
+    pub fn write(&mut self, addr: usize, value: u8) {
+        let cmd_addr = addr & 0x7FFF;
+
+        if self.mode == Mode::Program {
+            // programming can only clear bits; only an erase sets them again
+            let addr = addr % self.data.len();
+            self.data[addr] &= value;
+            self.dirty = true;
+            self.mode = Mode::Read;
+            return;
+        }
+
+        match (self.cycle, cmd_addr, value) {
+            (_, _, 0xF0) => {
+                // reset / software id exit
+                self.mode = Mode::Read;
+                self.cycle = 0;
+                self.erase_pending = false;
+            },
+            (0, 0x5555, 0xAA) => self.cycle = 1,
+            (1, 0x2AAA, 0x55) => self.cycle = 2,
+            (2, 0x5555, 0xA0) if !self.erase_pending => {
+                self.mode = Mode::Program;
+                self.cycle = 0;
+            },
+            (2, 0x5555, 0x80) if !self.erase_pending => {
+                self.erase_pending = true;
+                self.cycle = 0;
+            },
+            (2, 0x5555, 0x90) if !self.erase_pending => {
+                self.mode = Mode::SoftwareId;
+                self.cycle = 0;
+            },
+            (2, 0x5555, 0x10) if self.erase_pending => {
+                self.data.iter_mut().for_each(|byte| *byte = 0xFF);
+                self.dirty = true;
+                self.cycle = 0;
+                self.erase_pending = false;
+            },
+            (2, _, 0x30) if self.erase_pending => {
+                let start = (addr % self.data.len()) & !(SECTOR_SIZE - 1);
+                let end = (start + SECTOR_SIZE).min(self.data.len());
+                self.data[start..end].iter_mut().for_each(|byte| *byte = 0xFF);
+                self.dirty = true;
+                self.cycle = 0;
+                self.erase_pending = false;
+            },
+            _ => {
+                self.cycle = 0;
+                self.erase_pending = false;
+            },
+        }
+    }
+
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.memory(&mut self.data);
+        if state.loading() {
+            self.dirty = true;
+        }
+
+        let mut mode = self.mode as u8;
+        state.u8(&mut mode);
+        self.mode = match mode {
+            1 => Mode::SoftwareId,
+            2 => Mode::Program,
+            _ => Mode::Read,
+        };
+
+        state.u8(&mut self.cycle);
+        state.bool(&mut self.erase_pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flash;
+
+    fn command(flash: &mut Flash, command: u8) {
+        flash.write(0x5555, 0xAA);
+        flash.write(0x2AAA, 0x55);
+        flash.write(0x5555, command);
+    }
+
+    #[test]
+    fn program_clears_bits() {
+        let mut flash = Flash::new(vec![0xFF; 0x10000]);
+        command(&mut flash, 0xA0);
+        flash.write(0x1234, 0x5A);
+        assert_eq!(flash.read(0x1234), 0x5A);
+        assert!(flash.dirty);
+
+        // programming again can't set bits that are clear
+        command(&mut flash, 0xA0);
+        flash.write(0x1234, 0xF0);
+        assert_eq!(flash.read(0x1234), 0x50);
+
+        // without the unlock sequence writes do nothing
+        flash.write(0x1235, 0x00);
+        assert_eq!(flash.read(0x1235), 0xFF);
+    }
+
+    #[test]
+    fn broken_unlock_sequence() {
+        let mut flash = Flash::new(vec![0xFF; 0x10000]);
+        flash.write(0x5555, 0xAA);
+        flash.write(0x2AAB, 0x55);
+        flash.write(0x5555, 0xA0);
+        flash.write(0x0000, 0x00);
+        assert_eq!(flash.read(0x0000), 0xFF);
+        assert!(!flash.dirty);
+    }
+
+    #[test]
+    fn sector_erase() {
+        let mut flash = Flash::new(vec![0x00; 0x10000]);
+        command(&mut flash, 0x80);
+        flash.write(0x5555, 0xAA);
+        flash.write(0x2AAA, 0x55);
+        flash.write(0x1234, 0x30);
+
+        assert!(flash.data[0x1000..0x2000].iter().all(|&byte| byte == 0xFF));
+        assert_eq!(flash.read(0x0FFF), 0x00);
+        assert_eq!(flash.read(0x2000), 0x00);
+        assert!(flash.dirty);
+    }
+
+    #[test]
+    fn chip_erase() {
+        let mut flash = Flash::new(vec![0x00; 0x10000]);
+        command(&mut flash, 0x80);
+        command(&mut flash, 0x10);
+        assert!(flash.data.iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn software_id() {
+        let mut flash = Flash::new(vec![0x00; 0x10000]);
+        command(&mut flash, 0x90);
+        assert_eq!(flash.read(0x0000), 0xBF);
+        assert_eq!(flash.read(0x0001), 0xB7);
+
+        flash.write(0x0000, 0xF0);
+        assert_eq!(flash.read(0x0001), 0x00);
+    }
+}
@@ -0,0 +1,363 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::cartridge::Cartridge;
+use crate::input::Device;
+use crate::movie::{Movie, MovieFrame, COMMAND_POWER, COMMAND_RESET};
+use crate::region::Region;
+use crate::zip::ZipArchive;
+
+// Buttons in the order both BizHawk and Mesen log them, with their bits
+const BUTTON_NAMES: [(&str, u8); 8] = [
+    ("Up", 0x10), ("Down", 0x20), ("Left", 0x40), ("Right", 0x80),
+    ("Start", 0x08), ("Select", 0x04), ("B", 0x02), ("A", 0x01),
+];
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn text_file(archive: &ZipArchive, name: &str) -> Result<Option<String>> {
+    match archive.read(name) {
+        Some(data) => Ok(Some(String::from_utf8_lossy(&data?).into_owned())),
+        None => Ok(None),
+    }
+}
+
+// `Key Value` lines
+fn header_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    text.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        if name == key { Some(value.trim()) } else { None }
+    })
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    let hex = hex.trim().trim_start_matches("SHA1:");
+    if hex.len() != 40 {
+        return None;
+    }
+
+    let mut digest = [0; 20];
+    for (n, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(n * 2..n * 2 + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/**
+ * A movie from another emulator, converted to the frames of an FM2 movie.
+ *
+ * BizHawk .bk2: a zip of
+ *   Header.txt: `Key Value` lines - Platform, SHA1, rerecordCount, PAL,
+ *     StartsFromSavestate, ...
+ *   Input Log.txt: a LogKey line naming the buttons of each field,
+ *     e.g. LogKey:#Reset|Power|#P1 Up|P1 Down|...|P1 A|, then a line
+ *     per frame, e.g. |..|U......A|........|
+ *   Comments.txt, Subtitles.txt
+ *
+ * Mesen .mmo: a zip of
+ *   GameSettings.txt: `Key Value` lines - SHA1, Region, Controller1-4, ...
+ *   Input.txt: a line per frame, each controller as UDLRSsBA
+ *   SaveState.mst when the movie starts from a savestate
+ *
+ * A button is held when its character is anything but '.' or a space.
+ *
+ * References:
+ * https://tasvideos.org/Bizhawk/BK2Format
+ * https://github.com/SourMesen/Mesen/blob/master/Core/MovieRecorder.cpp
+ */
+pub struct ImportedMovie {
+    pub movie: Movie,
+
+    // what the movie was recorded with, when it says
+    pub sha1: Option<[u8; 20]>,
+    pub region: Option<Region>,
+
+    // parts of the movie that can't be played back here
+    pub warnings: Vec<String>,
+}
+
+impl ImportedMovie {
+    // a .bk2 or .mmo file, told apart by the extension
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("bk2") => ImportedMovie::from_bk2(&data),
+            Some("mmo") => ImportedMovie::from_mmo(&data),
+            _ => Err(invalid(format!("{} is not a .bk2 or .mmo movie", path.display()))),
+        }
+    }
+
+    pub fn from_bk2(data: &[u8]) -> Result<Self> {
+        let archive = ZipArchive::parse(data)?;
+        let header = text_file(&archive, "Header.txt")?.ok_or_else(|| invalid("Header.txt missing from .bk2".to_string()))?;
+        let log = text_file(&archive, "Input Log.txt")?.ok_or_else(|| invalid("Input Log.txt missing from .bk2".to_string()))?;
+
+        if let Some(platform) = header_value(&header, "Platform").filter(|&platform| platform != "NES") {
+            return Err(invalid(format!("{} movies are not supported", platform)));
+        }
+        if header_value(&header, "StartsFromSavestate") == Some("True") {
+            return Err(invalid("movies that start from a savestate are not supported".to_string()));
+        }
+
+        let mut imported = ImportedMovie {
+            movie: Movie::new(header_value(&header, "GameName").unwrap_or(""), [0; 16]),
+            sha1: header_value(&header, "SHA1").and_then(parse_sha1),
+            region: header_value(&header, "PAL").map(|pal| if pal == "True" { Region::Pal } else { Region::Ntsc }),
+            warnings: Vec::new(),
+        };
+        imported.movie.rerecord_count = header_value(&header, "rerecordCount").and_then(|count| count.parse().ok()).unwrap_or(0);
+        if header_value(&header, "StartsFromSaveRam") == Some("True") {
+            imported.warnings.push("the movie starts from save RAM, which is not loaded".to_string());
+        }
+        for (name, lines) in [("Comments.txt", &mut imported.movie.comments), ("Subtitles.txt", &mut imported.movie.subtitles)] {
+            if let Some(text) = text_file(&archive, name)? {
+                lines.extend(text.lines().filter(|line| !line.is_empty()).map(str::to_string));
+            }
+        }
+
+        // the button names of each field
+        let groups: Vec<Vec<&str>> = log.lines()
+            .find_map(|line| line.strip_prefix("LogKey:"))
+            .ok_or_else(|| invalid("Input Log.txt has no LogKey".to_string()))?
+            .split('#')
+            .filter(|group| !group.is_empty())
+            .map(|group| group.split('|').filter(|name| !name.is_empty()).collect())
+            .collect();
+
+        for line in log.lines().filter(|line| line.starts_with('|')) {
+            let fields = line.trim_matches('|').split('|');
+            let mut frame = MovieFrame::default();
+
+            for (names, field) in groups.iter().zip(fields) {
+                for (&name, c) in names.iter().zip(field.chars()) {
+                    let held = c != '.' && c != ' ';
+                    match imported.bk2_button(name) {
+                        Some((Some(controller), bit)) if held => frame.controllers[controller] |= bit,
+                        Some((None, command)) if held => frame.commands |= command,
+                        _ => {},
+                    }
+                }
+            }
+
+            imported.movie.frames.push(frame);
+        }
+
+        imported.movie.fourscore = imported.movie.frames.iter().any(|frame| frame.controllers[2] | frame.controllers[3] != 0);
+        imported.movie.pal = imported.region == Some(Region::Pal);
+        Ok(imported)
+    }
+
+    // "P2 Start" -> controller 1 and the Start bit, "Reset" -> the reset command.
+    // Warns about anything else once
+    fn bk2_button(&mut self, name: &str) -> Option<(Option<usize>, u8)> {
+        match name {
+            "Reset" => return Some((None, COMMAND_RESET)),
+            "Power" => return Some((None, COMMAND_POWER)),
+            _ => {},
+        }
+
+        let button = name.strip_prefix('P')
+            .and_then(|name| name.split_once(' '))
+            .and_then(|(player, button)| {
+                let controller = player.parse::<usize>().ok().filter(|player| (1..=4).contains(player))? - 1;
+                let &(_, bit) = BUTTON_NAMES.iter().find(|&&(button_name, _)| button_name == button)?;
+                Some((Some(controller), bit))
+            });
+
+        if button.is_none() {
+            let warning = format!("input '{}' is not supported", name);
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        }
+        button
+    }
+
+    pub fn from_mmo(data: &[u8]) -> Result<Self> {
+        let archive = ZipArchive::parse(data)?;
+        let settings = text_file(&archive, "GameSettings.txt")?.ok_or_else(|| invalid("GameSettings.txt missing from .mmo".to_string()))?;
+        let input = text_file(&archive, "Input.txt")?.ok_or_else(|| invalid("Input.txt missing from .mmo".to_string()))?;
+
+        if archive.read("SaveState.mst").is_some() {
+            return Err(invalid("movies that start from a savestate are not supported".to_string()));
+        }
+
+        let mut imported = ImportedMovie {
+            movie: Movie::new(header_value(&settings, "GameFile").unwrap_or(""), [0; 16]),
+            sha1: header_value(&settings, "SHA1").and_then(parse_sha1),
+            region: header_value(&settings, "Region").and_then(Region::parse),
+            warnings: Vec::new(),
+        };
+
+        let mut controllers = [false; 4];
+        for (n, connected) in controllers.iter_mut().enumerate() {
+            match header_value(&settings, &format!("Controller{}", n + 1)) {
+                Some("StandardController") => *connected = true,
+                None | Some("None") => {},
+                Some(other) => imported.warnings.push(format!("controller {} is a {}, which is not supported", n + 1, other)),
+            }
+        }
+        if let Some(device) = header_value(&settings, "ExpansionDevice").filter(|&device| device != "None") {
+            imported.warnings.push(format!("expansion device {} is not supported", device));
+        }
+
+        for line in input.lines().filter(|line| line.starts_with('|')) {
+            let mut frame = MovieFrame::default();
+            for (controller, field) in line[1..].split('|').take(4).enumerate() {
+                for (&(_, bit), c) in BUTTON_NAMES.iter().zip(field.chars()) {
+                    if c != '.' && c != ' ' {
+                        frame.controllers[controller] |= bit;
+                    }
+                }
+            }
+            imported.movie.frames.push(frame);
+        }
+
+        imported.movie.fourscore = controllers[2] || controllers[3];
+        imported.movie.ports = [0, 1].map(|n| if controllers[n] { Device::Controller } else { Device::None });
+        imported.movie.pal = imported.region == Some(Region::Pal);
+        Ok(imported)
+    }
+
+    // warnings about differences between how the movie
+    // was recorded and how it is about to be played back
+    pub fn check(&self, cartridge: &Cartridge, region: Region) -> Vec<String> {
+        let mut warnings = self.warnings.clone();
+
+        if self.sha1.is_some_and(|sha1| sha1 != cartridge.sha1()) {
+            warnings.push("the movie was recorded with a different ROM".to_string());
+        }
+        if let Some(recorded) = self.region.filter(|&recorded| recorded != region) {
+            warnings.push(format!("the movie was recorded on {:?}, but the console is {:?}", recorded, region));
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImportedMovie;
+    use crate::cartridge::Cartridge;
+    use crate::input::Device;
+    use crate::movie::COMMAND_RESET;
+    use crate::region::Region;
+    use crate::zip::tests::text_zip;
+
+    const SHA1: &str = "0123456789abcdef0123456789ABCDEF01234567";
+
+    const BK2_HEADER: &str = "MovieVersion BizHawk v2.0
+Platform NES
+GameName Test Game
+SHA1 0123456789abcdef0123456789ABCDEF01234567
+rerecordCount 12
+PAL False
+StartsFromSaveRam True
+";
+
+    const BK2_LOG: &str = "[Input]
+LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|#FDS Eject|
+|..|U......A|........|.|
+|r.|...R..B.|.......A|.|
+|..|........|U.......|E|
+[/Input]
+";
+
+    #[test]
+    fn bk2() {
+        let data = text_zip(&[("Header.txt", BK2_HEADER), ("Input Log.txt", BK2_LOG), ("Comments.txt", "first\n\nsecond\n")]);
+        let imported = ImportedMovie::from_bk2(&data).unwrap();
+        let movie = &imported.movie;
+
+        assert_eq!(movie.rom_filename, "Test Game");
+        assert_eq!(movie.rerecord_count, 12);
+        assert_eq!(movie.comments, ["first", "second"]);
+        assert_eq!(imported.sha1.unwrap()[..3], [0x01, 0x23, 0x45]);
+        assert_eq!(imported.sha1.unwrap()[19], 0x67);
+        assert_eq!(imported.region, Some(Region::Ntsc));
+        assert!(!movie.pal && !movie.fourscore);
+
+        // Up A, then reset with Right B and A on controller 2, then Up on controller 2
+        let frames: Vec<_> = movie.frames.iter().map(|frame| (frame.commands, frame.controllers)).collect();
+        assert_eq!(frames, [(0, [0x11, 0, 0, 0]), (COMMAND_RESET, [0x82, 0x01, 0, 0]), (0, [0, 0x10, 0, 0])]);
+
+        // each unsupported input is only mentioned once
+        assert_eq!(imported.warnings, [
+            "the movie starts from save RAM, which is not loaded",
+            "input 'FDS Eject' is not supported",
+        ]);
+    }
+
+    #[test]
+    fn bk2_errors() {
+        let savestate = BK2_HEADER.replace("StartsFromSaveRam", "StartsFromSavestate");
+        let snes = BK2_HEADER.replace("Platform NES", "Platform SNES");
+        assert!(ImportedMovie::from_bk2(&text_zip(&[("Header.txt", &savestate), ("Input Log.txt", BK2_LOG)])).is_err());
+        assert!(ImportedMovie::from_bk2(&text_zip(&[("Header.txt", &snes), ("Input Log.txt", BK2_LOG)])).is_err());
+        assert!(ImportedMovie::from_bk2(&text_zip(&[("Header.txt", BK2_HEADER)])).is_err());
+        assert!(ImportedMovie::from_bk2(&text_zip(&[("Header.txt", BK2_HEADER), ("Input Log.txt", "|..|\n")])).is_err());
+    }
+
+    const MMO_SETTINGS: &str = "MesenVersion 0.9.9
+GameFile test.nes
+SHA1 0123456789abcdef0123456789ABCDEF01234567
+Region PAL
+Controller1 StandardController
+Controller2 Zapper
+ExpansionDevice FamilyBasicKeyboard
+";
+
+    #[test]
+    fn mmo() {
+        // UDLRSsBA for each controller
+        let input = "|U......A|........\n|...RS...|.D......\n";
+        let data = text_zip(&[("GameSettings.txt", MMO_SETTINGS), ("Input.txt", input)]);
+        let imported = ImportedMovie::from_mmo(&data).unwrap();
+        let movie = &imported.movie;
+
+        assert_eq!(movie.rom_filename, "test.nes");
+        assert_eq!(imported.sha1.unwrap()[0], 0x01);
+        assert_eq!(imported.region, Some(Region::Pal));
+        assert!(movie.pal && !movie.fourscore);
+        assert_eq!(movie.ports, [Device::Controller, Device::None]);
+
+        let frames: Vec<_> = movie.frames.iter().map(|frame| frame.controllers).collect();
+        assert_eq!(frames, [[0x11, 0, 0, 0], [0x88, 0x20, 0, 0]]);
+
+        assert_eq!(imported.warnings, [
+            "controller 2 is a Zapper, which is not supported",
+            "expansion device FamilyBasicKeyboard is not supported",
+        ]);
+    }
+
+    #[test]
+    fn mmo_errors() {
+        let input = "|........|........\n";
+        assert!(ImportedMovie::from_mmo(&text_zip(&[("GameSettings.txt", MMO_SETTINGS)])).is_err());
+        assert!(ImportedMovie::from_mmo(&text_zip(&[
+            ("GameSettings.txt", MMO_SETTINGS), ("Input.txt", input), ("SaveState.mst", "state"),
+        ])).is_err());
+    }
+
+    #[test]
+    fn check() {
+        let mut rom = b"NES\x1A\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        rom.resize(16 + 0x4000, 0);
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+
+        let data = text_zip(&[("GameSettings.txt", MMO_SETTINGS), ("Input.txt", "")]);
+        let mut imported = ImportedMovie::from_mmo(&data).unwrap();
+        imported.warnings.clear();
+        assert_eq!(imported.check(&cartridge, Region::Ntsc), [
+            "the movie was recorded with a different ROM",
+            "the movie was recorded on Pal, but the console is Ntsc",
+        ]);
+
+        imported.sha1 = Some(cartridge.sha1());
+        assert!(imported.check(&cartridge, Region::Pal).is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::{Error, ErrorKind, Result};
+
+// Decoder for DEFLATE streams, the compression used in zip files,
+// after Mark Adler's puff.c.
+//
+// References:
+// https://www.ietf.org/rfc/rfc1951.txt
+// https://github.com/madler/zlib/blob/master/contrib/puff/puff.c
+
+const MAX_BITS: usize = 15;
+
+// base lengths and distances of the length and distance codes, and their extra bits
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// order in which dynamic blocks give the code lengths of the code length code
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    // `n` bits, LSB first
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman code, given by how many codes there are of each
+// length and the symbols in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    // codes are stored MSB first, one bit at a time
+    fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = BitReader { data, pos: 0, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored(&mut reader, &mut out)?,
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                codes(&mut reader, &mut out, &lengths, &distances)?;
+            },
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut reader)?;
+                codes(&mut reader, &mut out, &lengths, &distances)?;
+            },
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<()> {
+    reader.align();
+
+    let header = reader.data.get(reader.pos..reader.pos + 4).ok_or_else(|| invalid("truncated stored block"))?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+    if length != !complement {
+        return Err(invalid("corrupt stored block length"));
+    }
+    reader.pos += 4;
+
+    let block = reader.data.get(reader.pos..reader.pos + length as usize).ok_or_else(|| invalid("truncated stored block"))?;
+    out.extend_from_slice(block);
+    reader.pos += length as usize;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid("too many codes in dynamic block"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    // the literal/length and distance code lengths follow as one run-length coded list
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or_else(|| invalid("repeat with no previous length"))?;
+                (previous, 3 + reader.bits(2)?)
+            },
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err(invalid("too many code lengths in dynamic block"));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+
+    if lengths[256] == 0 {
+        return Err(invalid("dynamic block has no end code"));
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn codes(reader: &mut BitReader, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> Result<()> {
+    loop {
+        let symbol = lengths.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length code"));
+                }
+                let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(reader)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(invalid("invalid distance code"));
+                }
+                let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance too far back"));
+                }
+
+                // the copy may overlap what it writes
+                let start = out.len() - distance;
+                for n in 0..length {
+                    let byte = out[start + n];
+                    out.push(byte);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inflate;
+
+    // "Hello, Hello, Hello!" with the fixed codes, the repeat as a length and distance
+    const FIXED: [u8; 12] = [0xF3, 0x48, 0xCD, 0xC9, 0xC9, 0xD7, 0x51, 0xF0, 0x40, 0xA2, 0x14, 0x01];
+
+    // 16 down to 1 bottles of beer, with codes of its own
+    const DYNAMIC: [u8; 74] = [
+        0x85, 0xD0, 0xBB, 0x09, 0x80, 0x40, 0x10, 0x45, 0xD1, 0xDC, 0x2A, 0x5E,
+        0x09, 0x8E, 0x7F, 0xCB, 0x71, 0x61, 0x16, 0x83, 0xC1, 0x05, 0x1D, 0xB0,
+        0x7D, 0x3B, 0xB8, 0xC6, 0x27, 0x3B, 0xB6, 0xA8, 0xB4, 0xCC, 0xF0, 0x47,
+        0xAD, 0xAA, 0xB8, 0xDF, 0x6A, 0x97, 0xF2, 0x74, 0xBD, 0x47, 0x44, 0x67,
+        0x33, 0xF3, 0xC4, 0x3C, 0x32, 0x0F, 0xCC, 0xC6, 0xDC, 0x23, 0xEF, 0xA8,
+        0x1B, 0xEA, 0x8A, 0xCA, 0x63, 0x1C, 0xC6, 0x5F, 0xDC, 0xF5, 0xB3, 0x85,
+        0xFA, 0x01,
+    ];
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(inflate(b"\x01\x05\x00\xFA\xFFhello").unwrap(), b"hello");
+        assert_eq!(inflate(b"\x01\x00\x00\xFF\xFF").unwrap(), b"");
+
+        // the length doesn't match its complement, and the data is cut short
+        assert!(inflate(b"\x01\x05\x00\xFA\xFEhello").is_err());
+        assert!(inflate(b"\x01\x05\x00\xFA\xFFhell").is_err());
+    }
+
+    #[test]
+    fn fixed_codes() {
+        assert_eq!(inflate(&FIXED).unwrap(), b"Hello, Hello, Hello!");
+    }
+
+    #[test]
+    fn dynamic_codes() {
+        let expected: String = (1..=16).rev().map(|n| format!("{} bottles of beer on the wall\n", n)).collect();
+        assert_eq!(inflate(&DYNAMIC).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn blocks_follow_each_other() {
+        // a stored block that isn't the last, then the fixed one
+        let mut data = b"\x00\x03\x00\xFC\xFFOh!".to_vec();
+        data.extend_from_slice(&FIXED);
+        assert_eq!(inflate(&data).unwrap(), b"Oh!Hello, Hello, Hello!");
+    }
+
+    #[test]
+    fn invalid_streams() {
+        // block type 3
+        assert_eq!(inflate(&[0x07]).unwrap_err().to_string(), "invalid deflate block type");
+
+        // a length and distance before any output
+        assert_eq!(inflate(&[0x03, 0x02]).unwrap_err().to_string(), "distance too far back");
+
+        assert!(inflate(&DYNAMIC[..40]).is_err());
+        assert!(inflate(&[]).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use crate::controller::{Controller, Macro};
+use crate::keyboard::Keyboard;
+use crate::powerpad::PowerPad;
+use crate::ppu::PPU;
+use crate::savestate::Savestate;
+use crate::vaus::Vaus;
+use crate::zapper::Zapper;
+
+// Where a device is plugged in
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Slot {
+    Port1,
+    Port2,
+    Expansion,
+}
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Device {
+    None,
+    Controller,
+    Zapper,
+    // Arkanoid paddles
+    Vaus,
+    VausFamicom,
+    // the exercise mat, NES and Famicom versions
+    PowerPad,
+    FamilyTrainer,
+    Keyboard,
+}
+
+impl Device {
+    pub fn parse(name: &str) -> Option<Device> {
+        match name {
+            "none" => Some(Device::None),
+            "controller" => Some(Device::Controller),
+            "zapper" => Some(Device::Zapper),
+            "vaus" => Some(Device::Vaus),
+            "vaus_famicom" => Some(Device::VausFamicom),
+            "powerpad" => Some(Device::PowerPad),
+            "family_trainer" => Some(Device::FamilyTrainer),
+            "keyboard" => Some(Device::Keyboard),
+            _ => None,
+        }
+    }
+
+    // the default expansion device, byte 15 of a NES 2.0 header,
+    // and where it goes. Standard controllers need no mention
+    pub fn from_nes2(device: u8) -> Option<(Slot, Device)> {
+        match device {
+            0x08 => Some((Slot::Port2, Device::Zapper)),
+            0x0B | 0x0C => Some((Slot::Port2, Device::PowerPad)),
+            0x0D | 0x0E => Some((Slot::Expansion, Device::FamilyTrainer)),
+            0x0F => Some((Slot::Port2, Device::Vaus)),
+            0x10 => Some((Slot::Expansion, Device::VausFamicom)),
+            0x23 => Some((Slot::Expansion, Device::Keyboard)),
+            _ => None,
+        }
+    }
+}
+
+// Adapters that connect two more controllers for four player games
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Adapter {
+    None,
+    // NES Four Score and NES Satellite, on the controller ports
+    FourScore,
+    // Hori 4 Players Adaptor in 4 player mode, on the Famicom expansion port
+    Hori,
+    // Famicom expansion port adapters without a signature
+    Simple,
+}
+
+impl Adapter {
+    pub fn parse(name: &str) -> Option<Adapter> {
+        match name {
+            "none" => Some(Adapter::None),
+            "fourscore" => Some(Adapter::FourScore),
+            "hori" => Some(Adapter::Hori),
+            "simple" => Some(Adapter::Simple),
+            _ => None,
+        }
+    }
+
+    // the default expansion device, byte 15 of a NES 2.0 header
+    pub fn from_nes2(device: u8) -> Option<Adapter> {
+        match device {
+            0x01 => Some(Adapter::None),
+            0x02 => Some(Adapter::FourScore),
+            0x03 => Some(Adapter::Simple),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Everything plugged into the controller ports and the expansion port.
+ * Each port is read through bits 0-4 of $4016 and $4017, and the expansion
+ * port through bits 1-4 of both.
+ *
+ * With an adapter, controllers 3 and 4 are read through the same ports:
+ *   Four Score, D0 of $4016/$4017: controller 1/2, controller 3/4,
+ *     then a signature on reads 17-24, 0,0,0,1,0,0,0,0 on $4016 and
+ *     0,0,1,0,0,0,0,0 on $4017, 24 reads in all
+ *   Hori, D1 of $4016/$4017: the same order with the signatures swapped.
+ *     Controllers 1 and 2 are also on D0, like the Famicom's own
+ *   Simple, D1 of $4016/$4017: controller 3/4, 8 reads
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Four_Score
+ * https://wiki.nesdev.org/w/index.php/Four_player_adapters
+ */
+pub struct Input {
+    pub controllers: [Controller; 4],
+    pub adapter: Adapter,
+
+    // what is in controller ports 1 and 2 and the Famicom expansion port
+    pub ports: [Device; 2],
+    pub expansion: Device,
+
+    pub zapper: Zapper,
+    pub vaus: Vaus,
+    pub power_pad: PowerPad,
+    pub keyboard: Keyboard,
+
+    // macros by name, and the macro and controller each key plays them on
+    pub macros: HashMap<String, Macro>,
+    pub bindings: HashMap<String, (String, usize)>,
+
+    // what the adapter shifts out of each port, LSB first
+    shift: [u32; 2],
+    strobe: bool,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            controllers: Default::default(),
+            adapter: Adapter::None,
+            ports: [Device::Controller; 2],
+            expansion: Device::None,
+            zapper: Zapper::default(),
+            vaus: Vaus::new(),
+            power_pad: PowerPad::default(),
+            keyboard: Keyboard::default(),
+            macros: HashMap::new(),
+            bindings: HashMap::new(),
+            shift: [0; 2],
+            strobe: false,
+        }
+    }
+
+    fn reload(&mut self) {
+        let signatures = match self.adapter {
+            Adapter::FourScore => [0x08, 0x04],
+            _ => [0x04, 0x08],
+        };
+
+        for (port, shift) in self.shift.iter_mut().enumerate() {
+            let first = self.controllers[port].state() as u32;
+            let second = self.controllers[port + 2].state() as u32;
+            *shift = match self.adapter {
+                Adapter::FourScore | Adapter::Hori => first | second << 8 | signatures[port] << 16 | 0xFF00_0000,
+                _ => second | 0xFFFF_FF00,
+            };
+        }
+    }
+
+    // false if there is no macro with that name
+    pub fn play_macro(&mut self, name: &str, port: usize) -> bool {
+        match self.macros.get(name) {
+            Some(macro_) => {
+                self.controllers[port].play(macro_.clone());
+                true
+            },
+            None => false,
+        }
+    }
+
+    // play the macro bound to `key`, false if there is none
+    pub fn press_key(&mut self, key: &str) -> bool {
+        match self.bindings.get(key).cloned() {
+            Some((name, port)) => self.play_macro(&name, port),
+            None => false,
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        for controller in self.controllers.iter_mut() {
+            controller.end_frame();
+        }
+    }
+
+    // $4016 write
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.reload();
+        }
+
+        for controller in self.controllers[..2].iter_mut() {
+            controller.write(value);
+        }
+        self.vaus.write(value);
+        self.power_pad.write(value);
+        self.keyboard.write(value);
+    }
+
+    // D0-D4 of a read of $4016 (port 0) or $4017 (port 1).
+    // Light guns look at what the PPU is drawing
+    pub fn read(&mut self, port: usize, ppu: &PPU) -> u8 {
+        let value = match self.ports[port] {
+            Device::Controller => self.read_controllers(port),
+            Device::Zapper => self.zapper.read(ppu),
+            Device::Vaus => self.vaus.read_nes(),
+            Device::PowerPad => self.power_pad.read_nes(),
+            _ => 0,
+        };
+
+        let expansion = match (self.expansion, port) {
+            (Device::VausFamicom, _) => self.vaus.read_famicom(port),
+            (Device::FamilyTrainer, 1) => self.power_pad.read_famicom(),
+            (Device::Keyboard, 1) => self.keyboard.read(),
+            _ => 0,
+        };
+
+        value | expansion
+    }
+
+    fn read_controllers(&mut self, port: usize) -> u8 {
+        if self.adapter == Adapter::None {
+            return self.controllers[port].read();
+        }
+
+        if self.strobe {
+            self.reload();
+        }
+        let bit = (self.shift[port] & 0x01) as u8;
+        self.shift[port] = 0x8000_0000 | self.shift[port] >> 1;
+
+        match self.adapter {
+            Adapter::FourScore => bit,
+            _ => bit << 1 | self.controllers[port].read(),
+        }
+    }
+
+    // power cycle: every shift register empties and the strobe is low.
+    // What is plugged in and held stays as it is
+    pub fn power(&mut self) {
+        for controller in self.controllers.iter_mut() {
+            controller.power();
+        }
+        self.vaus.power();
+        self.power_pad.power();
+        self.keyboard.power();
+        self.shift = [0; 2];
+        self.strobe = false;
+    }
+
+    // the shift registers of everything plugged in
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        for controller in self.controllers.iter_mut() {
+            controller.savestate(state);
+        }
+        self.vaus.savestate(state);
+        self.power_pad.savestate(state);
+        self.keyboard.savestate(state);
+        for shift in self.shift.iter_mut() {
+            state.u32(shift);
+        }
+        state.bool(&mut self.strobe);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adapter, Input};
+    use crate::controller::{Button, Macro};
+    use crate::ppu::PPU;
+
+    // strobe, then `count` reads of each port
+    fn read_ports(input: &mut Input, count: usize) -> [Vec<u8>; 2] {
+        let ppu = PPU::new();
+        input.write(0x01);
+        input.write(0x00);
+        [0, 1].map(|port| (0..count).map(|_| input.read(port, &ppu)).collect())
+    }
+
+    fn bits(reads: &[u8], bit: u8) -> u32 {
+        reads.iter().enumerate().fold(0, |value, (n, &read)| value | ((read >> bit) as u32 & 0x01) << n)
+    }
+
+    fn input(adapter: Adapter) -> Input {
+        let mut input = Input::new();
+        input.adapter = adapter;
+        for (n, controller) in input.controllers.iter_mut().enumerate() {
+            controller.buttons = 0x11 << n;
+        }
+        input
+    }
+
+    #[test]
+    fn four_score() {
+        let mut input = input(Adapter::FourScore);
+        let [port1, port2] = read_ports(&mut input, 32);
+
+        // controller 1/2, controller 3/4, the signature, then 1s
+        assert_eq!(bits(&port1, 0), 0x11 | 0x44 << 8 | 0x08 << 16 | 0xFF00_0000);
+        assert_eq!(bits(&port2, 0), 0x22 | 0x88 << 8 | 0x04 << 16 | 0xFF00_0000);
+        assert!(port1.iter().chain(port2.iter()).all(|&read| read & !0x01 == 0));
+    }
+
+    #[test]
+    fn hori() {
+        let mut input = input(Adapter::Hori);
+        let [port1, port2] = read_ports(&mut input, 32);
+
+        // the same order on D1 with the signatures swapped,
+        // and controllers 1 and 2 on D0 as well
+        assert_eq!(bits(&port1, 1), 0x11 | 0x44 << 8 | 0x04 << 16 | 0xFF00_0000);
+        assert_eq!(bits(&port2, 1), 0x22 | 0x88 << 8 | 0x08 << 16 | 0xFF00_0000);
+        assert_eq!(bits(&port1[..8], 0), 0x11);
+        assert_eq!(bits(&port2[..8], 0), 0x22);
+    }
+
+    #[test]
+    fn simple() {
+        let mut input = input(Adapter::Simple);
+        let [port1, port2] = read_ports(&mut input, 16);
+        assert_eq!(bits(&port1, 1), 0x44 | 0xFF00);
+        assert_eq!(bits(&port2, 1), 0x88 | 0xFF00);
+    }
+
+    #[test]
+    fn strobe_reloads() {
+        let mut input = input(Adapter::FourScore);
+        let ppu = PPU::new();
+        input.write(0x01);
+        for _ in 0..4 {
+            assert_eq!(input.read(0, &ppu), 0x01);
+        }
+    }
+
+    #[test]
+    fn bound_key_plays_macro() {
+        let mut input = Input::new();
+        input.macros.insert("jump".to_string(), Macro::parse("a*2").unwrap());
+        input.bindings.insert("f1".to_string(), ("jump".to_string(), 1));
+
+        assert!(!input.press_key("f2"));
+        assert!(input.press_key("f1"));
+        assert_eq!(input.controllers[0].state(), 0);
+        assert_eq!(input.controllers[1].state(), Button::A.bit());
+
+        // a binding to a macro that doesn't exist
+        input.bindings.insert("f3".to_string(), ("kick".to_string(), 0));
+        assert!(!input.press_key("f3"));
+    }
+}
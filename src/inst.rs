@@ -30,7 +30,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::IndX,       length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 3 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::ASL, mode: AddrMode::Zero,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 5 },
@@ -38,7 +38,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::ASL, mode: AddrMode::Imp,        length: 1, cycles: 2 }, // ACC
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Abs,        length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::Abs,        length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::ASL, mode: AddrMode::Abs,        length: 3, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
@@ -47,15 +47,15 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::IndY,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::ASL, mode: AddrMode::ZeroX,      length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::CLC, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::ORA, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::ASL, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
@@ -81,15 +81,15 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::AND, mode: AddrMode::IndY,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::AND, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::ROL, mode: AddrMode::ZeroX,      length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::SEC, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::AND, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::AND, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::ROL, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
@@ -98,7 +98,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::EOR, mode: AddrMode::IndX,       length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 3 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::EOR, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::LSR, mode: AddrMode::Zero,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 5 },
@@ -115,15 +115,15 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::EOR, mode: AddrMode::IndY,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::EOR, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::LSR, mode: AddrMode::ZeroX,      length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::CLI, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::EOR, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::EOR, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::LSR, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
@@ -132,7 +132,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::IndX,       length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 3 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::ROR, mode: AddrMode::Zero,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 5 },
@@ -142,36 +142,36 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::JMP, mode: AddrMode::Ind,        length: 3, cycles: 5 },
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::Abs,        length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::ROR, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
+    Inst { mnemonic: Mnemonic::ROR, mode: AddrMode::Abs,        length: 3, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
 
     Inst { mnemonic: Mnemonic::BVS, mode: AddrMode::Rel,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::IndY,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::ROR, mode: AddrMode::ZeroX,      length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::SEI, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::ADC, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::ROR, mode: AddrMode::Abs,        length: 3, cycles: 6 },
+    Inst { mnemonic: Mnemonic::ROR, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
 
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::STA, mode: AddrMode::IndX,       length: 2, cycles: 6 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::STY, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::STA, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::STX, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::DEY, mode: AddrMode::Imp,        length: 1, cycles: 2 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::TXA, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::STY, mode: AddrMode::Abs,        length: 3, cycles: 4 },
@@ -188,7 +188,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::STX, mode: AddrMode::ZeroY,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::TYA, mode: AddrMode::Imp,        length: 1, cycles: 2 },
-    Inst { mnemonic: Mnemonic::STA, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
+    Inst { mnemonic: Mnemonic::STA, mode: AddrMode::AbsY,       length: 3, cycles: 5 },
     Inst { mnemonic: Mnemonic::TXS, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 5 },
@@ -232,7 +232,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
 
     Inst { mnemonic: Mnemonic::CPY, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::CMP, mode: AddrMode::IndX,       length: 2, cycles: 6 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
     Inst { mnemonic: Mnemonic::CPY, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::CMP, mode: AddrMode::Zero,       length: 2, cycles: 3 },
@@ -251,22 +251,22 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::CMP, mode: AddrMode::IndY,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::CMP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::DEC, mode: AddrMode::ZeroX,      length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::CLD, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::CMP, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::CMP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::DEC, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
 
     Inst { mnemonic: Mnemonic::CPX, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::IndX,       length: 2, cycles: 6 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imm,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
     Inst { mnemonic: Mnemonic::CPX, mode: AddrMode::Zero,       length: 2, cycles: 3 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::Zero,       length: 2, cycles: 3 },
@@ -274,7 +274,7 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::INX, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::Imm,        length: 2, cycles: 2 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::CPX, mode: AddrMode::Abs,        length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::Abs,        length: 3, cycles: 4 },
@@ -285,15 +285,15 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::IndY,       length: 2, cycles: 5 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 8 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::ZeroX,      length: 2, cycles: 4 },
     Inst { mnemonic: Mnemonic::INC, mode: AddrMode::ZeroX,      length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 6 },
     Inst { mnemonic: Mnemonic::SED, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::AbsY,       length: 3, cycles: 4 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 2 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 1, cycles: 2 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
-    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::Imp,        length: 2, cycles: 4 },
+    Inst { mnemonic: Mnemonic::NOP, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::SBC, mode: AddrMode::AbsX,       length: 3, cycles: 4 },
     Inst { mnemonic: Mnemonic::INC, mode: AddrMode::AbsX,       length: 3, cycles: 7 },
     Inst { mnemonic: Mnemonic::XXX, mode: AddrMode::Imp,        length: 2, cycles: 7 },
@@ -302,7 +302,8 @@ pub static INSTRUCTIONS: [Inst; 0x100] = [
 // http://www.6502.org/tutorials/6502opcodes.html
 pub trait Inst6502 {
     // run operation
-    fn execute_op(&mut self);
+    // returns whether the operation may take an additional cycle
+    fn execute_op(&mut self) -> bool;
 
     fn jump_if(&mut self, pred: bool) -> bool;
 
@@ -310,9 +311,12 @@ pub trait Inst6502 {
     // whether or not the operation has a possibility
     // of an additional cycle
 
-    // bitwise AND with accumulator
+    // add with carry
     fn adc(&mut self) -> bool;
 
+    // bitwise AND with accumulator
+    fn and(&mut self) -> bool;
+
     // arithmetic shift left
     fn asl(&mut self) -> bool;
 
@@ -428,8 +432,67 @@ pub trait Inst6502 {
 }
 
 impl Inst6502 for CPU {
-    fn execute_op(&mut self) {
-        todo!()
+    fn execute_op(&mut self) -> bool {
+        let opcode = self.opcode as usize;
+        match INSTRUCTIONS[opcode].mnemonic {
+            Mnemonic::ADC => self.adc(),
+            Mnemonic::AND => self.and(),
+            Mnemonic::ASL => self.asl(),
+            Mnemonic::BCC => self.bcc(),
+            Mnemonic::BCS => self.bcs(),
+            Mnemonic::BEQ => self.beq(),
+            Mnemonic::BIT => self.bit(),
+            Mnemonic::BMI => self.bmi(),
+            Mnemonic::BNE => self.bne(),
+            Mnemonic::BPL => self.bpl(),
+            Mnemonic::BRK => self.brk(),
+            Mnemonic::BVC => self.bvc(),
+            Mnemonic::BVS => self.bvs(),
+            Mnemonic::CLC => self.clc(),
+            Mnemonic::CLD => self.cld(),
+            Mnemonic::CLI => self.cli(),
+            Mnemonic::CLV => self.clv(),
+            Mnemonic::CMP => self.cmp(),
+            Mnemonic::CPX => self.cpx(),
+            Mnemonic::CPY => self.cpy(),
+            Mnemonic::DEC => self.dec(),
+            Mnemonic::DEX => self.dex(),
+            Mnemonic::DEY => self.dey(),
+            Mnemonic::EOR => self.eor(),
+            Mnemonic::INC => self.inc(),
+            Mnemonic::INX => self.inx(),
+            Mnemonic::INY => self.iny(),
+            Mnemonic::JMP => self.jmp(),
+            Mnemonic::JSR => self.jsr(),
+            Mnemonic::LDA => self.lda(),
+            Mnemonic::LDX => self.ldx(),
+            Mnemonic::LDY => self.ldy(),
+            Mnemonic::LSR => self.lsr(),
+            Mnemonic::NOP => self.nop(),
+            Mnemonic::ORA => self.ora(),
+            Mnemonic::PHA => self.pha(),
+            Mnemonic::PHP => self.php(),
+            Mnemonic::PLA => self.pla(),
+            Mnemonic::PLP => self.plp(),
+            Mnemonic::ROL => self.rol(),
+            Mnemonic::ROR => self.ror(),
+            Mnemonic::RTI => self.rti(),
+            Mnemonic::RTS => self.rts(),
+            Mnemonic::SBC => self.sbc(),
+            Mnemonic::SEC => self.sec(),
+            Mnemonic::SED => self.sed(),
+            Mnemonic::SEI => self.sei(),
+            Mnemonic::STA => self.sta(),
+            Mnemonic::STX => self.stx(),
+            Mnemonic::STY => self.sty(),
+            Mnemonic::TAX => self.tax(),
+            Mnemonic::TAY => self.tay(),
+            Mnemonic::TSX => self.tsx(),
+            Mnemonic::TXA => self.txa(),
+            Mnemonic::TXS => self.txs(),
+            Mnemonic::TYA => self.tya(),
+            Mnemonic::XXX => self.xxx(),
+        }
     }
 
     fn jump_if(&mut self, pred: bool) -> bool {
@@ -438,7 +501,7 @@ impl Inst6502 for CPU {
             self.cycles += 1;
 
             // NOTE: jump_offset is set in the REL function
-            let addr = self.pc + self.jump_offset;
+            let addr = self.pc.wrapping_add(self.jump_offset);
 
             // +1 if jump to different page
             if (addr & 0xFF00) != (self.pc & 0xFF00) {
@@ -446,6 +509,7 @@ impl Inst6502 for CPU {
             }
 
             self.eff_addr = addr;
+            self.pc = addr;
         }
 
         false
@@ -466,7 +530,7 @@ impl Inst6502 for CPU {
 
         // http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
         self.flags.set(Flags::V, (!(acc ^ fetched) & (acc ^ sum) & 0x80) != 0);
-        self.flags.set(Flags::Z, sum == 0);
+        self.flags.set(Flags::Z, sum & 0x00FF == 0);
         self.flags.set(Flags::C, sum > 0xFF);
 
         self.acc = (sum & 0x00FF) as u8;
@@ -474,6 +538,18 @@ impl Inst6502 for CPU {
         true
     }
 
+    // AND: Bitwise AND with accumulator
+    // Affects: N Z
+    fn and(&mut self) -> bool {
+        self.fetch();
+
+        self.acc &= self.fetched;
+        self.flags.set(Flags::N, (self.acc & 0x80) != 0);
+        self.flags.set(Flags::Z, self.acc == 0);
+
+        true
+    }
+
     // ASL: Shift left one bit
     // Affects: N Z C
     fn asl(&mut self) -> bool {
@@ -502,9 +578,10 @@ impl Inst6502 for CPU {
 
         let and = self.acc & self.fetched;
 
+        // N and V are copied from memory, not from the result
         self.flags.set(Flags::Z, and == 0);
-        self.flags.set(Flags::N, and & (1 << 7) != 0);
-        self.flags.set(Flags::V, and & (1 << 6) != 0);
+        self.flags.set(Flags::N, self.fetched & (1 << 7) != 0);
+        self.flags.set(Flags::V, self.fetched & (1 << 6) != 0);
 
         false
     }
@@ -557,16 +634,20 @@ impl Inst6502 for CPU {
     // PC + 2
     // https://wiki.nesdev.org/w/index.php?title=CPU_interrupts
     fn brk(&mut self) -> bool {
-        self.pc += 1;
-        self.flags.set(Flags::I, true);
+        // skip the padding byte after the opcode
+        self.pc = self.pc.wrapping_add(1);
 
         self.push(((self.pc >> 8) & 0x00FF) as u8);
         self.push((self.pc & 0x00FF) as u8);
 
         // we're pushing the status flag in a S/W context
-        self.flags.set(Flags::B, true);
+        let flags = CpuFlags { brk: true, ignored: true, ..self.flags };
+        self.push(flags.to_byte());
+        self.flags.set(Flags::I, true);
 
-        todo!()
+        self.pc = self.read_word(0xFFFE);
+
+        false
     }
 
     // CMP: Compare memory with accumulator
@@ -616,11 +697,11 @@ impl Inst6502 for CPU {
     fn dec(&mut self) -> bool {
         self.fetch();
 
-        let sub = self.fetched as u16 - 1;
-        self.write(self.eff_addr, (sub & 0x00FF) as u8);
+        let sub = self.fetched.wrapping_sub(1);
+        self.write(self.eff_addr, sub);
 
-        self.flags.set(Flags::N, sub & 0x0080 != 0);
-        self.flags.set(Flags::Z, sub & 0x00FF == 0);
+        self.flags.set(Flags::N, sub & 0x80 != 0);
+        self.flags.set(Flags::Z, sub == 0);
 
         false
     }
@@ -630,10 +711,10 @@ impl Inst6502 for CPU {
     fn eor(&mut self) -> bool {
         self.fetch();
 
-        let xor = self.acc ^ self.fetched;
+        self.acc ^= self.fetched;
 
-        self.flags.set(Flags::N, xor & 0x80 != 0);
-        self.flags.set(Flags::Z, xor == 0);
+        self.flags.set(Flags::N, self.acc & 0x80 != 0);
+        self.flags.set(Flags::Z, self.acc == 0);
 
         true
     }
@@ -705,7 +786,7 @@ impl Inst6502 for CPU {
     // Affects: None
     fn jsr(&mut self) -> bool {
         // NOTE: we assume that sp points to the topmost empty space
-        let ret_addr = self.pc - 1;
+        let ret_addr = self.pc.wrapping_sub(1);
 
         self.push(((ret_addr >> 8) & 0xFF) as u8);
         self.push((ret_addr & 0xFF) as u8);
@@ -810,8 +891,8 @@ impl Inst6502 for CPU {
     fn tax(&mut self) -> bool {
         self.x = self.acc;
 
-        self.flags.set(Flags::N, self.x == 0);
-        self.flags.set(Flags::Z, (self.x & 0x80) != 0);
+        self.flags.set(Flags::N, (self.x & 0x80) != 0);
+        self.flags.set(Flags::Z, self.x == 0);
 
         false
     }
@@ -821,8 +902,8 @@ impl Inst6502 for CPU {
     fn txa(&mut self) -> bool {
         self.acc = self.x;
 
-        self.flags.set(Flags::N, self.acc == 0);
-        self.flags.set(Flags::Z, (self.acc & 0x80) != 0);
+        self.flags.set(Flags::N, (self.acc & 0x80) != 0);
+        self.flags.set(Flags::Z, self.acc == 0);
 
         false
     }
@@ -830,10 +911,10 @@ impl Inst6502 for CPU {
     // DEX: Decrement X
     // Affects: N Z
     fn dex(&mut self) -> bool {
-        self.x -= 1;
+        self.x = self.x.wrapping_sub(1);
 
-        self.flags.set(Flags::N, self.x == 0);
-        self.flags.set(Flags::Z, (self.x & 0x80) != 0);
+        self.flags.set(Flags::N, (self.x & 0x80) != 0);
+        self.flags.set(Flags::Z, self.x == 0);
 
         false
     }
@@ -841,10 +922,10 @@ impl Inst6502 for CPU {
     // INX: Increment X
     // Affects: N Z
     fn inx(&mut self) -> bool {
-        self.x += 1;
+        self.x = self.x.wrapping_add(1);
 
-        self.flags.set(Flags::N, self.x == 0);
-        self.flags.set(Flags::Z, (self.x & 0x80) != 0);
+        self.flags.set(Flags::N, (self.x & 0x80) != 0);
+        self.flags.set(Flags::Z, self.x == 0);
 
         false
     }
@@ -854,8 +935,8 @@ impl Inst6502 for CPU {
     fn tay(&mut self) -> bool {
         self.y = self.acc;
 
-        self.flags.set(Flags::N, self.y == 0);
-        self.flags.set(Flags::Z, (self.y & 0x80) != 0);
+        self.flags.set(Flags::N, (self.y & 0x80) != 0);
+        self.flags.set(Flags::Z, self.y == 0);
 
         false
     }
@@ -865,8 +946,8 @@ impl Inst6502 for CPU {
     fn tya(&mut self) -> bool {
         self.acc = self.y;
 
-        self.flags.set(Flags::N, self.acc == 0);
-        self.flags.set(Flags::Z, (self.acc & 0x80) != 0);
+        self.flags.set(Flags::N, (self.acc & 0x80) != 0);
+        self.flags.set(Flags::Z, self.acc == 0);
 
         false
     }
@@ -874,10 +955,10 @@ impl Inst6502 for CPU {
     // DEY: Decrement Y
     // Affects: N Z
     fn dey(&mut self) -> bool {
-        self.y -= 1;
+        self.y = self.y.wrapping_sub(1);
 
-        self.flags.set(Flags::N, self.y == 0);
-        self.flags.set(Flags::Z, (self.y & 0x80) != 0);
+        self.flags.set(Flags::N, (self.y & 0x80) != 0);
+        self.flags.set(Flags::Z, self.y == 0);
 
         false
     }
@@ -885,10 +966,10 @@ impl Inst6502 for CPU {
     // INY: Increment Y
     // Affects: N Z
     fn iny(&mut self) -> bool {
-        self.y += 1;
+        self.y = self.y.wrapping_add(1);
 
-        self.flags.set(Flags::N, self.y == 0);
-        self.flags.set(Flags::Z, (self.y & 0x80) != 0);
+        self.flags.set(Flags::N, (self.y & 0x80) != 0);
+        self.flags.set(Flags::Z, self.y == 0);
 
         false
     }
@@ -935,8 +1016,16 @@ impl Inst6502 for CPU {
         false
     }
 
+    // RTI: Return from interrupt
+    // Affects: All
     fn rti(&mut self) -> bool {
-        todo!()
+        self.plp();
+
+        let lo: u16 = self.pull().into();
+        let hi: u16 = self.pull().into();
+        self.pc = hi << 8 | lo;
+
+        false
     }
 
     // RTS: Return from subroutine
@@ -945,7 +1034,7 @@ impl Inst6502 for CPU {
         let lo: u16 = self.pull().into();
         let hi: u16 = self.pull().into();
 
-        self.pc = (hi << 8 | lo) + 1;
+        self.pc = (hi << 8 | lo).wrapping_add(1);
 
         false
     }
@@ -974,7 +1063,7 @@ impl Inst6502 for CPU {
 
         // http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
         self.flags.set(Flags::V, (!(acc ^ fetched) & (acc ^ sub) & 0x80) != 0);
-        self.flags.set(Flags::Z, sub == 0);
+        self.flags.set(Flags::Z, sub & 0x00FF == 0);
         self.flags.set(Flags::C, sub > 0xFF);
 
         self.acc = (sub & 0x00FF) as u8;
@@ -997,9 +1086,13 @@ impl Inst6502 for CPU {
     }
 
     // TSX: Transfer stack pointer to X
-    // Affects: None
+    // Affects: N Z
     fn tsx(&mut self) -> bool {
         self.x = self.sp;
+
+        self.flags.set(Flags::N, (self.x & 0x80) != 0);
+        self.flags.set(Flags::Z, self.x == 0);
+
         false
     }
 
@@ -1057,3 +1150,415 @@ impl Inst6502 for CPU {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::cpu::CPU;
+    use crate::region::Region;
+
+    // programs run from RAM at $0200, BRK and IRQs go to $0300
+    fn new_cpu(program: &[u8]) -> CPU {
+        let mut rom = b"NES\x1A\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        rom.resize(16 + 0x4000, 0);
+        rom[16 + 0x3FFE] = 0x00;
+        rom[16 + 0x3FFF] = 0x03;
+
+        let mut cpu = CPU::new(Cartridge::from_bytes(&rom).unwrap(), Region::Ntsc);
+        cpu.ram[0x200..0x200 + program.len()].copy_from_slice(program);
+        cpu.pc = 0x0200;
+        cpu.sp = 0xFD;
+        cpu
+    }
+
+    // run one instruction, returning how many cycles it took
+    fn step(cpu: &mut CPU) -> u8 {
+        let mut cycles = 0;
+        loop {
+            cpu.clock();
+            cycles += 1;
+            if cpu.cycles == 0 {
+                return cycles;
+            }
+        }
+    }
+
+    fn run(cpu: &mut CPU, instructions: usize) {
+        for _ in 0..instructions {
+            step(cpu);
+        }
+    }
+
+    #[test]
+    fn reset_leaves_sp_at_fd() {
+        let mut cpu = new_cpu(&[]);
+        cpu.sp = 0x00;
+        cpu.reset();
+        assert_eq!(cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn lda_addressing_modes() {
+        // (program, x, y, cycles)
+        let cases: [(&[u8], u8, u8, u8); 8] = [
+            (&[0xA9, 0x42], 0, 0, 2),             // #$42
+            (&[0xA5, 0x10], 0, 0, 3),             // $10
+            (&[0xB5, 0x0F], 1, 0, 4),             // $0F,X
+            (&[0xAD, 0x10, 0x00], 0, 0, 4),       // $0010
+            (&[0xBD, 0xFF, 0x00], 0x11, 0, 5),    // $00FF,X crossing into $0110
+            (&[0xB9, 0x0F, 0x00], 0, 1, 4),       // $000F,Y
+            (&[0xA1, 0x20], 4, 0, 6),             // ($20,X)
+            (&[0xB1, 0x22], 0, 0x20, 6),          // ($22),Y crossing into $0110
+        ];
+
+        for (program, x, y, cycles) in cases.iter() {
+            let mut cpu = new_cpu(program);
+            cpu.ram[0x10] = 0x42;
+            cpu.ram[0x110] = 0x42;
+            // pointers to $00F0 and $0110
+            cpu.ram[0x22..0x26].copy_from_slice(&[0xF0, 0x00, 0x10, 0x01]);
+            cpu.x = *x;
+            cpu.y = *y;
+
+            assert_eq!(step(&mut cpu), *cycles, "{:02X?}", program);
+            assert_eq!(cpu.acc, 0x42, "{:02X?}", program);
+            assert_eq!(cpu.pc as usize, 0x200 + program.len(), "{:02X?}", program);
+        }
+    }
+
+    #[test]
+    fn load_flags() {
+        let mut cpu = new_cpu(&[0xA9, 0x00, 0xA2, 0x80, 0xA0, 0x01]);
+        step(&mut cpu);
+        assert!(cpu.flags.zero && !cpu.flags.negative);
+        step(&mut cpu);
+        assert!(!cpu.flags.zero && cpu.flags.negative && cpu.x == 0x80);
+        step(&mut cpu);
+        assert!(!cpu.flags.zero && !cpu.flags.negative && cpu.y == 0x01);
+    }
+
+    #[test]
+    fn stores() {
+        // STA $10, STX $11, STY $0012, STA $0100,Y
+        let mut cpu = new_cpu(&[0x85, 0x10, 0x86, 0x11, 0x8C, 0x12, 0x00, 0x99, 0x00, 0x01]);
+        cpu.acc = 1;
+        cpu.x = 2;
+        cpu.y = 3;
+        run(&mut cpu, 3);
+        assert_eq!(&cpu.ram[0x10..0x13], &[1, 2, 3]);
+
+        // stores always take the page crossing cycle
+        assert_eq!(step(&mut cpu), 5);
+        assert_eq!(cpu.ram[0x103], 1);
+    }
+
+    #[test]
+    fn adc() {
+        // (a, operand, carry in, result, carry out, overflow)
+        let cases = [
+            (0x01, 0x01, false, 0x02, false, false),
+            (0x01, 0x01, true, 0x03, false, false),
+            (0x7F, 0x01, false, 0x80, false, true),
+            (0xFF, 0x01, false, 0x00, true, false),
+            (0x80, 0x80, false, 0x00, true, true),
+        ];
+
+        for &(a, operand, carry, result, carry_out, overflow) in &cases {
+            let mut cpu = new_cpu(&[0x69, operand]);
+            cpu.acc = a;
+            cpu.flags.carry = carry;
+            step(&mut cpu);
+            assert_eq!(cpu.acc, result);
+            assert_eq!(cpu.flags.carry, carry_out);
+            assert_eq!(cpu.flags.overflow, overflow);
+            assert_eq!(cpu.flags.zero, result == 0);
+            assert_eq!(cpu.flags.negative, result & 0x80 != 0);
+        }
+    }
+
+    #[test]
+    fn sbc() {
+        // (a, operand, carry in, result, carry out, overflow)
+        let cases = [
+            (0x05, 0x03, true, 0x02, true, false),
+            (0x05, 0x03, false, 0x01, true, false),
+            (0x03, 0x05, true, 0xFE, false, false),
+            (0x80, 0x01, true, 0x7F, true, true),
+            (0x05, 0x05, true, 0x00, true, false),
+        ];
+
+        for &(a, operand, carry, result, carry_out, overflow) in &cases {
+            let mut cpu = new_cpu(&[0xE9, operand]);
+            cpu.acc = a;
+            cpu.flags.carry = carry;
+            step(&mut cpu);
+            assert_eq!(cpu.acc, result);
+            assert_eq!(cpu.flags.carry, carry_out);
+            assert_eq!(cpu.flags.overflow, overflow);
+            assert_eq!(cpu.flags.zero, result == 0);
+        }
+    }
+
+    #[test]
+    fn logic() {
+        let mut cpu = new_cpu(&[0x29, 0x0F, 0x09, 0x80, 0x49, 0xFF]);
+        cpu.acc = 0x3C;
+        step(&mut cpu);
+        assert_eq!(cpu.acc, 0x0C);
+        step(&mut cpu);
+        assert_eq!(cpu.acc, 0x8C);
+        assert!(cpu.flags.negative);
+        step(&mut cpu);
+        assert_eq!(cpu.acc, 0x73);
+        assert!(!cpu.flags.negative);
+    }
+
+    #[test]
+    fn bit() {
+        let mut cpu = new_cpu(&[0x24, 0x10]);
+        cpu.ram[0x10] = 0xC0;
+        cpu.acc = 0x01;
+        step(&mut cpu);
+        assert!(cpu.flags.zero && cpu.flags.negative && cpu.flags.overflow);
+        assert_eq!(cpu.acc, 0x01);
+    }
+
+    #[test]
+    fn shifts_and_rotates() {
+        // ASL A, LSR $10, ROL A, ROR $0011
+        let mut cpu = new_cpu(&[0x0A, 0x46, 0x10, 0x2A, 0x6E, 0x11, 0x00]);
+        cpu.acc = 0x81;
+        cpu.ram[0x10] = 0x03;
+        cpu.ram[0x11] = 0x02;
+
+        step(&mut cpu);
+        assert_eq!(cpu.acc, 0x02);
+        assert!(cpu.flags.carry);
+
+        step(&mut cpu);
+        assert_eq!(cpu.ram[0x10], 0x01);
+        assert!(cpu.flags.carry);
+
+        step(&mut cpu);
+        assert_eq!(cpu.acc, 0x05);
+        assert!(!cpu.flags.carry);
+
+        cpu.flags.carry = true;
+        assert_eq!(step(&mut cpu), 6);
+        assert_eq!(cpu.ram[0x11], 0x81);
+        assert!(!cpu.flags.carry && cpu.flags.negative);
+    }
+
+    #[test]
+    fn ror_absolute_x() {
+        let mut cpu = new_cpu(&[0x7E, 0x10, 0x00]);
+        cpu.x = 1;
+        cpu.ram[0x11] = 0x01;
+        assert_eq!(step(&mut cpu), 7);
+        assert_eq!(cpu.ram[0x11], 0x00);
+        assert!(cpu.flags.carry && cpu.flags.zero);
+    }
+
+    #[test]
+    fn increments_wrap() {
+        // INC $10, DEC $11, INX, DEX, INY, DEY
+        let mut cpu = new_cpu(&[0xE6, 0x10, 0xC6, 0x11, 0xE8, 0xCA, 0xC8, 0x88]);
+        cpu.ram[0x10] = 0xFF;
+        cpu.ram[0x11] = 0x00;
+        cpu.x = 0xFF;
+        cpu.y = 0x00;
+
+        step(&mut cpu);
+        assert_eq!(cpu.ram[0x10], 0x00);
+        assert!(cpu.flags.zero);
+        step(&mut cpu);
+        assert_eq!(cpu.ram[0x11], 0xFF);
+        assert!(cpu.flags.negative);
+        step(&mut cpu);
+        assert_eq!(cpu.x, 0x00);
+        assert!(cpu.flags.zero && !cpu.flags.negative);
+        step(&mut cpu);
+        assert_eq!(cpu.x, 0xFF);
+        assert!(!cpu.flags.zero && cpu.flags.negative);
+        step(&mut cpu);
+        assert_eq!(cpu.y, 0x01);
+        step(&mut cpu);
+        assert_eq!(cpu.y, 0x00);
+        assert!(cpu.flags.zero);
+    }
+
+    #[test]
+    fn transfers() {
+        // TAX, TAY, TXA, TYA, TSX, TXS
+        let mut cpu = new_cpu(&[0xAA, 0xA8, 0x8A, 0x98, 0xBA, 0x9A]);
+        cpu.acc = 0x80;
+        step(&mut cpu);
+        assert!(cpu.x == 0x80 && cpu.flags.negative && !cpu.flags.zero);
+        step(&mut cpu);
+        assert_eq!(cpu.y, 0x80);
+
+        cpu.x = 0;
+        step(&mut cpu);
+        assert!(cpu.acc == 0 && cpu.flags.zero && !cpu.flags.negative);
+        step(&mut cpu);
+        assert_eq!(cpu.acc, 0x80);
+        step(&mut cpu);
+        assert!(cpu.x == 0xFD && cpu.flags.negative);
+
+        cpu.x = 0x00;
+        step(&mut cpu);
+        assert_eq!(cpu.sp, 0x00);
+        assert!(cpu.flags.negative, "TXS leaves the flags alone");
+    }
+
+    #[test]
+    fn compares() {
+        // CMP #$10, CPX #$10, CPY #$10
+        let mut cpu = new_cpu(&[0xC9, 0x10, 0xE0, 0x10, 0xC0, 0x10]);
+        cpu.acc = 0x10;
+        cpu.x = 0x20;
+        cpu.y = 0x05;
+        step(&mut cpu);
+        assert!(cpu.flags.zero && cpu.flags.carry);
+        step(&mut cpu);
+        assert!(!cpu.flags.zero && cpu.flags.carry);
+        step(&mut cpu);
+        assert!(!cpu.flags.zero && !cpu.flags.carry && cpu.flags.negative);
+    }
+
+    #[test]
+    fn branches() {
+        type Setup = fn(&mut CPU);
+
+        // (opcode, flag setup, taken)
+        let cases: [(u8, Setup, bool); 8] = [
+            (0x10, |cpu| cpu.flags.negative = false, true),
+            (0x30, |cpu| cpu.flags.negative = false, false),
+            (0x50, |cpu| cpu.flags.overflow = false, true),
+            (0x70, |cpu| cpu.flags.overflow = true, true),
+            (0x90, |cpu| cpu.flags.carry = true, false),
+            (0xB0, |cpu| cpu.flags.carry = true, true),
+            (0xD0, |cpu| cpu.flags.zero = true, false),
+            (0xF0, |cpu| cpu.flags.zero = true, true),
+        ];
+
+        for &(opcode, setup, taken) in &cases {
+            let mut cpu = new_cpu(&[opcode, 0x04]);
+            setup(&mut cpu);
+            let cycles = step(&mut cpu);
+            if taken {
+                assert_eq!((cpu.pc, cycles), (0x0206, 3), "{:02X}", opcode);
+            } else {
+                assert_eq!((cpu.pc, cycles), (0x0202, 2), "{:02X}", opcode);
+            }
+        }
+
+        // backwards into the previous page
+        let mut cpu = new_cpu(&[0xD0, 0xFC]);
+        cpu.flags.zero = false;
+        assert_eq!(step(&mut cpu), 4);
+        assert_eq!(cpu.pc, 0x01FE);
+    }
+
+    #[test]
+    fn flag_instructions() {
+        // SEC, SED, SEI, CLC, CLD, CLI, CLV
+        let mut cpu = new_cpu(&[0x38, 0xF8, 0x78, 0x18, 0xD8, 0x58, 0xB8]);
+        run(&mut cpu, 3);
+        assert!(cpu.flags.carry && cpu.flags.decimal && cpu.flags.interrupt);
+        cpu.flags.overflow = true;
+        run(&mut cpu, 4);
+        assert!(!cpu.flags.carry && !cpu.flags.decimal && !cpu.flags.interrupt && !cpu.flags.overflow);
+    }
+
+    #[test]
+    fn jumps() {
+        let mut cpu = new_cpu(&[0x4C, 0x34, 0x12]);
+        assert_eq!(step(&mut cpu), 3);
+        assert_eq!(cpu.pc, 0x1234);
+
+        // the high byte of an indirect JMP through $xxFF comes from $xx00
+        let mut cpu = new_cpu(&[0x6C, 0xFF, 0x00]);
+        cpu.ram[0xFF] = 0x34;
+        cpu.ram[0x00] = 0x12;
+        cpu.ram[0x100] = 0x56;
+        assert_eq!(step(&mut cpu), 5);
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn jsr_rts() {
+        // JSR $0210 ... $0210: RTS
+        let mut program = [0xEA; 0x11];
+        program[..3].copy_from_slice(&[0x20, 0x10, 0x02]);
+        program[0x10] = 0x60;
+        let mut cpu = new_cpu(&program);
+
+        assert_eq!(step(&mut cpu), 6);
+        assert_eq!(cpu.pc, 0x0210);
+        assert_eq!(cpu.sp, 0xFB);
+        assert_eq!(&cpu.ram[0x1FC..0x1FE], &[0x02, 0x02]);
+
+        assert_eq!(step(&mut cpu), 6);
+        assert_eq!(cpu.pc, 0x0203);
+        assert_eq!(cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn stack() {
+        // PHA, PHP, LDA #$00, PLP, PLA
+        let mut cpu = new_cpu(&[0x48, 0x08, 0xA9, 0x00, 0x28, 0x68]);
+        cpu.acc = 0x42;
+        cpu.flags.carry = true;
+        run(&mut cpu, 2);
+        assert_eq!(cpu.ram[0x1FD], 0x42);
+        // PHP pushes B and the unused bit set
+        assert_eq!(cpu.ram[0x1FC] & 0x31, 0x31);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.acc, 0x42);
+        assert!(cpu.flags.carry && !cpu.flags.brk);
+        assert_eq!(cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn stack_pointer_wraps() {
+        let mut cpu = new_cpu(&[0x48, 0x68]);
+        cpu.sp = 0x00;
+        cpu.acc = 0x42;
+        step(&mut cpu);
+        assert_eq!((cpu.sp, cpu.ram[0x100]), (0xFF, 0x42));
+        step(&mut cpu);
+        assert_eq!((cpu.sp, cpu.acc), (0x00, 0x42));
+    }
+
+    #[test]
+    fn brk_rti() {
+        let mut cpu = new_cpu(&[0x00, 0xFF]);
+        cpu.ram[0x300] = 0x40;
+        cpu.flags.interrupt = false;
+        cpu.flags.carry = true;
+
+        assert_eq!(step(&mut cpu), 7);
+        assert_eq!(cpu.pc, 0x0300);
+        assert!(cpu.flags.interrupt);
+        // return address skips the padding byte, B is set in the pushed status
+        assert_eq!(&cpu.ram[0x1FC..0x1FE], &[0x02, 0x02]);
+        assert_eq!(cpu.ram[0x1FB] & 0x31, 0x31);
+
+        assert_eq!(step(&mut cpu), 6);
+        assert_eq!(cpu.pc, 0x0202);
+        assert!(cpu.flags.carry && !cpu.flags.interrupt);
+    }
+
+    #[test]
+    fn unofficial_nops_skip_operands() {
+        // NOP, NOP #imm, NOP zp, NOP zp,X, NOP abs, NOP abs,X, NOP
+        let program = [0x1A, 0x80, 0xFF, 0x04, 0xFF, 0x14, 0xFF, 0x0C, 0xFF, 0xFF, 0x1C, 0xFF, 0x00, 0xEA];
+        let mut cpu = new_cpu(&program);
+        cpu.x = 1;
+        let cycles: Vec<u8> = (0..7).map(|_| step(&mut cpu)).collect();
+        assert_eq!(cycles, [2, 2, 3, 4, 4, 5, 2]);
+        assert_eq!(cpu.pc as usize, 0x200 + program.len());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::savestate::Savestate;
+
+// The key matrix, by row, with the 4 keys of column 0 and then
+// the 4 keys of column 1, each in the order of bits 1-4 of $4017
+const KEYS: [[&str; 8]; 9] = [
+    ["]", "[", "return", "f8", "stop", "yen", "rshift", "kana"],
+    [";", ":", "@", "f7", "^", "-", "/", "_"],
+    ["k", "l", "o", "f6", "0", "p", ",", "."],
+    ["j", "u", "i", "f5", "8", "9", "n", "m"],
+    ["h", "g", "y", "f4", "6", "7", "v", "b"],
+    ["d", "r", "t", "f3", "4", "5", "c", "f"],
+    ["a", "s", "w", "f2", "3", "e", "z", "x"],
+    ["ctr", "q", "esc", "f1", "2", "1", "grph", "lshift"],
+    ["left", "right", "up", "clr", "ins", "del", "space", "down"],
+];
+
+/**
+ * Family BASIC keyboard, on the Famicom expansion port. The keys are
+ * scanned 4 at a time: 9 rows, each split into two columns.
+ *
+ * $4016 write:
+ *   2: enable the keyboard
+ *   1: column select; going from 1 back to 0 moves on to the next row
+ *   0: reset to row 0, column 0
+ * $4017 read:
+ *   4-1: the 4 keys of the selected row and column, 0 while pressed.
+ *        All 0 while the keyboard is disabled
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Family_BASIC_Keyboard
+ */
+#[derive(Default)]
+pub struct Keyboard {
+    // bit n of row r is set while KEYS[r][n] is held
+    pub keys: [u8; 9],
+
+    enabled: bool,
+    row: usize,
+    column: u8,
+}
+
+impl Keyboard {
+    // the row and bit of a key, named as in KEYS
+    pub fn find_key(name: &str) -> Option<(usize, u8)> {
+        let name = name.to_ascii_lowercase();
+        KEYS.iter().enumerate().find_map(|(row, keys)| {
+            keys.iter().position(|&key| key == name).map(|bit| (row, bit as u8))
+        })
+    }
+
+    // false if there is no key with that name
+    pub fn set_key(&mut self, name: &str, pressed: bool) -> bool {
+        match Keyboard::find_key(name) {
+            Some((row, bit)) => {
+                if pressed {
+                    self.keys[row] |= 1 << bit;
+                } else {
+                    self.keys[row] &= !(1 << bit);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.enabled = value & 0x04 != 0;
+
+        let column = (value >> 1) & 0x01;
+        if self.column == 1 && column == 0 {
+            self.row += 1;
+        }
+        self.column = column;
+
+        if value & 0x01 != 0 {
+            self.row = 0;
+            self.column = 0;
+        }
+    }
+
+    // bits 1-4 of $4017
+    pub fn read(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let pressed = match self.keys.get(self.row) {
+            Some(keys) => keys >> (self.column * 4) & 0x0F,
+            None => 0,
+        };
+        !pressed << 1 & 0x1E
+    }
+
+    pub fn power(&mut self) {
+        self.enabled = false;
+        self.row = 0;
+        self.column = 0;
+    }
+
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.bool(&mut self.enabled);
+        state.usize(&mut self.row);
+        state.u8(&mut self.column);
+    }
+}
@@ -1,7 +1,315 @@
 mod cpu;
 mod inst;
 mod addr;
+mod apu;
+mod audio;
+mod cartridge;
+mod config;
+mod controller;
+mod database;
+mod dma;
+mod eeprom;
+mod flash;
+mod hash;
+mod import;
+mod inflate;
+mod input;
+mod keyboard;
+mod movie;
+mod nametable;
+mod nes;
+mod ntsc;
+mod palette;
+mod patch;
+mod powerpad;
+mod ppu;
+mod region;
+mod save;
+mod savestate;
+mod vaus;
+mod vs;
+mod wav;
+mod zapper;
+mod zip;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+use cartridge::{Cartridge, Console};
+use config::Config;
+use database::RomDatabase;
+use import::ImportedMovie;
+use input::{Adapter, Device, Slot};
+use movie::Movie;
+use nes::NES;
+use ntsc::{NtscFilter, NTSC_WIDTH};
+use palette::Palette;
+use ppu::{PpuModel, SCREEN_HEIGHT, SCREEN_WIDTH};
+use region::Region;
+use save::SaveFile;
+use vs::{VsProtection, VsSystem};
+
+fn stop_recording(nes: &mut NES, config: &Config) {
+    if let (Err(err), Some(path)) = (nes.stop_recording(), &config.record) {
+        eprintln!("failed to write {}: {}", path.display(), err);
+    }
+}
+
+fn write_ppm(path: &Path, width: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", width, SCREEN_HEIGHT)?;
+    file.write_all(rgb)?;
+    file.flush()
+}
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let rom_path = match &config.rom {
+        Some(path) => path.as_path(),
+        None => {
+            eprintln!("usage: nesrs <rom> [--config <file>] [--patch <ips|ups|bps>] [--frames <n>]");
+            eprintln!("             [--palette <name|file.pal>] [--filter <none|ntsc>] [--screenshot <file.ppm>]");
+            eprintln!("             [--region <auto|ntsc|pal|dendy>] [--database <file>] [--no-sprite-limit]");
+            eprintln!("             [--ppu <auto|2c0x[-nn]>] [--dip-switches <bits>] [--coins <frame,...>]");
+            eprintln!("             [--adapter <auto|none|fourscore|hori|simple>] ");
+            eprintln!("             [--turbo <a,b>] [--turbo-rate <frames>] [--macro-<name> <steps>]");
+            eprintln!("             [--bind-<key> <macro[:port]>] [--run-macro <frame:macro[:port],...>]");
+            eprintln!("             [--press-keys <frame:key,...>] [--press-mat <frame:button,...>]");
+            eprintln!("             [--movie-record <file.fm2>] [--movie-play <file.fm2|bk2|mmo>]");
+            eprintln!("             [--state-load <file>] [--state-save <file>]");
+            eprintln!("             [--port1 <device>] [--port2 <device>] [--expansion <device>]");
+            eprintln!("             devices: auto, none, controller, zapper, vaus, vaus_famicom, powerpad,");
+            eprintln!("                      family_trainer, keyboard");
+            eprintln!("             [--sample-rate <hz>] [--mute <channel,...>] [--solo <channel,...>]");
+            eprintln!("             [--volume-<pulse1|pulse2|triangle|noise|dmc> <gain>]");
+            eprintln!("             [--record <file.wav>] [--record-stems] [--record-start <frame>] [--record-stop <frame>]");
+            process::exit(1);
+        }
+    };
+
+    let cartridge = match Cartridge::load(rom_path, config.patch.as_deref()) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            eprintln!("failed to load {}: {}", rom_path.display(), err);
+            process::exit(1);
+        }
+    };
+
+    println!("mapper {}, {} KB PRG ROM, {} KB CHR", cartridge.mapper, cartridge.prg_rom.len() / 1024, cartridge.chr.len() / 1024);
+
+    let mut save = SaveFile::for_rom(rom_path);
+    let mut cartridge = cartridge;
+    if let Err(err) = save.load(&mut cartridge) {
+        eprintln!("failed to load {}: {}", save.path.display(), err);
+    }
+
+    // an explicit setting wins over the header, which wins over the database
+    let database = match &config.database {
+        Some(path) => RomDatabase::load(path).unwrap_or_else(|err| {
+            eprintln!("failed to load {}: {}", path.display(), err);
+            RomDatabase::default()
+        }),
+        None => RomDatabase::default(),
+    };
+    let info = database.lookup(cartridge.crc32()).cloned().unwrap_or_default();
+
+    let region = config.region.or(cartridge.region).or(info.region).unwrap_or(Region::Ntsc);
+
+    // arcade boards use an RGB PPU, the 2C03 unless told otherwise
+    let default_model = if cartridge.console == Console::Nes { PpuModel::Rp2C02 } else { PpuModel::Rp2C03 };
+    let ppu_model = config.ppu.or(cartridge.ppu_model).or(info.ppu).unwrap_or(default_model);
+
+    let palette_name = match config.palette.as_str() {
+        "auto" if ppu_model.is_rgb() => "2c03",
+        "auto" => "2c02",
+        name => name,
+    };
+    let palette = match Palette::select(palette_name, &config.ntsc) {
+        Ok(palette) => palette.for_ppu(ppu_model),
+        Err(err) => {
+            eprintln!("failed to load palette: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let vs = if cartridge.console == Console::VsSystem {
+        let protection = Some(cartridge.vs_protection)
+            .filter(|&protection| protection != VsProtection::None)
+            .or(info.protection)
+            .unwrap_or(VsProtection::None);
+        Some(VsSystem::new(protection, config.dip_switches))
+    } else {
+        None
+    };
+
+    let mut nes = NES::new(cartridge, region);
+    nes.cpu.ppu.sprite_limit = config.sprite_limit;
+    nes.cpu.ppu.model = ppu_model;
+    nes.cpu.vs = vs;
+    nes.cpu.input.adapter = config.adapter
+        .or_else(|| Adapter::from_nes2(nes.cpu.cartridge.input_device))
+        .or(info.adapter)
+        .unwrap_or(Adapter::None);
+
+    for controller in nes.cpu.input.controllers.iter_mut() {
+        controller.turbo = config.turbo;
+        controller.turbo_rate = config.turbo_rate;
+    }
+    for (name, macro_) in &config.macros {
+        nes.define_macro(name, macro_.clone());
+    }
+    for (key, name, port) in &config.bindings {
+        nes.bind_macro(key, name, *port);
+    }
+
+    let header_device = Device::from_nes2(nes.cpu.cartridge.input_device);
+    let device = |slot: Slot, configured: Option<Device>, known: Option<Device>, default: Device| {
+        configured
+            .or(header_device.filter(|&(header_slot, _)| header_slot == slot).map(|(_, device)| device))
+            .or(known)
+            .unwrap_or(default)
+    };
+    nes.cpu.input.ports = [
+        device(Slot::Port1, config.port1, info.port1, Device::Controller),
+        device(Slot::Port2, config.port2, info.port2, Device::Controller),
+    ];
+    nes.cpu.input.expansion = device(Slot::Expansion, config.expansion, info.expansion, Device::None);
+    nes.set_sample_rate(config.sample_rate);
+
+    let mixer = &mut nes.cpu.apu.mixer;
+    for &channel in &config.mute {
+        mixer.set_muted(channel, true);
+    }
+    for &channel in &config.solo {
+        mixer.set_solo(channel, true);
+    }
+    for &(channel, volume) in &config.volumes {
+        mixer.set_volume(channel, volume);
+    }
+
+    if let Some(path) = &config.state_load {
+        if let Err(err) = fs::read(path).and_then(|data| nes.load_state(&data)) {
+            eprintln!("failed to load {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(path) = &config.movie_play {
+        // BizHawk and Mesen movies are converted, with their own checks
+        let extension = path.extension().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+        let loaded = if extension == "bk2" || extension == "mmo" {
+            ImportedMovie::load(path).map(|imported| {
+                for warning in imported.check(&nes.cpu.cartridge, nes.region) {
+                    eprintln!("warning: {}: {}", path.display(), warning);
+                }
+                imported.movie
+            })
+        } else {
+            Movie::load(path).inspect(|movie| {
+                for warning in movie.check(&nes.cpu.cartridge, nes.region) {
+                    eprintln!("warning: {}: {}", path.display(), warning);
+                }
+            })
+        };
+
+        if let Err(err) = loaded.and_then(|movie| nes.play_movie(movie)) {
+            eprintln!("failed to load {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    } else if config.movie_record.is_some() {
+        let rom_name = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+        let movie = Movie::new(&rom_name, nes.cpu.cartridge.md5());
+        nes.record_movie(movie, config.state_load.is_some());
+    }
+
+    for frame in 0..config.frames {
+        if config.coins.contains(&frame) {
+            nes.insert_coin();
+        }
+
+        if let (Some(path), true) = (&config.record, frame == config.record_start) {
+            if let Err(err) = nes.start_recording(path, config.record_stems) {
+                eprintln!("failed to write {}: {}", path.display(), err);
+            }
+        }
+        for (_, name, port) in config.run_macros.iter().filter(|(at, _, _)| *at == frame) {
+            if !nes.play_macro(name, *port) {
+                eprintln!("unknown macro '{}'", name);
+            }
+        }
+
+        if config.record_stop == Some(frame) {
+            stop_recording(&mut nes, &config);
+        }
+
+        let keys: Vec<&str> = config.key_presses.iter().filter(|(at, _)| *at == frame).map(|(_, key)| key.as_str()).collect();
+        let buttons: Vec<u8> = config.mat_presses.iter().filter(|(at, _)| *at == frame).map(|&(_, button)| button).collect();
+        for &key in &keys {
+            // a key bound to a macro plays it, any other is a keyboard key
+            if !nes.press_key(key) && !nes.set_key(key, true) {
+                eprintln!("unknown key '{}'", key);
+            }
+        }
+        for &button in &buttons {
+            if !nes.set_mat_button(button, true) {
+                eprintln!("invalid mat button {}, expected 1 to 12", button);
+            }
+        }
+
+        nes.run_frame();
+
+        for &key in &keys {
+            nes.set_key(key, false);
+        }
+        for &button in &buttons {
+            nes.set_mat_button(button, false);
+        }
+        if let Err(err) = save.autosave(&mut nes.cpu.cartridge) {
+            eprintln!("failed to write {}: {}", save.path.display(), err);
+        }
+    }
+
+    stop_recording(&mut nes, &config);
+
+    if let (Some(path), Some(movie)) = (&config.movie_record, nes.stop_movie()) {
+        if let Err(err) = movie.save(path) {
+            eprintln!("failed to write {}: {}", path.display(), err);
+        }
+    }
+
+    if let Some(path) = &config.state_save {
+        if let Err(err) = fs::write(path, nes.save_state()) {
+            eprintln!("failed to write {}: {}", path.display(), err);
+        }
+    }
+
+    if let Some(path) = &config.screenshot {
+        let ppu = &nes.cpu.ppu;
+        let (width, rgb) = if config.filter == "ntsc" {
+            let filter = NtscFilter::new(config.ntsc_filter, config.ntsc);
+            (NTSC_WIDTH, filter.render(&ppu.framebuffer, ppu.frame))
+        } else {
+            (SCREEN_WIDTH, palette.to_rgb(&ppu.framebuffer))
+        };
+
+        if let Err(err) = write_ppm(path, width, &rgb) {
+            eprintln!("failed to write {}: {}", path.display(), err);
+        }
+    }
+
+    if let Err(err) = save.flush(&mut nes.cpu.cartridge) {
+        eprintln!("failed to write {}: {}", save.path.display(), err);
+    }
 }
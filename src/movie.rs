@@ -0,0 +1,402 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cartridge::Cartridge;
+use crate::hash;
+use crate::input::Device;
+use crate::region::Region;
+
+// commands in the first field of a frame, run before the frame
+pub const COMMAND_RESET: u8 = 0x01;
+pub const COMMAND_POWER: u8 = 0x02;
+pub const COMMAND_COIN: u8 = 0x10;
+
+// button order of a controller field, Right is bit 7 and A bit 0
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub controllers: [u8; 4],
+
+    // aim and trigger, when a Zapper is plugged in.
+    // None aims away from the screen
+    pub zapper: (Option<(u16, u16)>, bool),
+}
+
+/**
+ * Input recording in FCEUX's text movie format, FM2. A header of
+ * `key value` lines is followed by one line per frame:
+ *   |commands|port 0|port 1|port 2|
+ * e.g. |0|...T...A|........||
+ *
+ * Commands: 1 reset, 2 power, 16 insert coin.
+ * A controller is 8 characters RLDUTSBA, a space or . for each button up.
+ * A Zapper is "x y buttons 0 0". With the Four Score the line has four
+ * controllers and then port 2, the Famicom expansion port, which stays empty.
+ *
+ * A movie starts from power on, or from the savestate in its header.
+ * Savestates are this emulator's own, see `Savestate`, so movies that
+ * start from one can't be exchanged with FCEUX. Binary movies are refused.
+ *
+ * References:
+ * https://fceux.com/web/help/fm2.html
+ */
+#[derive(Debug,Clone)]
+pub struct Movie {
+    pub rerecord_count: u32,
+    pub pal: bool,
+    pub rom_filename: String,
+
+    // MD5 of the PRG and CHR ROM
+    pub rom_checksum: [u8; 16],
+
+    pub guid: String,
+    pub fourscore: bool,
+    pub ports: [Device; 2],
+
+    pub comments: Vec<String>,
+    pub subtitles: Vec<String>,
+
+    // the state the movie starts from, None to start from power on
+    pub savestate: Option<Vec<u8>>,
+
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_filename: &str, rom_checksum: [u8; 16]) -> Self {
+        Movie {
+            rerecord_count: 0,
+            pal: false,
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            guid: new_guid(),
+            fourscore: false,
+            ports: [Device::Controller, Device::Controller],
+            comments: Vec::new(),
+            subtitles: Vec::new(),
+            savestate: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = |number: usize, msg: String| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, msg));
+
+        let mut movie = Movie::new("", [0; 16]);
+        movie.guid.clear();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('|') {
+                let frame = movie.parse_frame(line).map_err(|msg| invalid(number, msg))?;
+                movie.frames.push(frame);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number_value = || value.trim().parse::<u32>().map_err(|_| invalid(number, format!("invalid {} '{}'", key, value)));
+            match key {
+                "version" if value.trim() != "3" => return Err(invalid(number, format!("unsupported version {}", value))),
+                "rerecordCount" => movie.rerecord_count = number_value()?,
+                "palFlag" => movie.pal = number_value()? != 0,
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let digest = value.trim().strip_prefix("base64:").and_then(base64_decode)
+                        .filter(|digest| digest.len() == 16)
+                        .ok_or_else(|| invalid(number, format!("invalid romChecksum '{}'", value)))?;
+                    movie.rom_checksum.copy_from_slice(&digest);
+                },
+                "guid" => movie.guid = value.trim().to_string(),
+                "fourscore" => movie.fourscore = number_value()? != 0,
+                "port0" | "port1" => {
+                    let port = if key == "port0" { 0 } else { 1 };
+                    movie.ports[port] = match number_value()? {
+                        0 => Device::None,
+                        1 => Device::Controller,
+                        2 => Device::Zapper,
+                        other => return Err(invalid(number, format!("unsupported {} device {}", key, other))),
+                    };
+                },
+                "port2" if number_value()? != 0 => return Err(invalid(number, "expansion port devices are not supported".to_string())),
+                "FDS" if number_value()? != 0 => return Err(invalid(number, "FDS movies are not supported".to_string())),
+                "binary" if number_value()? != 0 => return Err(invalid(number, "binary movies are not supported".to_string())),
+                "savestate" => {
+                    let state = value.trim().strip_prefix("base64:").and_then(base64_decode)
+                        .ok_or_else(|| invalid(number, "invalid savestate".to_string()))?;
+                    movie.savestate = Some(state);
+                },
+                "comment" => movie.comments.push(value.to_string()),
+                "subtitle" => movie.subtitles.push(value.to_string()),
+                // emuVersion, microphone, NewPPU, ...
+                _ => {},
+            }
+        }
+
+        Ok(movie)
+    }
+
+    fn parse_frame(&self, line: &str) -> std::result::Result<MovieFrame, String> {
+        let fields: Vec<&str> = line.split('|').collect();
+        let controllers = if self.fourscore { 4 } else { 2 };
+        // the empty strings before the first and after the last |
+        if fields.len() < controllers + 3 {
+            return Err(format!("expected {} fields, got '{}'", controllers + 2, line));
+        }
+
+        let mut frame = MovieFrame {
+            commands: fields[1].trim().parse().map_err(|_| format!("invalid commands '{}'", fields[1]))?,
+            ..MovieFrame::default()
+        };
+
+        for (index, field) in fields[2..2 + controllers].iter().enumerate() {
+            let device = if self.fourscore { Device::Controller } else { self.ports[index] };
+            match device {
+                Device::Controller => frame.controllers[index] = parse_buttons(field)?,
+                Device::Zapper => frame.zapper = parse_zapper(field)?,
+                _ => {},
+            }
+        }
+
+        Ok(frame)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Movie::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_fm2())
+    }
+
+    // warnings about differences between how the movie
+    // was recorded and how it is about to be played back
+    pub fn check(&self, cartridge: &Cartridge, region: Region) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.rom_checksum != cartridge.md5() {
+            warnings.push("the movie was recorded with a different ROM".to_string());
+        }
+        if self.pal != (region != Region::Ntsc) {
+            let recorded = if self.pal { "PAL" } else { "NTSC" };
+            warnings.push(format!("the movie was recorded on {}, but the console is {:?}", recorded, region));
+        }
+
+        warnings
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::new();
+        let port = |device: Device| match device {
+            Device::Controller => 1,
+            Device::Zapper => 2,
+            _ => 0,
+        };
+
+        text += "version 3\n";
+        text += "emuVersion 22020\n";
+        text += &format!("rerecordCount {}\n", self.rerecord_count);
+        text += &format!("palFlag {}\n", self.pal as u8);
+        text += &format!("romFilename {}\n", self.rom_filename);
+        text += &format!("romChecksum base64:{}\n", base64_encode(&self.rom_checksum));
+        text += &format!("guid {}\n", self.guid);
+        text += &format!("fourscore {}\n", self.fourscore as u8);
+        text += "microphone 0\n";
+        text += &format!("port0 {}\n", if self.fourscore { 1 } else { port(self.ports[0]) });
+        text += &format!("port1 {}\n", if self.fourscore { 1 } else { port(self.ports[1]) });
+        text += "port2 0\n";
+        text += "FDS 0\n";
+        text += "NewPPU 0\n";
+        if let Some(state) = &self.savestate {
+            text += &format!("savestate base64:{}\n", base64_encode(state));
+        }
+        for comment in &self.comments {
+            text += &format!("comment {}\n", comment);
+        }
+        for subtitle in &self.subtitles {
+            text += &format!("subtitle {}\n", subtitle);
+        }
+
+        for frame in &self.frames {
+            text += &format!("|{}|", frame.commands);
+            if self.fourscore {
+                for &buttons in &frame.controllers {
+                    text += &format_buttons(buttons);
+                    text += "|";
+                }
+            } else {
+                for (index, &device) in self.ports.iter().enumerate() {
+                    match device {
+                        Device::Controller => text += &format_buttons(frame.controllers[index]),
+                        Device::Zapper => text += &format_zapper(frame.zapper),
+                        _ => {},
+                    }
+                    text += "|";
+                }
+            }
+            text += "|\n";
+        }
+
+        text
+    }
+}
+
+fn parse_buttons(field: &str) -> std::result::Result<u8, String> {
+    if field.len() != 8 {
+        return Err(format!("invalid controller '{}'", field));
+    }
+    Ok(field.bytes().fold(0, |buttons, c| buttons << 1 | (c != b'.' && c != b' ') as u8))
+}
+
+fn format_buttons(buttons: u8) -> String {
+    BUTTONS.iter().enumerate()
+        .map(|(n, &c)| if buttons & (0x80 >> n) != 0 { c as char } else { '.' })
+        .collect()
+}
+
+fn parse_zapper(field: &str) -> std::result::Result<(Option<(u16, u16)>, bool), String> {
+    let values: Vec<u16> = field.split_whitespace()
+        .map(|value| value.parse().map_err(|_| format!("invalid zapper '{}'", field)))
+        .collect::<std::result::Result<_, _>>()?;
+    if values.len() < 3 {
+        return Err(format!("invalid zapper '{}'", field));
+    }
+
+    let aim = Some((values[0], values[1])).filter(|&(x, y)| x < 256 && y < 240);
+    Ok((aim, values[2] & 0x01 != 0))
+}
+
+fn format_zapper((aim, trigger): (Option<(u16, u16)>, bool)) -> String {
+    let (x, y) = aim.unwrap_or((256, 240));
+    format!("{} {} {} 0 0", x, y, trigger as u8)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for n in 0..4 {
+            if n <= chunk.len() {
+                text.push(BASE64[(value >> (18 - 6 * n) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut value = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|&c| c != b'=') {
+        value = value << 6 | BASE64.iter().position(|&digit| digit == c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((value >> bits) as u8);
+        }
+    }
+    Some(data)
+}
+
+// a GUID that only has to be unique enough to tell recordings apart
+fn new_guid() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0);
+    let digest = hash::md5(&nanos.to_le_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, COMMAND_POWER, COMMAND_RESET};
+    use crate::controller::Button;
+    use crate::input::Device;
+
+    // in the order `to_fm2` writes it, so writing the movie gives it back
+    const FM2: &str = "version 3
+emuVersion 22020
+rerecordCount 7
+palFlag 0
+romFilename game
+romChecksum base64:ASNFZ4mrze/+3LqYdlQyEA==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+microphone 0
+port0 1
+port1 2
+port2 0
+FDS 0
+NewPPU 0
+savestate base64:TkVTUlNTVAFzdGF0ZQ==
+comment author someone
+subtitle 10 hello
+|0|R..U...A|128 96 1 0 0||
+|1|........|256 240 0 0 0||
+|2|.L..TSB.|10 20 0 0 0||
+";
+
+    #[test]
+    fn fm2_round_trip() {
+        let movie = Movie::parse(FM2).unwrap();
+        assert_eq!(movie.rerecord_count, 7);
+        assert!(!movie.pal);
+        assert_eq!(movie.rom_filename, "game");
+        assert_eq!(movie.rom_checksum, [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF,
+            0xFE, 0xDC, 0xBA, 0x98, 0x76, 0x54, 0x32, 0x10,
+        ]);
+        assert_eq!(movie.guid, "452DE2C3-EF43-2FA9-77AC-0677FC51543B");
+        assert_eq!(movie.ports, [Device::Controller, Device::Zapper]);
+        assert_eq!(movie.savestate.as_deref(), Some(&b"NESRSST\x01state"[..]));
+        assert_eq!(movie.comments, ["author someone"]);
+        assert_eq!(movie.subtitles, ["10 hello"]);
+
+        let frames = &movie.frames;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].controllers[0], Button::Right.bit() | Button::Up.bit() | Button::A.bit());
+        assert_eq!(frames[0].zapper, (Some((128, 96)), true));
+        assert_eq!(frames[1].commands, COMMAND_RESET);
+        assert_eq!(frames[1].controllers[0], 0);
+        assert_eq!(frames[1].zapper, (None, false));
+        assert_eq!(frames[2].commands, COMMAND_POWER);
+        assert_eq!(frames[2].controllers[0], Button::Left.bit() | Button::Start.bit() | Button::Select.bit() | Button::B.bit());
+        assert_eq!(frames[2].zapper, (Some((10, 20)), false));
+
+        assert_eq!(movie.to_fm2(), FM2);
+    }
+
+    #[test]
+    fn fm2_four_score() {
+        let text = "version 3\nfourscore 1\n|0|.......A|......B.|R.......|...U....||\n";
+        let movie = Movie::parse(text).unwrap();
+        assert!(movie.fourscore);
+        assert_eq!(movie.frames[0].controllers, [0x01, 0x02, 0x80, 0x10]);
+        assert!(movie.to_fm2().ends_with("\n|0|.......A|......B.|R.......|...U....||\n"));
+        assert!(movie.savestate.is_none());
+        assert!(!movie.to_fm2().contains("savestate"));
+    }
+
+    #[test]
+    fn fm2_errors() {
+        assert!(Movie::parse("version 2\n").is_err());
+        assert!(Movie::parse("romChecksum base64:AAAA\n").is_err());
+        assert!(Movie::parse("savestate 1234\n").is_err());
+        assert!(Movie::parse("binary 1\n").is_err());
+        assert!(Movie::parse("|0|........|\n").is_err());
+        assert!(Movie::parse("|x|........|........||\n").is_err());
+        assert!(Movie::parse("port1 2\n|0|........|12 x 0 0 0||\n").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::cartridge::Cartridge;
+
+// 1KB of memory that one of the four nametables at $2000, $2400,
+// $2800 and $2C00 is mapped to
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum NametablePage {
+    // the two halves of the 2KB CIRAM inside the console
+    CiramA,
+    CiramB,
+    // a page of RAM (or ROM) supplied by the cartridge
+    Cartridge(u8),
+}
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Mirroring {
+    // $2000 = $2400, $2800 = $2C00 (vertical arrangement, vertical scrolling games)
+    Horizontal,
+    // $2000 = $2800, $2400 = $2C00 (horizontal arrangement, horizontal scrolling games)
+    Vertical,
+    // all four nametables show the same page, selected by
+    // mappers such as Bandai FCG and UNROM 512
+    SingleScreenA,
+    SingleScreenB,
+    // the cartridge supplies 2KB more so every nametable is unique
+    FourScreen,
+}
+
+impl Mirroring {
+    // pages mapped to $2000, $2400, $2800 and $2C00
+    pub fn pages(self) -> [NametablePage; 4] {
+        use NametablePage::*;
+
+        match self {
+            Mirroring::Horizontal => [CiramA, CiramA, CiramB, CiramB],
+            Mirroring::Vertical => [CiramA, CiramB, CiramA, CiramB],
+            Mirroring::SingleScreenA => [CiramA; 4],
+            Mirroring::SingleScreenB => [CiramB; 4],
+            Mirroring::FourScreen => [CiramA, CiramB, Cartridge(0), Cartridge(1)],
+        }
+    }
+
+    // the page and the offset within it that a $2000-$3EFF address refers to
+    pub fn translate(self, addr: u16) -> (NametablePage, usize) {
+        let addr = (addr & 0x0FFF) as usize;
+        (self.pages()[addr / 0x400], addr % 0x400)
+    }
+}
+
+/**
+ * $2000-$23FF: Nametable 0
+ * $2400-$27FF: Nametable 1
+ * $2800-$2BFF: Nametable 2
+ * $2C00-$2FFF: Nametable 3
+ * $3000-$3EFF: Mirrors of $2000-$2EFF
+ *
+ * The console only has 2KB for nametables, so the cartridge decides which
+ * nametables share memory, and can change that at any time by changing
+ * `Cartridge::mirroring`.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Mirroring#Nametable_Mirroring
+ */
+pub struct Nametables {
+    pub ciram: [u8; 0x800],
+}
+
+impl Nametables {
+    pub fn new() -> Self {
+        Nametables {
+            ciram: [0; 0x800],
+        }
+    }
+
+    pub fn read(&self, addr: u16, cartridge: &mut Cartridge) -> u8 {
+        match cartridge.mirroring.translate(addr) {
+            (NametablePage::CiramA, offset) => self.ciram[offset],
+            (NametablePage::CiramB, offset) => self.ciram[0x400 + offset],
+            (NametablePage::Cartridge(page), offset) => cartridge.nametable_read(page, offset),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8, cartridge: &mut Cartridge) {
+        match cartridge.mirroring.translate(addr) {
+            (NametablePage::CiramA, offset) => self.ciram[offset] = value,
+            (NametablePage::CiramB, offset) => self.ciram[0x400 + offset] = value,
+            (NametablePage::Cartridge(page), offset) => cartridge.nametable_write(page, offset, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use NametablePage::*;
+
+    fn check(mirroring: Mirroring, expected: &[(u16, NametablePage, usize)]) {
+        for &(addr, page, offset) in expected {
+            assert_eq!(mirroring.translate(addr), (page, offset), "{:?} ${:04X}", mirroring, addr);
+        }
+    }
+
+    #[test]
+    fn horizontal() {
+        check(Mirroring::Horizontal, &[
+            (0x2000, CiramA, 0x000),
+            (0x2400, CiramA, 0x000),
+            (0x27FF, CiramA, 0x3FF),
+            (0x2800, CiramB, 0x000),
+            (0x2C00, CiramB, 0x000),
+            (0x2FFF, CiramB, 0x3FF),
+        ]);
+    }
+
+    #[test]
+    fn vertical() {
+        check(Mirroring::Vertical, &[
+            (0x2000, CiramA, 0x000),
+            (0x2400, CiramB, 0x000),
+            (0x2800, CiramA, 0x000),
+            (0x2BFF, CiramA, 0x3FF),
+            (0x2C00, CiramB, 0x000),
+            (0x2FFF, CiramB, 0x3FF),
+        ]);
+    }
+
+    #[test]
+    fn single_screen() {
+        for addr in [0x2000, 0x2400, 0x2800, 0x2C00] {
+            check(Mirroring::SingleScreenA, &[(addr + 0x123, CiramA, 0x123)]);
+            check(Mirroring::SingleScreenB, &[(addr + 0x123, CiramB, 0x123)]);
+        }
+    }
+
+    #[test]
+    fn four_screen() {
+        check(Mirroring::FourScreen, &[
+            (0x2000, CiramA, 0x000),
+            (0x2400, CiramB, 0x000),
+            (0x2800, Cartridge(0), 0x000),
+            (0x2C00, Cartridge(1), 0x000),
+            (0x2FFF, Cartridge(1), 0x3FF),
+        ]);
+    }
+
+    #[test]
+    fn mirrors_above_3000() {
+        check(Mirroring::Vertical, &[
+            (0x3000, CiramA, 0x000),
+            (0x3400, CiramB, 0x000),
+            (0x3EFF, CiramB, 0x2FF),
+        ]);
+    }
+}
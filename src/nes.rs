@@ -0,0 +1,539 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::audio::{self, AudioOutput};
+use crate::cartridge::Cartridge;
+use crate::controller::{Button, Macro};
+use crate::cpu::{CpuFlags, CPU};
+use crate::dma::DmaState;
+use crate::input::{Adapter, Device};
+use crate::movie::{Movie, MovieFrame, COMMAND_COIN, COMMAND_POWER, COMMAND_RESET};
+use crate::ppu::PPU;
+use crate::region::Region;
+use crate::savestate::Savestate;
+use crate::wav::AudioRecorder;
+
+// The console as a whole. The CPU owns everything on its bus,
+// so this only has to keep the chips in step with each other.
+pub struct NES {
+    pub cpu: CPU,
+
+    pub region: Region,
+
+    // counts PPU dots since power on
+    pub system_clock: u64,
+
+    // master clocks left until the next CPU cycle
+    cpu_countdown: u8,
+
+    // WAV recording of the audio, if one is running
+    pub recorder: Option<AudioRecorder>,
+
+    // the input movie being recorded or played back
+    pub movie: Option<Movie>,
+    movie_playing: bool,
+    movie_frame: usize,
+
+    // resets and coins since the last frame, for the movie being recorded
+    movie_commands: u8,
+}
+
+impl NES {
+    pub fn new(cartridge: Cartridge, region: Region) -> Self {
+        let mut cpu = CPU::new(cartridge, region);
+        cpu.reset();
+
+        NES {
+            cpu,
+            region,
+            system_clock: 0,
+            cpu_countdown: 0,
+            recorder: None,
+            movie: None,
+            movie_playing: false,
+            movie_frame: 0,
+            movie_commands: 0,
+        }
+    }
+
+    // One PPU dot. Both chips divide the same master clock, the CPU by 12
+    // and the PPU by 4 on NTSC, so the CPU runs every 3rd dot there and
+    // 5 times in every 16 dots on PAL.
+    pub fn clock(&mut self) {
+        self.cpu.ppu.clock(&mut self.cpu.cartridge);
+
+        let ppu_divider = self.region.ppu_divider();
+        if self.cpu_countdown < ppu_divider {
+            self.cpu.clock();
+            self.cpu_countdown += self.region.cpu_divider();
+        }
+        self.cpu_countdown -= ppu_divider;
+
+        self.system_clock += 1;
+    }
+
+    pub fn run_frame(&mut self) {
+        self.movie_input();
+
+        while !self.cpu.ppu.frame_complete {
+            self.clock();
+        }
+        self.cpu.ppu.frame_complete = false;
+        self.cpu.apu.end_frame();
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_frame(&self.cpu.apu);
+        }
+
+        if let Some(vs) = &mut self.cpu.vs {
+            vs.end_frame();
+        }
+        self.cpu.input.end_frame();
+    }
+
+    // The reset button. The CPU jumps through the reset vector and the
+    // APU goes silent, RAM and the cartridge keep their contents
+    pub fn reset(&mut self) {
+        self.cpu.apu.cpu_write(0x4015, 0x00);
+        self.cpu.reset();
+        self.movie_commands |= COMMAND_RESET;
+    }
+
+    // Turn the console off and on again. Battery-backed saves survive
+    pub fn power(&mut self) {
+        let cpu = &mut self.cpu;
+        cpu.ram = [0; 0x800];
+        cpu.acc = 0;
+        cpu.x = 0;
+        cpu.y = 0;
+        cpu.sp = 0x00;
+        cpu.flags = CpuFlags { interrupt: true, ignored: true, ..CpuFlags::empty() };
+        cpu.cycle_count = 0;
+        cpu.dma = DmaState::default();
+        cpu.cartridge.power();
+        cpu.input.power();
+
+        let mut ppu = PPU::new();
+        ppu.region = cpu.ppu.region;
+        ppu.model = cpu.ppu.model;
+        ppu.sprite_limit = cpu.ppu.sprite_limit;
+        cpu.ppu = ppu;
+
+        cpu.apu.power();
+        cpu.reset();
+        self.system_clock = 0;
+        self.cpu_countdown = 0;
+        self.movie_commands |= COMMAND_POWER;
+    }
+
+    // Vs. System coin slot 1
+    pub fn insert_coin(&mut self) {
+        if let Some(vs) = &mut self.cpu.vs {
+            vs.insert_coin(0);
+            self.movie_commands |= COMMAND_COIN;
+        }
+    }
+
+    // A savestate of the whole console, see `Savestate`
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut state = Savestate::save();
+        // only loading checks anything
+        let _ = self.savestate(&mut state);
+        state.finish().unwrap_or_default()
+    }
+
+    // Put the console back to a state from `save_state`. A state
+    // of another game, or that doesn't fit, changes nothing
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        Savestate::load(data.to_vec()).header(self.cpu.cartridge.md5())?;
+        if data.len() != self.save_state().len() {
+            return Err(Error::new(ErrorKind::InvalidData, "the savestate doesn't match this console"));
+        }
+
+        let mut state = Savestate::load(data.to_vec());
+        self.savestate(&mut state)?;
+        state.finish()?;
+        Ok(())
+    }
+
+    fn savestate(&mut self, state: &mut Savestate) -> Result<()> {
+        state.header(self.cpu.cartridge.md5())?;
+        state.u64(&mut self.system_clock);
+        state.u8(&mut self.cpu_countdown);
+        self.cpu.savestate(state);
+        Ok(())
+    }
+
+    // Record the input of every frame into `movie`, whose frames are
+    // replaced. The console is power cycled first, or with `from_savestate`
+    // the movie starts from the console as it is, saved in the movie
+    pub fn record_movie(&mut self, mut movie: Movie, from_savestate: bool) {
+        let input = &self.cpu.input;
+        movie.frames.clear();
+        movie.pal = self.region != Region::Ntsc;
+        movie.fourscore = input.adapter == Adapter::FourScore;
+        movie.ports = [0, 1].map(|port| match input.ports[port] {
+            Device::Controller | Device::Zapper => input.ports[port],
+            _ => Device::None,
+        });
+
+        if from_savestate {
+            movie.savestate = Some(self.save_state());
+        } else {
+            movie.savestate = None;
+            self.power();
+        }
+        self.movie = Some(movie);
+        self.movie_playing = false;
+        self.movie_commands = 0;
+    }
+
+    // Power cycle, or load the savestate the movie starts from, and play
+    // `movie` back. Its input replaces that of the controllers and the
+    // Zapper until the movie ends
+    pub fn play_movie(&mut self, movie: Movie) -> Result<()> {
+        if let Some(state) = &movie.savestate {
+            self.load_state(state)?;
+        }
+
+        let input = &mut self.cpu.input;
+        if movie.fourscore {
+            input.adapter = Adapter::FourScore;
+            input.ports = [Device::Controller; 2];
+        } else {
+            input.adapter = Adapter::None;
+            input.ports = movie.ports;
+        }
+
+        if movie.savestate.is_none() {
+            self.power();
+        }
+        self.movie = Some(movie);
+        self.movie_playing = true;
+        self.movie_frame = 0;
+        Ok(())
+    }
+
+    // true once a movie being played back has run out of frames
+    pub fn movie_finished(&self) -> bool {
+        match &self.movie {
+            Some(movie) => self.movie_playing && self.movie_frame >= movie.frames.len(),
+            None => false,
+        }
+    }
+
+    // stop recording or playing back, returning the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take()
+    }
+
+    // at the start of each frame, apply or capture its input
+    fn movie_input(&mut self) {
+        let commands = std::mem::take(&mut self.movie_commands);
+        if self.movie.is_none() {
+            return;
+        }
+
+        if !self.movie_playing {
+            let input = &self.cpu.input;
+            let mut frame = MovieFrame {
+                commands,
+                controllers: [0; 4],
+                zapper: (input.zapper.aim, input.zapper.trigger),
+            };
+            for (buttons, controller) in frame.controllers.iter_mut().zip(input.controllers.iter()) {
+                *buttons = controller.state();
+            }
+            if let Some(movie) = &mut self.movie {
+                movie.frames.push(frame);
+            }
+            return;
+        }
+
+        let frame = match self.movie.as_ref().and_then(|movie| movie.frames.get(self.movie_frame)) {
+            Some(&frame) => frame,
+            None => return,
+        };
+        self.movie_frame += 1;
+
+        if frame.commands & COMMAND_POWER != 0 {
+            self.power();
+        }
+        if frame.commands & COMMAND_RESET != 0 {
+            self.reset();
+        }
+        if frame.commands & COMMAND_COIN != 0 {
+            self.insert_coin();
+        }
+        self.movie_commands = 0;
+
+        let input = &mut self.cpu.input;
+        for (controller, &buttons) in input.controllers.iter_mut().zip(frame.controllers.iter()) {
+            controller.buttons = buttons;
+            controller.turbo = 0;
+            controller.stop();
+        }
+        input.zapper.aim = frame.zapper.0;
+        input.zapper.trigger = frame.zapper.1;
+    }
+
+    // The buttons held on controller `port` (0 to 3) from now on,
+    // e.g. Button::A.bit() | Button::Right.bit(). Games read the controllers
+    // once a frame, so this is usually set before each `run_frame`
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.cpu.input.controllers[port].buttons = buttons;
+    }
+
+    pub fn set_button(&mut self, port: usize, button: Button, pressed: bool) {
+        self.cpu.input.controllers[port].set_button(button, pressed);
+    }
+
+    // Make `buttons` autofire on controller `port` while held, pressed
+    // for `rate` frames and released for `rate` frames
+    pub fn set_turbo(&mut self, port: usize, buttons: u8, rate: u8) {
+        let controller = &mut self.cpu.input.controllers[port];
+        controller.turbo = buttons;
+        controller.turbo_rate = rate;
+    }
+
+    pub fn define_macro(&mut self, name: &str, macro_: Macro) {
+        self.cpu.input.macros.insert(name.to_string(), macro_);
+    }
+
+    // have `press_key(key)` play the macro `name` on controller `port`
+    pub fn bind_macro(&mut self, key: &str, name: &str, port: usize) {
+        self.cpu.input.bindings.insert(key.to_string(), (name.to_string(), port));
+    }
+
+    // Start a macro on controller `port` from the next frame on.
+    // False if there is no macro with that name
+    pub fn play_macro(&mut self, name: &str, port: usize) -> bool {
+        self.cpu.input.play_macro(name, port)
+    }
+
+    pub fn press_key(&mut self, key: &str) -> bool {
+        self.cpu.input.press_key(key)
+    }
+
+    // Point the Zapper at a pixel, or away from the screen with None
+    pub fn aim_zapper(&mut self, aim: Option<(u16, u16)>) {
+        self.cpu.input.zapper.aim = aim;
+    }
+
+    pub fn set_zapper_trigger(&mut self, pulled: bool) {
+        self.cpu.input.zapper.trigger = pulled;
+    }
+
+    // Arkanoid paddle position, about $62 to $F2, and fire button
+    pub fn set_paddle(&mut self, position: u8, button: bool) {
+        self.cpu.input.vaus.position = position;
+        self.cpu.input.vaus.button = button;
+    }
+
+    // Power Pad or Family Trainer button 1 to 12.
+    // False if there is no such button
+    pub fn set_mat_button(&mut self, button: u8, pressed: bool) -> bool {
+        self.cpu.input.power_pad.set_button(button, pressed)
+    }
+
+    // Family BASIC keyboard key by name, e.g. "a", "return" or "lshift".
+    // False if there is no such key
+    pub fn set_key(&mut self, name: &str, pressed: bool) -> bool {
+        self.cpu.input.keyboard.set_key(name, pressed)
+    }
+
+    // e.g. 44100 or 48000 Hz
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.apu.output = AudioOutput::new(self.region.cpu_clock_rate(), sample_rate);
+
+        let stems = !self.cpu.apu.stems.is_empty();
+        self.cpu.apu.set_stems(stems);
+    }
+
+    // Record the audio to a WAV file from the next frame on, and with `stems`
+    // each channel to its own file next to it, e.g. song.pulse1.wav.
+    // A recording that is already running is finished first.
+    pub fn start_recording(&mut self, path: &Path, stems: bool) -> Result<()> {
+        self.stop_recording()?;
+
+        let recorder = AudioRecorder::create(path, self.cpu.apu.output.sample_rate, stems)?;
+        self.cpu.apu.set_stems(recorder.records_stems());
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        self.cpu.apu.set_stems(false);
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // the audio of the last frame, with each sample
+    // repeated for `channels` interleaved channels
+    pub fn audio_f32(&self, channels: usize) -> Vec<f32> {
+        audio::interleave(self.cpu.apu.output.last_frame(), channels)
+    }
+
+    pub fn audio_i16(&self, channels: usize) -> Vec<i16> {
+        audio::to_i16(&self.audio_f32(channels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NES;
+    use crate::cartridge::Cartridge;
+    use crate::controller::Button;
+    use crate::movie::Movie;
+    use crate::region::Region;
+
+    // an NROM game that loops at $8000, adding up the first
+    // bit read from controller 1 and counting the loops
+    const PROGRAM: &[u8] = &[
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x16, 0x40, // STA $4016
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x16, 0x40, // STA $4016
+        0xAD, 0x16, 0x40, // LDA $4016
+        0x65, 0x00,       // ADC $00
+        0x85, 0x00,       // STA $00
+        0xE6, 0x01,       // INC $01
+        0x4C, 0x00, 0x80, // JMP $8000
+    ];
+
+    fn rom(id: u8) -> Vec<u8> {
+        let mut rom = b"NES\x1A\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        rom.resize(16 + 0x4000, id);
+        rom[16..16 + PROGRAM.len()].copy_from_slice(PROGRAM);
+        rom[16 + 0x3FFC] = 0x00;
+        rom[16 + 0x3FFD] = 0x80;
+        rom
+    }
+
+    fn new_nes() -> NES {
+        NES::new(Cartridge::from_bytes(&rom(0)).unwrap(), Region::Ntsc)
+    }
+
+    #[test]
+    fn audio_samples_per_frame() {
+        for &(sample_rate, min, max) in &[(44100, 733, 735), (48000, 798, 800)] {
+            let mut nes = new_nes();
+            nes.set_sample_rate(sample_rate);
+            nes.run_frame();
+
+            let mut total = 0;
+            for _ in 0..60 {
+                nes.run_frame();
+                let stereo = nes.audio_f32(2);
+                assert_eq!(stereo.len() % 2, 0);
+                assert!((min..=max).contains(&(stereo.len() / 2)), "{} samples at {} Hz", stereo.len() / 2, sample_rate);
+                assert_eq!(nes.audio_i16(1).len(), stereo.len() / 2);
+                total += stereo.len() / 2;
+            }
+
+            // 60 frames of 29780.67 CPU cycles
+            let expected = 60.0 * 29780.67 * sample_rate as f64 / Region::Ntsc.cpu_clock_rate();
+            assert!((total as f64 - expected).abs() < 2.0, "{} samples, expected {}", total, expected);
+        }
+    }
+
+    #[test]
+    fn audio_channels_are_interleaved() {
+        let mut nes = new_nes();
+        nes.run_frame();
+        let mono = nes.audio_f32(1);
+        let stereo = nes.audio_f32(2);
+        for (n, &sample) in mono.iter().enumerate() {
+            assert_eq!(stereo[2 * n], sample);
+            assert_eq!(stereo[2 * n + 1], sample);
+        }
+    }
+
+    #[test]
+    fn power_resets_counters_and_input() {
+        let mut nes = new_nes();
+        nes.run_frame();
+        nes.set_buttons(0, Button::A.bit());
+        nes.cpu.write(0x4016, 0x01);
+
+        nes.power();
+        assert_eq!(nes.system_clock, 0);
+        assert_eq!(nes.cpu.cycle_count, 0);
+        assert_eq!(nes.cpu.ram[0x01], 0);
+
+        // the strobe is low and nothing is left to shift out
+        assert_eq!(nes.cpu.read(0x4016) & 0x01, 0);
+    }
+
+    #[test]
+    fn savestate_round_trip() {
+        let mut nes = new_nes();
+        for frame in 0..10 {
+            nes.set_buttons(0, frame);
+            nes.run_frame();
+        }
+        let state = nes.save_state();
+        for _ in 0..5 {
+            nes.run_frame();
+        }
+        let later = nes.save_state();
+        assert_ne!(later, state);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.save_state(), state);
+        for _ in 0..5 {
+            nes.run_frame();
+        }
+        assert_eq!(nes.save_state(), later);
+
+        // a console that has just been switched on picks up from there too
+        let mut other = new_nes();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+    }
+
+    #[test]
+    fn load_state_checks_the_state() {
+        let mut nes = new_nes();
+        nes.run_frame();
+        let state = nes.save_state();
+        nes.run_frame();
+        let current = nes.save_state();
+
+        // another game, part of a state, and not a state at all change nothing
+        let mut other = NES::new(Cartridge::from_bytes(&rom(1)).unwrap(), Region::Ntsc);
+        assert!(other.load_state(&state).is_err());
+        assert!(nes.load_state(&state[..state.len() - 1]).is_err());
+        assert!(nes.load_state(b"NES\x1A").is_err());
+        assert_eq!(nes.save_state(), current);
+    }
+
+    #[test]
+    fn movie_from_savestate() {
+        let mut nes = new_nes();
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+
+        let md5 = nes.cpu.cartridge.md5();
+        nes.record_movie(Movie::new("test", md5), true);
+        for frame in 0..10 {
+            nes.set_buttons(0, if frame % 3 == 0 { Button::A.bit() } else { 0 });
+            nes.run_frame();
+        }
+        let end = nes.save_state();
+        let movie = nes.stop_movie().unwrap();
+        assert!(movie.savestate.is_some());
+
+        // played back on a console just switched on, through an FM2 file
+        let mut player = new_nes();
+        player.play_movie(Movie::parse(&movie.to_fm2()).unwrap()).unwrap();
+        for _ in 0..10 {
+            player.run_frame();
+        }
+        assert!(player.movie_finished());
+        assert_eq!(player.save_state(), end);
+    }
+}
@@ -0,0 +1,160 @@
+use std::f32::consts::PI;
+
+use crate::palette::{composite_level, yiq_to_rgb, NtscParams};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// 256 input pixels come out as 602 output pixels, like blargg's nes_ntsc,
+// which keeps a pixel aspect ratio close to that of a TV
+pub const NTSC_WIDTH: usize = 602;
+
+// the PPU generates the signal with a 12 step color clock that advances
+// twice per master clock, which is 8 samples per pixel
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = SCREEN_WIDTH * SAMPLES_PER_PIXEL;
+
+// Controls of the filter, in the range -1.0 to 1.0 except
+// for fringing, which goes from 0.0 to 1.0
+#[derive(Debug,Clone,Copy)]
+pub struct NtscFilterParams {
+    // how narrow the luma filter is. Sharper pictures let
+    // more of the color signal through as dot patterns
+    pub sharpness: f32,
+
+    // how much brightness changes show up as false colors along
+    // edges; 0.0 decodes color as if luma and chroma were separate
+    pub fringing: f32,
+
+    // how far colors smear horizontally
+    pub bleed: f32,
+}
+
+impl Default for NtscFilterParams {
+    fn default() -> Self {
+        NtscFilterParams {
+            sharpness: 0.0,
+            fringing: 1.0,
+            bleed: 0.0,
+        }
+    }
+}
+
+/**
+ * NTSC composite video filter.
+ * The palette indices and emphasis bits of each scanline are turned back into
+ * the composite signal the PPU generates, which is then decoded to YIQ with a
+ * narrow filter for luma and a wider one for chroma, the way a TV does.
+ * Because luma and chroma share the signal, sharp changes in brightness
+ * decode as false colors, and the phase of the color clock moving each
+ * scanline and frame makes those artifacts crawl.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/NTSC_video
+ * http://slack.net/~ant/libs/ntsc.html
+ */
+pub struct NtscFilter {
+    pub params: NtscFilterParams,
+    pub picture: NtscParams,
+
+    // the signal of every pixel value at each of the 12 color phases
+    levels: Vec<[f32; 12]>,
+
+    // the average of each pixel's signal, i.e. its luma
+    luma: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(params: NtscFilterParams, picture: NtscParams) -> Self {
+        let levels: Vec<[f32; 12]> = (0..512u16).map(|pixel| {
+            let mut wave = [0.0; 12];
+            for (phase, level) in wave.iter_mut().enumerate() {
+                *level = composite_level(pixel, phase as u16);
+            }
+            wave
+        }).collect();
+
+        let luma = levels.iter().map(|wave| wave.iter().sum::<f32>() / 12.0).collect();
+
+        NtscFilter {
+            params,
+            picture,
+            levels,
+            luma,
+        }
+    }
+
+    // filter widths in samples
+    fn luma_width(&self) -> usize {
+        (12.0 * (1.0 - 0.5 * self.params.sharpness)).round().clamp(4.0, 24.0) as usize
+    }
+
+    fn chroma_width(&self) -> usize {
+        (24.0 * (1.0 + self.params.bleed)).round().clamp(12.0, 48.0) as usize
+    }
+
+    // filter a whole frame into packed 24-bit RGB, NTSC_WIDTH x 240
+    pub fn render(&self, framebuffer: &[u16], frame: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(NTSC_WIDTH * SCREEN_HEIGHT * 3);
+
+        // a scanline is 341 * 8 samples long, so each one starts 4 phases
+        // later than the previous one. Frames alternate between two starting
+        // phases because every other frame is one dot shorter.
+        let frame_phase = (frame % 2) as usize * 4;
+
+        for (y, line) in framebuffer.chunks(SCREEN_WIDTH).enumerate().take(SCREEN_HEIGHT) {
+            let phase = (frame_phase + y * 4) % 12;
+            self.render_line(line, phase, &mut out);
+        }
+
+        out
+    }
+
+    fn render_line(&self, line: &[u16], start_phase: usize, out: &mut Vec<u8>) {
+        let hue = self.picture.hue / 30.0;
+        let carrier: Vec<(f32, f32)> = (0..12)
+            .map(|phase| {
+                let angle = PI / 6.0 * (phase as f32 + hue);
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+
+        // running sums of the signal and of its demodulated chroma,
+        // so any window can be averaged in constant time
+        let mut sum_y = vec![0.0f32; SAMPLES_PER_LINE + 1];
+        let mut sum_i = vec![0.0f32; SAMPLES_PER_LINE + 1];
+        let mut sum_q = vec![0.0f32; SAMPLES_PER_LINE + 1];
+
+        let crosstalk = 1.0 - self.params.fringing.clamp(0.0, 1.0);
+
+        for sample in 0..SAMPLES_PER_LINE {
+            let pixel = (line[sample / SAMPLES_PER_PIXEL] & 0x1FF) as usize;
+            let phase = (start_phase + sample) % 12;
+
+            let signal = self.levels[pixel][phase];
+            let chroma = signal - crosstalk * self.luma[pixel];
+            let (cos, sin) = carrier[phase];
+
+            sum_y[sample + 1] = sum_y[sample] + signal;
+            sum_i[sample + 1] = sum_i[sample] + chroma * cos;
+            sum_q[sample + 1] = sum_q[sample] + chroma * sin;
+        }
+
+        let average = |sums: &[f32], center: usize, width: usize| {
+            let start = center.saturating_sub(width / 2);
+            let end = (start + width).min(SAMPLES_PER_LINE);
+            (sums[end] - sums[start]) / (end - start) as f32
+        };
+
+        let luma_width = self.luma_width();
+        let chroma_width = self.chroma_width();
+
+        for x in 0..NTSC_WIDTH {
+            let center = (x * 2 + 1) * SAMPLES_PER_LINE / (NTSC_WIDTH * 2);
+
+            let y = average(&sum_y, center, luma_width);
+            let i = average(&sum_i, center, chroma_width);
+            let q = average(&sum_q, center, chroma_width);
+
+            out.extend_from_slice(&yiq_to_rgb(y, i, q, &self.picture));
+        }
+    }
+}
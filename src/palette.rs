@@ -0,0 +1,346 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::ppu::PpuModel;
+
+// The PPU outputs 6-bit indices into the NES master palette, plus the three
+// color emphasis bits of PPUMASK. The NES generates composite video directly
+// from these, so there is no "true" RGB value for each color and different
+// emulators and capture setups settled on different palettes.
+//
+// Pixels in the framebuffer are laid out as `eee iiiiii`:
+// eee: emphasis bits (blue, green, red), iiiiii: master palette index
+//
+// References:
+// https://wiki.nesdev.org/w/index.php/PPU_palettes
+// https://wiki.nesdev.org/w/index.php/.pal
+pub struct Palette {
+    // 8 emphasis combinations of 64 colors each
+    pub colors: Vec<[u8; 3]>,
+}
+
+// how much emphasizing one color darkens the other two, for palettes
+// that only list the 64 colors without emphasis
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+pub const BUILTIN_PALETTES: [&str; 5] = ["2c02", "fceux", "nestopia", "2c03", "generated"];
+
+// Controls for the generated palette. The defaults give a
+// palette close to a typical TV; hue is in degrees.
+#[derive(Debug,Clone,Copy)]
+pub struct NtscParams {
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    pub gamma: f32,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        NtscParams {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 1.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+impl Palette {
+    pub fn from_colors(colors: &[[u8; 3]]) -> Self {
+        assert!(colors.len() == 64 || colors.len() == 512);
+
+        if colors.len() == 512 {
+            return Palette { colors: colors.to_vec() };
+        }
+
+        let mut expanded = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for color in colors {
+                let mut rgb = [color[0] as f32, color[1] as f32, color[2] as f32];
+
+                // emphasizing a channel attenuates the two others
+                for channel in 0..3 {
+                    if emphasis & (1 << channel) != 0 {
+                        for (other, value) in rgb.iter_mut().enumerate() {
+                            if other != channel {
+                                *value *= EMPHASIS_ATTENUATION;
+                            }
+                        }
+                    }
+                }
+
+                expanded.push([rgb[0] as u8, rgb[1] as u8, rgb[2] as u8]);
+            }
+        }
+
+        Palette { colors: expanded }
+    }
+
+    // .pal files are raw RGB triplets: 64 colors (192 bytes), or all
+    // 8 emphasis combinations of them (1536 bytes)
+    pub fn from_pal(data: &[u8]) -> Result<Self> {
+        if data.len() != 64 * 3 && data.len() != 512 * 3 {
+            return Err(Error::new(ErrorKind::InvalidData, "a .pal file must be 192 or 1536 bytes"));
+        }
+
+        let colors: Vec<[u8; 3]> = data.chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+        Ok(Palette::from_colors(&colors))
+    }
+
+    /**
+     * Generate the palette from the composite signal the PPU produces.
+     * 12 samples of one color cycle are decoded to YIQ as a TV would, then
+     * converted to RGB.
+     *
+     * References:
+     * https://bisqwit.iki.fi/jutut/kuvat/programming_examples/nesemu1/nesemu1.cc
+     */
+    pub fn generate(params: &NtscParams) -> Self {
+        let colors = (0..512u16).map(|pixel| {
+            let (mut y, mut i, mut q) = (0.0f32, 0.0f32, 0.0f32);
+            for phase in 0..12u16 {
+                let value = composite_level(pixel, phase) / 12.0;
+
+                // 12 samples per color cycle, so each one is 30 degrees apart
+                let angle = std::f32::consts::PI / 6.0 * (phase as f32 + params.hue / 30.0);
+                y += value;
+                i += value * angle.cos();
+                q += value * angle.sin();
+            }
+
+            yiq_to_rgb(y, i, q, params)
+        }).collect();
+
+        Palette { colors }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        let colors = match name {
+            "2c02" => from_hex(&PALETTE_2C02),
+            "fceux" => from_hex(&PALETTE_FCEUX),
+            "nestopia" => from_hex(&PALETTE_NESTOPIA),
+            "2c03" => from_3bit(&PALETTE_2C03),
+            _ => return None,
+        };
+        Some(Palette::from_colors(&colors))
+    }
+
+    // `name` is either a built-in palette, "generated",
+    // which uses `params`, or the path to a .pal file
+    pub fn select(name: &str, params: &NtscParams) -> Result<Self> {
+        if name == "generated" {
+            return Ok(Palette::generate(params));
+        }
+
+        if let Some(palette) = Palette::builtin(name) {
+            return Ok(palette);
+        }
+
+        let path = Path::new(name);
+        if path.is_file() {
+            return Palette::from_pal(&fs::read(path)?);
+        }
+
+        let msg = format!("'{}' is neither a .pal file nor one of {}", name, BUILTIN_PALETTES.join(", "));
+        Err(Error::new(ErrorKind::NotFound, msg))
+    }
+
+    // The colors as shown by `model`. The 2C04 variants look up each index
+    // in their own order, and on the RGB PPUs emphasis turns a channel
+    // fully on instead of darkening the other two.
+    pub fn for_ppu(&self, model: PpuModel) -> Palette {
+        if !model.is_rgb() {
+            return Palette { colors: self.colors.clone() };
+        }
+
+        let order = match model {
+            PpuModel::Rp2C04(1) => Some(&ORDER_2C04_0001),
+            PpuModel::Rp2C04(2) => Some(&ORDER_2C04_0002),
+            PpuModel::Rp2C04(3) => Some(&ORDER_2C04_0003),
+            PpuModel::Rp2C04(4) => Some(&ORDER_2C04_0004),
+            _ => None,
+        };
+
+        let colors = (0..512).map(|pixel| {
+            let index = pixel & 0x3F;
+            let mut rgb = self.colors[order.map_or(index, |order| order[index] as usize)];
+            for (channel, value) in rgb.iter_mut().enumerate() {
+                if pixel & (0x40 << channel) != 0 {
+                    *value = 0xFF;
+                }
+            }
+            rgb
+        }).collect();
+
+        Palette { colors }
+    }
+
+    pub fn rgb(&self, pixel: u16) -> [u8; 3] {
+        self.colors[(pixel & 0x1FF) as usize]
+    }
+
+    // convert a whole frame to packed 24-bit RGB
+    pub fn to_rgb(&self, framebuffer: &[u16]) -> Vec<u8> {
+        framebuffer.iter().flat_map(|&pixel| self.rgb(pixel)).collect()
+    }
+}
+
+/**
+ * Level of the composite signal for `pixel` (eee iiiiii) at one of the 12
+ * phases of the color subcarrier, where 0.0 is black and 1.0 is white.
+ * Each color is a square wave between two voltage levels, with the phase
+ * of the wave (the hue) given by the low nybble of the index.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/NTSC_video
+ */
+pub fn composite_level(pixel: u16, phase: u16) -> f32 {
+    // signal levels relative to sync, in volts:
+    // the low levels of luma 0-3, then the high levels
+    const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+    const BLACK: f32 = 0.518;
+    const WHITE: f32 = 1.962;
+    // emphasis attenuates the signal during part of the color cycle
+    const ATTENUATION: f32 = 0.746;
+
+    // whether the square wave of `color` is high at `phase`
+    let in_color_phase = |color: u16| (color + phase + 8) % 12 < 6;
+
+    let color = pixel & 0x0F;
+    // colors $xE and $xF are forced black
+    let luma = if color < 0x0E { (pixel >> 4) & 0x03 } else { 1 };
+
+    // color $x0 is high for the whole cycle and $xD low,
+    // so they show up as grey
+    let low = LEVELS[(luma + if color == 0x00 { 4 } else { 0 }) as usize];
+    let high = LEVELS[(luma + if color < 0x0D { 4 } else { 0 }) as usize];
+
+    let mut signal = if in_color_phase(color) { high } else { low };
+
+    let emphasized = (pixel & 0x040 != 0 && in_color_phase(0))
+        || (pixel & 0x080 != 0 && in_color_phase(4))
+        || (pixel & 0x100 != 0 && in_color_phase(8));
+    if emphasized {
+        signal *= ATTENUATION;
+    }
+
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+// apply the picture controls to a decoded YIQ color and convert it to RGB
+pub fn yiq_to_rgb(y: f32, i: f32, q: f32, params: &NtscParams) -> [u8; 3] {
+    let y = ((y - 0.5) * params.contrast + 0.5) * params.brightness;
+    let chroma = params.contrast * params.brightness * params.saturation;
+    let (i, q) = (i * chroma, q * chroma);
+
+    let gamma_fix = |value: f32| if value <= 0.0 { 0.0 } else { value.powf(2.2 / params.gamma) };
+    let to_byte = |value: f32| (gamma_fix(value) * 255.0).clamp(0.0, 255.0) as u8;
+
+    // the FCC YIQ to RGB matrix
+    [
+        to_byte(y + 0.946882 * i + 0.623557 * q),
+        to_byte(y - 0.274788 * i - 0.635691 * q),
+        to_byte(y - 1.108545 * i + 1.709007 * q),
+    ]
+}
+
+fn from_hex(table: &[u32; 64]) -> Vec<[u8; 3]> {
+    table.iter().map(|&c| [(c >> 16) as u8, (c >> 8) as u8, c as u8]).collect()
+}
+
+// the RGB PPUs use a 3-bit DAC per channel
+fn from_3bit(table: &[u16; 64]) -> Vec<[u8; 3]> {
+    let level = |n: u16| (n as u32 * 255 / 7) as u8;
+    table.iter().map(|&c| [level(c >> 8), level((c >> 4) & 0xF), level(c & 0xF)]).collect()
+}
+
+// NTSC 2C02 as measured from composite output
+#[rustfmt::skip]
+static PALETTE_2C02: [u32; 64] = [
+    0x666666, 0x002A88, 0x1412A7, 0x3B00A4, 0x5C007E, 0x6E0040, 0x6C0600, 0x561D00,
+    0x333500, 0x0B4800, 0x005200, 0x004F08, 0x00404D, 0x000000, 0x000000, 0x000000,
+    0xADADAD, 0x155FD9, 0x4240FF, 0x7527FE, 0xA01ACC, 0xB71E7B, 0xB53120, 0x994E00,
+    0x6B6D00, 0x388700, 0x0C9300, 0x008F32, 0x007C8D, 0x000000, 0x000000, 0x000000,
+    0xFFFEFF, 0x64B0FF, 0x9290FF, 0xC676FF, 0xF36AFF, 0xFE6ECC, 0xFE8170, 0xEA9E22,
+    0xBCBE00, 0x88D800, 0x5CE430, 0x45E082, 0x48CDDE, 0x4F4F4F, 0x000000, 0x000000,
+    0xFFFEFF, 0xC0DFFF, 0xD3D2FF, 0xE8C8FF, 0xFBC2FF, 0xFEC4EA, 0xFECCC5, 0xF7D8A5,
+    0xE4E594, 0xCFEF96, 0xBDF4AB, 0xB3F3CC, 0xB5EBF2, 0xB8B8B8, 0x000000, 0x000000,
+];
+
+// FCEUX default palette
+#[rustfmt::skip]
+static PALETTE_FCEUX: [u32; 64] = [
+    0x747474, 0x24188C, 0x0000A8, 0x44009C, 0x8C0074, 0xA80010, 0xA40000, 0x7C0800,
+    0x402C00, 0x004400, 0x005000, 0x003C14, 0x183C5C, 0x000000, 0x000000, 0x000000,
+    0xBCBCBC, 0x0070EC, 0x2038EC, 0x8000F0, 0xBC00BC, 0xE40058, 0xD82800, 0xC84C0C,
+    0x887000, 0x009400, 0x00A800, 0x009038, 0x008088, 0x000000, 0x000000, 0x000000,
+    0xFCFCFC, 0x3CBCFC, 0x5C94FC, 0xCC88FC, 0xF478FC, 0xFC74B4, 0xFC7460, 0xFC9838,
+    0xF0BC3C, 0x80D010, 0x4CDC48, 0x58F898, 0x00E8D8, 0x787878, 0x000000, 0x000000,
+    0xFCFCFC, 0xA8E4FC, 0xC4D4FC, 0xD4C8FC, 0xFCC4FC, 0xFCC4D8, 0xFCBCB0, 0xFCD8A8,
+    0xFCE4A0, 0xE0FCA0, 0xA8F0BC, 0xB0FCCC, 0x9CFCF0, 0xC4C4C4, 0x000000, 0x000000,
+];
+
+// Nestopia YUV palette
+#[rustfmt::skip]
+static PALETTE_NESTOPIA: [u32; 64] = [
+    0x656565, 0x00127D, 0x18008E, 0x360082, 0x56005D, 0x5A0018, 0x4F0500, 0x381900,
+    0x1D3100, 0x003D00, 0x004100, 0x003B17, 0x002E55, 0x000000, 0x000000, 0x000000,
+    0xAFAFAF, 0x194EC8, 0x472FE3, 0x6B1FD7, 0x931BAE, 0x9E1A5E, 0x993200, 0x7B4B00,
+    0x5B6700, 0x267A00, 0x008200, 0x007A3E, 0x006E8A, 0x000000, 0x000000, 0x000000,
+    0xFFFFFF, 0x64A9FF, 0x8E89FF, 0xB676FF, 0xE06FFF, 0xEF6CC4, 0xF0806A, 0xD8982C,
+    0xB9B40A, 0x83CB0C, 0x5BD63F, 0x4AD17E, 0x4DC7CB, 0x4C4C4C, 0x000000, 0x000000,
+    0xFFFFFF, 0xC7E5FF, 0xD9D9FF, 0xE9D1FF, 0xF9CEFF, 0xFFCCF1, 0xFFD4CB, 0xF8DFB1,
+    0xEDEAA4, 0xD6F4A4, 0xC5F8B8, 0xBEF6D3, 0xBFF1F1, 0xB9B9B9, 0x000000, 0x000000,
+];
+
+// 2C03 RGB PPU (Vs. System, PlayChoice-10, Famicom Titler), 0xRGB with 3 bits per channel
+#[rustfmt::skip]
+static PALETTE_2C03: [u16; 64] = [
+    0x333, 0x014, 0x006, 0x326, 0x403, 0x503, 0x510, 0x420,
+    0x320, 0x120, 0x031, 0x040, 0x022, 0x000, 0x000, 0x000,
+    0x555, 0x036, 0x027, 0x407, 0x507, 0x704, 0x700, 0x630,
+    0x430, 0x140, 0x040, 0x053, 0x044, 0x000, 0x000, 0x000,
+    0x777, 0x357, 0x447, 0x637, 0x707, 0x737, 0x740, 0x750,
+    0x660, 0x360, 0x070, 0x276, 0x077, 0x000, 0x000, 0x000,
+    0x777, 0x567, 0x657, 0x757, 0x747, 0x755, 0x764, 0x772,
+    0x773, 0x572, 0x473, 0x276, 0x467, 0x000, 0x000, 0x000,
+];
+
+// The 2C04 PPUs hold the 2C03 colors in a different order; these give the
+// 2C03 index of each of their colors. The unused entries show black ($2E).
+// https://wiki.nesdev.org/w/index.php/PPU_palettes#2C04
+#[rustfmt::skip]
+static ORDER_2C04_0001: [u8; 64] = [
+    0x35, 0x23, 0x16, 0x22, 0x1C, 0x09, 0x1D, 0x15, 0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+    0x21, 0x3E, 0x1F, 0x29, 0x3C, 0x32, 0x36, 0x12, 0x3F, 0x2B, 0x2E, 0x1E, 0x3D, 0x2D, 0x24, 0x01,
+    0x0E, 0x31, 0x33, 0x2A, 0x2C, 0x0C, 0x1B, 0x14, 0x2E, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2E,
+    0x2E, 0x19, 0x10, 0x0A, 0x39, 0x3A, 0x1A, 0x37, 0x17, 0x38, 0x30, 0x11, 0x0F, 0x03, 0x0B, 0x18,
+];
+
+#[rustfmt::skip]
+static ORDER_2C04_0002: [u8; 64] = [
+    0x2E, 0x27, 0x18, 0x39, 0x3A, 0x25, 0x1C, 0x31, 0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3C, 0x0B,
+    0x0F, 0x21, 0x06, 0x3D, 0x1B, 0x29, 0x1E, 0x22, 0x1D, 0x24, 0x0E, 0x2B, 0x32, 0x08, 0x2E, 0x03,
+    0x04, 0x36, 0x26, 0x33, 0x11, 0x1F, 0x10, 0x02, 0x14, 0x3F, 0x00, 0x09, 0x12, 0x2E, 0x28, 0x20,
+    0x3E, 0x0D, 0x2A, 0x17, 0x0C, 0x01, 0x15, 0x19, 0x2E, 0x2C, 0x07, 0x37, 0x35, 0x05, 0x0A, 0x2D,
+];
+
+#[rustfmt::skip]
+static ORDER_2C04_0003: [u8; 64] = [
+    0x14, 0x25, 0x3A, 0x10, 0x0B, 0x20, 0x31, 0x09, 0x01, 0x2E, 0x36, 0x08, 0x15, 0x3D, 0x3E, 0x3C,
+    0x22, 0x1C, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1B, 0x00, 0x03, 0x2E, 0x02, 0x16, 0x06, 0x34, 0x35,
+    0x23, 0x0F, 0x0E, 0x37, 0x0D, 0x27, 0x26, 0x20, 0x29, 0x04, 0x21, 0x24, 0x11, 0x2D, 0x2E, 0x1F,
+    0x2C, 0x1E, 0x39, 0x33, 0x07, 0x2A, 0x28, 0x1D, 0x0A, 0x2E, 0x32, 0x38, 0x13, 0x2B, 0x3F, 0x0C,
+];
+
+#[rustfmt::skip]
+static ORDER_2C04_0004: [u8; 64] = [
+    0x18, 0x03, 0x1C, 0x28, 0x2E, 0x35, 0x01, 0x17, 0x10, 0x1F, 0x2A, 0x0E, 0x36, 0x37, 0x0B, 0x39,
+    0x25, 0x1E, 0x12, 0x34, 0x2E, 0x1D, 0x06, 0x26, 0x3E, 0x1B, 0x22, 0x19, 0x04, 0x2E, 0x3A, 0x21,
+    0x05, 0x0A, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15, 0x0C, 0x3D, 0x11, 0x0F, 0x0D, 0x38, 0x2D, 0x24,
+    0x33, 0x20, 0x08, 0x16, 0x3F, 0x2B, 0x20, 0x3C, 0x2E, 0x27, 0x23, 0x31, 0x29, 0x32, 0x2C, 0x09,
+];
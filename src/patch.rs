@@ -0,0 +1,493 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::hash::crc32;
+
+// Soft-patching of ROM images.
+// Patches are applied to the raw file contents in memory, before the
+// cartridge header is parsed, so the ROM on disk is never modified.
+//
+// References:
+// https://zerosoft.zophar.net/ips.php
+// https://www.romhacking.net/documents/392/ (UPS)
+// https://www.romhacking.net/documents/746/ (BPS)
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    // file extensions searched for next to the ROM, in order of preference
+    pub const EXTENSIONS: [(&'static str, PatchFormat); 3] = [
+        ("bps", PatchFormat::Bps),
+        ("ups", PatchFormat::Ups),
+        ("ips", PatchFormat::Ips),
+    ];
+
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(b"PATCH") {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(b"UPS1") {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(b"BPS1") {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+// apply a patch of any supported format, detected from its magic number
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        None => Err(invalid("unrecognized patch format")),
+    }
+}
+
+// look for `<rom name>.bps`, `<rom name>.ups` or `<rom name>.ips`
+// in the same directory as the ROM
+pub fn find_patch_for(rom_path: &Path) -> Option<PathBuf> {
+    PatchFormat::EXTENSIONS.iter()
+        .map(|(ext, _)| rom_path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+// a cursor over the patch data that reports truncated patches
+// as errors instead of panicking
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Reader { data, pos }
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self.data.get(self.pos).ok_or_else(|| invalid("unexpected end of patch"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| invalid("unexpected end of patch"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // big endian, used by IPS
+    fn be(&mut self, len: usize) -> Result<usize> {
+        Ok(self.bytes(len)?.iter().fold(0, |acc, &b| acc << 8 | b as usize))
+    }
+
+    // variable length integer shared by UPS and BPS.
+    // every continuation also adds the next power of 128, which
+    // gives each number exactly one encoding
+    fn varint(&mut self) -> Result<usize> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()? as usize;
+            value = (byte & 0x7F).checked_mul(shift)
+                .and_then(|v| value.checked_add(v))
+                .ok_or_else(|| invalid("patch integer overflow"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or_else(|| invalid("patch integer overflow"))?;
+            value = value.checked_add(shift).ok_or_else(|| invalid("patch integer overflow"))?;
+        }
+    }
+}
+
+// IPS: a list of (offset, data) records terminated by "EOF".
+// A record with a size of zero is an RLE record that repeats a single byte.
+// The Lunar IPS extension appends a 3 byte size after "EOF" to truncate the output.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if !patch.starts_with(b"PATCH") {
+        return Err(invalid("missing IPS header"));
+    }
+
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(patch, 5);
+
+    loop {
+        let offset = reader.be(3)?;
+        // "EOF" in ASCII
+        if offset == 0x454F46 {
+            break;
+        }
+
+        let size = reader.be(2)?;
+        let (len, data) = if size == 0 {
+            let run = reader.be(2)?;
+            let value = reader.byte()?;
+            (run, vec![value; run])
+        } else {
+            (size, reader.bytes(size)?.to_vec())
+        };
+
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        out[offset..offset + len].copy_from_slice(&data);
+    }
+
+    // truncation extension
+    if reader.data.len() - reader.pos >= 3 {
+        let size = reader.be(3)?;
+        out.truncate(size);
+    }
+
+    Ok(out)
+}
+
+// the last 12 bytes of UPS and BPS patches hold the source,
+// target and patch CRC-32s, all little endian
+struct Footer {
+    source_crc: u32,
+    target_crc: u32,
+}
+
+fn read_footer(patch: &[u8]) -> Result<Footer> {
+    if patch.len() < 16 {
+        return Err(invalid("patch too short"));
+    }
+
+    let le = |at: usize| u32::from_le_bytes([patch[at], patch[at + 1], patch[at + 2], patch[at + 3]]);
+    let end = patch.len();
+
+    if crc32(&patch[..end - 4]) != le(end - 4) {
+        return Err(invalid("patch checksum mismatch; the patch file is corrupt"));
+    }
+
+    Ok(Footer {
+        source_crc: le(end - 12),
+        target_crc: le(end - 8),
+    })
+}
+
+// UPS: the target is the source XORed with the patch data
+// at the given relative offsets
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if !patch.starts_with(b"UPS1") {
+        return Err(invalid("missing UPS header"));
+    }
+
+    let footer = read_footer(patch)?;
+    if crc32(rom) != footer.source_crc {
+        return Err(invalid("ROM does not match the source checksum of the UPS patch"));
+    }
+
+    let mut reader = Reader::new(patch, 4);
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != rom.len() {
+        return Err(invalid("ROM size does not match the UPS patch"));
+    }
+
+    let mut out = vec![0u8; target_size];
+    let copied = rom.len().min(target_size);
+    out[..copied].copy_from_slice(&rom[..copied]);
+
+    let body_end = patch.len() - 12;
+    let mut pos: usize = 0;
+    while reader.pos < body_end {
+        pos = pos.checked_add(reader.varint()?).ok_or_else(|| invalid("patch integer overflow"))?;
+        loop {
+            let xor = reader.byte()?;
+            if xor == 0 {
+                pos += 1;
+                break;
+            }
+            if pos >= target_size {
+                return Err(invalid("UPS patch writes past the end of the target"));
+            }
+            out[pos] ^= xor;
+            pos += 1;
+        }
+    }
+
+    if crc32(&out) != footer.target_crc {
+        return Err(invalid("patched ROM does not match the target checksum of the UPS patch"));
+    }
+
+    Ok(out)
+}
+
+// BPS: the target is built front to back from four kinds of actions
+// that copy from the source, the patch or the target built so far
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if !patch.starts_with(b"BPS1") {
+        return Err(invalid("missing BPS header"));
+    }
+
+    let footer = read_footer(patch)?;
+    if crc32(rom) != footer.source_crc {
+        return Err(invalid("ROM does not match the source checksum of the BPS patch"));
+    }
+
+    let mut reader = Reader::new(patch, 4);
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(invalid("ROM size does not match the BPS patch"));
+    }
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    // offsets for the copy actions are relative and signed,
+    // with the sign in the lowest bit
+    let relative = |reader: &mut Reader, base: usize| -> Result<usize> {
+        let data = reader.varint()?;
+        let delta = data >> 1;
+        let moved = if data & 1 != 0 { base.checked_sub(delta) } else { base.checked_add(delta) };
+        moved.ok_or_else(|| invalid("BPS copy offset out of range"))
+    };
+
+    let body_end = patch.len() - 12;
+    while reader.pos < body_end {
+        let data = reader.varint()?;
+        let len = (data >> 2) + 1;
+        if out.len() + len > target_size {
+            return Err(invalid("BPS patch writes past the end of the target"));
+        }
+
+        match data & 0x3 {
+            // SourceRead
+            0 => {
+                let start = out.len();
+                let bytes = rom.get(start..start + len).ok_or_else(|| invalid("BPS source read out of range"))?;
+                out.extend_from_slice(bytes);
+            },
+            // TargetRead
+            1 => {
+                out.extend_from_slice(reader.bytes(len)?);
+            },
+            // SourceCopy
+            2 => {
+                source_offset = relative(&mut reader, source_offset)?;
+                let bytes = rom.get(source_offset..source_offset + len)
+                    .ok_or_else(|| invalid("BPS source copy out of range"))?;
+                out.extend_from_slice(bytes);
+                source_offset += len;
+            },
+            // TargetCopy
+            // NOTE: the source and destination may overlap, which is
+            // used as a cheap RLE, so this must be copied byte by byte
+            _ => {
+                target_offset = relative(&mut reader, target_offset)?;
+                for _ in 0..len {
+                    let byte = *out.get(target_offset).ok_or_else(|| invalid("BPS target copy out of range"))?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(invalid("BPS patch produced a target of the wrong size"));
+    }
+
+    if crc32(&out) != footer.target_crc {
+        return Err(invalid("patched ROM does not match the target checksum of the BPS patch"));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+
+    // the CRC-32s of the source, the target and the patch itself
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(PatchFormat::detect(b"PATCHEOF"), Some(PatchFormat::Ips));
+        assert_eq!(PatchFormat::detect(b"UPS1"), Some(PatchFormat::Ups));
+        assert_eq!(PatchFormat::detect(b"BPS1"), Some(PatchFormat::Bps));
+        assert_eq!(PatchFormat::detect(b"NES\x1A"), None);
+        assert!(apply(b"rom", b"junk").is_err());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        assert_eq!(varint(0), [0x80]);
+        assert_eq!(varint(127), [0xFF]);
+        assert_eq!(varint(128), [0x00, 0x80]);
+
+        for &value in &[0, 1, 127, 128, 255, 16511, 16512, 1 << 20, 123_456_789] {
+            let bytes = varint(value);
+            let mut reader = Reader::new(&bytes, 0);
+            assert_eq!(reader.varint().unwrap(), value);
+            assert_eq!(reader.pos, bytes.len());
+        }
+
+        assert!(Reader::new(&[0x00, 0x00], 0).varint().is_err());
+        assert!(Reader::new(&[0x7F; 16], 0).varint().is_err());
+    }
+
+    #[test]
+    fn ips_records() {
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 1
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, b'x', b'y']);
+        // RLE: 3 'z's at 4
+        patch.extend_from_slice(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03, b'z']);
+        // past the end of the ROM, which grows
+        patch.extend_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x01, b'!']);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(apply(b"abcdef", &patch).unwrap(), b"axydzzz\0!");
+    }
+
+    #[test]
+    fn ips_truncation() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, b'A']);
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+
+        assert_eq!(apply_ips(b"abcdef", &patch).unwrap(), b"Abc");
+    }
+
+    #[test]
+    fn ips_truncated_patch() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x04, b'x']);
+        assert!(apply_ips(b"abcdef", &patch).is_err());
+
+        // no EOF marker
+        assert!(apply_ips(b"abcdef", b"PATCH").is_err());
+        assert!(apply_ips(b"abcdef", b"PATC").is_err());
+    }
+
+    fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        // skip 2 and change 'c' to 'X', then skip 2 and append 'g'
+        patch.extend(varint(2));
+        patch.extend_from_slice(&[b'c' ^ b'X', 0x00]);
+        patch.extend(varint(2));
+        patch.extend_from_slice(&[b'g', 0x00]);
+        with_footer(patch, source, target)
+    }
+
+    #[test]
+    fn ups() {
+        let patch = ups_patch(b"abcdef", b"abXdefg");
+        assert_eq!(apply(b"abcdef", &patch).unwrap(), b"abXdefg");
+    }
+
+    #[test]
+    fn ups_checksums() {
+        let patch = ups_patch(b"abcdef", b"abXdefg");
+
+        // a different ROM
+        assert!(apply_ups(b"abcdeF", &patch).is_err());
+
+        // a corrupt patch
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 0x01;
+        assert!(apply_ups(b"abcdef", &corrupt).is_err());
+
+        // a patch whose target checksum doesn't match what it produces
+        let wrong_target = ups_patch(b"abcdef", b"abYdefg");
+        assert!(apply_ups(b"abcdef", &wrong_target).is_err());
+
+        assert!(apply_ups(b"abcdef", b"UPS1").is_err());
+    }
+
+    fn bps_patch(source: &[u8], target: &[u8], truncate: usize) -> Vec<u8> {
+        let action = |kind: usize, len: usize| varint((len - 1) << 2 | kind);
+
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(4));
+        patch.extend_from_slice(b"meta");
+
+        // SourceRead "ab"
+        patch.extend(action(0, 2));
+        // TargetRead "XY"
+        patch.extend(action(1, 2));
+        patch.extend_from_slice(b"XY");
+        // SourceCopy "ef", from offset +4
+        patch.extend(action(2, 2));
+        patch.extend(varint(4 << 1));
+        // TargetCopy from the 'f' just written, which repeats it 3 times
+        patch.extend(action(3, 3));
+        patch.extend(varint(5 << 1));
+        // SourceCopy "cd", 4 back from where the last one ended
+        patch.extend(action(2, 2));
+        patch.extend(varint(4 << 1 | 1));
+
+        patch.truncate(patch.len() - truncate);
+        with_footer(patch, source, target)
+    }
+
+    #[test]
+    fn bps() {
+        let patch = bps_patch(b"abcdef", b"abXYeffffcd", 0);
+        assert_eq!(apply(b"abcdef", &patch).unwrap(), b"abXYeffffcd");
+    }
+
+    #[test]
+    fn bps_errors() {
+        let patch = bps_patch(b"abcdef", b"abXYeffffcd", 0);
+        assert!(apply_bps(b"abcdeF", &patch).is_err());
+        assert!(apply_bps(b"abcdef", &bps_patch(b"abcdef", b"abXYeffffce", 0)).is_err());
+
+        let mut corrupt = patch.clone();
+        corrupt[12] ^= 0x01;
+        assert!(apply_bps(b"abcdef", &corrupt).is_err());
+
+        // the last action cut off, so the target comes out short
+        let truncated = bps_patch(b"abcdef", b"abXYeffffcd", 2);
+        assert!(apply_bps(b"abcdef", &truncated).is_err());
+
+        assert!(apply_bps(b"abcdef", b"BPS1").is_err());
+    }
+}
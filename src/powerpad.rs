@@ -0,0 +1,114 @@
+use crate::savestate::Savestate;
+
+// Power Pad buttons in the order the NES version shifts them out of
+// bits 3 and 4, numbered as on side B of the mat
+const BIT3_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const BIT4_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+/**
+ * Bandai's exercise mat, 12 buttons in 3 rows of 4:
+ *    1  2  3  4
+ *    5  6  7  8
+ *    9 10 11 12
+ * Side A of the mat only labels 8 of them differently; the console
+ * always sees these positions.
+ *
+ * NES Power Pad, usually in port 2, $4016/$4017 read after a strobe:
+ *   4: buttons 4, 3, 12, 8, then 1s
+ *   3: buttons 2, 1, 5, 9, 6, 10, 11, 7, then 1s
+ *   1 means pressed.
+ *
+ * Famicom Family Trainer, on the expansion port, a matrix:
+ *   $4016 write bits 2-0: row select, the row whose bit is 0 is read
+ *     011: buttons 4, 3, 2, 1 on $4017 bits 4-1
+ *     101: buttons 8, 7, 6, 5
+ *     110: buttons 12, 11, 10, 9
+ *   0 means pressed.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Power_Pad
+ */
+#[derive(Default)]
+pub struct PowerPad {
+    // bit n - 1 is set while button n is pressed
+    pub buttons: u16,
+
+    shift: [u8; 2],
+    strobe: bool,
+
+    // Family Trainer row select
+    select: u8,
+}
+
+impl PowerPad {
+    // false if there is no such button
+    pub fn set_button(&mut self, button: u8, pressed: bool) -> bool {
+        if !(1..=12).contains(&button) {
+            return false;
+        }
+
+        let bit = 1 << (button - 1);
+        if pressed {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+        true
+    }
+
+    fn pressed(&self, button: u8) -> u8 {
+        (self.buttons >> (button - 1)) as u8 & 0x01
+    }
+
+    fn reload(&mut self) {
+        let pack = |order: &[u8]| order.iter().enumerate()
+            .fold(0xFF, |shift: u8, (n, &button)| shift & !(1 << n) | self.pressed(button) << n);
+        self.shift = [pack(&BIT3_ORDER), pack(&BIT4_ORDER)];
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.reload();
+        }
+        self.select = value & 0x07;
+    }
+
+    // bits 3 and 4 of the port the NES mat is in
+    pub fn read_nes(&mut self) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let value = (self.shift[1] & 0x01) << 4 | (self.shift[0] & 0x01) << 3;
+        for shift in self.shift.iter_mut() {
+            *shift = 0x80 | *shift >> 1;
+        }
+        value
+    }
+
+    // bits 1-4 of $4017 for the Family Trainer
+    pub fn read_famicom(&self) -> u8 {
+        let first = match self.select {
+            0b011 => 1,
+            0b101 => 5,
+            0b110 => 9,
+            _ => return 0x1E,
+        };
+
+        let pressed = (first..first + 4).rev()
+            .fold(0, |bits, button| bits << 1 | self.pressed(button));
+        !pressed << 1 & 0x1E
+    }
+
+    pub fn power(&mut self) {
+        self.shift = [0; 2];
+        self.strobe = false;
+        self.select = 0;
+    }
+
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.bytes(&mut self.shift);
+        state.bool(&mut self.strobe);
+        state.u8(&mut self.select);
+    }
+}
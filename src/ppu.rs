@@ -0,0 +1,889 @@
+use crate::cartridge::Cartridge;
+use crate::nametable::Nametables;
+use crate::region::Region;
+use crate::savestate::Savestate;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// dots per scanline, the number of scanlines depends on the region
+pub const DOTS: u16 = 341;
+
+// the hardware draws at most 8 sprites per scanline
+const SPRITES_PER_LINE: usize = 8;
+const MAX_SPRITES: usize = 64;
+
+// The RGB PPUs of the arcade boards output RGB instead of composite video.
+// The 2C04 variants each shuffle the order of the 64 colors, and the 2C05
+// variants swap PPUCTRL and PPUMASK and return an ID in PPUSTATUS, both
+// of which Vs. System games use to check they run on the right cabinet.
+//
+// https://wiki.nesdev.org/w/index.php/PPU_variants
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum PpuModel {
+    Rp2C02,
+    // PlayChoice-10 and most Vs. System boards
+    Rp2C03,
+    // 2C04-0001 to 2C04-0004
+    Rp2C04(u8),
+    // 2C05-01 to 2C05-05
+    Rc2C05(u8),
+}
+
+impl PpuModel {
+    pub fn parse(name: &str) -> Option<PpuModel> {
+        match name.to_ascii_lowercase().as_str() {
+            "2c02" => Some(PpuModel::Rp2C02),
+            "2c03" => Some(PpuModel::Rp2C03),
+            "2c04-0001" => Some(PpuModel::Rp2C04(1)),
+            "2c04-0002" => Some(PpuModel::Rp2C04(2)),
+            "2c04-0003" => Some(PpuModel::Rp2C04(3)),
+            "2c04-0004" => Some(PpuModel::Rp2C04(4)),
+            "2c05-01" => Some(PpuModel::Rc2C05(1)),
+            "2c05-02" => Some(PpuModel::Rc2C05(2)),
+            "2c05-03" => Some(PpuModel::Rc2C05(3)),
+            "2c05-04" => Some(PpuModel::Rc2C05(4)),
+            "2c05-05" => Some(PpuModel::Rc2C05(5)),
+            _ => None,
+        }
+    }
+
+    // low nybble of byte 13 of a NES 2.0 header for Vs. System games
+    pub fn from_nes2(ppu_type: u8) -> Option<PpuModel> {
+        match ppu_type {
+            0 | 1 | 6 | 7 => Some(PpuModel::Rp2C03),
+            2..=5 => Some(PpuModel::Rp2C04(ppu_type - 1)),
+            8..=12 => Some(PpuModel::Rc2C05(ppu_type - 7)),
+            _ => None,
+        }
+    }
+
+    pub fn is_rgb(self) -> bool {
+        self != PpuModel::Rp2C02
+    }
+
+    fn swaps_ctrl_mask(self) -> bool {
+        matches!(self, PpuModel::Rc2C05(_))
+    }
+
+    // the value a 2C05 returns in the low bits of PPUSTATUS
+    fn status_id(self) -> Option<u8> {
+        match self {
+            PpuModel::Rc2C05(1) | PpuModel::Rc2C05(4) => Some(0x1B),
+            PpuModel::Rc2C05(2) => Some(0x3D),
+            PpuModel::Rc2C05(3) => Some(0x1C),
+            PpuModel::Rc2C05(_) => Some(0x00),
+            _ => None,
+        }
+    }
+}
+
+// a sprite as latched for the scanline being drawn
+#[derive(Clone,Copy,Default)]
+struct Sprite {
+    x: u8,
+    attr: u8,
+
+    // pattern bits, already flipped horizontally if need be
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+pub struct PPU {
+    // $2000 PPUCTRL
+    // VPHB SINN
+    // V: generate NMI at the start of vblank
+    // P: master/slave select (unused)
+    // H: sprite size (0: 8x8, 1: 8x16)
+    // B: background pattern table address (0: $0000, 1: $1000)
+    // S: sprite pattern table address for 8x8 sprites
+    // I: VRAM address increment per $2007 access (0: add 1, 1: add 32)
+    // NN: base nametable address
+    pub ctrl: u8,
+
+    // $2001 PPUMASK
+    // BGRs bMmG
+    // BGR: emphasize blue, green, red
+    // s: show sprites, b: show background
+    // M: show sprites in the leftmost 8 pixels, m: same for background
+    // G: greyscale
+    pub mask: u8,
+
+    // $2002 PPUSTATUS
+    // VSO- ----
+    // V: vblank started, S: sprite 0 hit, O: sprite overflow
+    pub status: u8,
+
+    // $2003 OAMADDR
+    pub oam_addr: u8,
+    pub oam: [u8; 0x100],
+
+    // NOTE: "loopy" scroll registers
+    // v and t are laid out as yyy NN YYYYY XXXXX
+    // yyy: fine Y scroll, NN: nametable select,
+    // YYYYY: coarse Y scroll, XXXXX: coarse X scroll
+    //
+    // https://wiki.nesdev.org/w/index.php/PPU_scrolling
+    pub v: u16,   // current VRAM address
+    pub t: u16,   // temporary VRAM address, i.e. the top left onscreen tile
+    pub x: u8,    // fine X scroll (3 bits)
+    pub w: bool,  // first or second write toggle for $2005/$2006
+
+    // $2007 reads below the palette are delayed by one read
+    pub read_buffer: u8,
+
+    // the data bus between the CPU and the PPU.
+    // reads of write-only registers return whatever was last written
+    pub io_latch: u8,
+
+    // 2KB nametable RAM inside the console, mapped by the cartridge
+    pub nametables: Nametables,
+    pub palette: [u8; 0x20],
+
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+
+    // the NMI line went low and the CPU has yet to service it
+    pub nmi: bool,
+
+    // reading $2002 just before vblank starts suppresses the flag and NMI
+    suppress_vblank: bool,
+
+    // set once the last visible scanline has been rendered
+    pub frame_complete: bool,
+
+    // background fetch latches
+    nt_latch: u8,
+    at_latch: u8,
+    bg_lo_latch: u8,
+    bg_hi_latch: u8,
+
+    // background shift registers; the upper 8 bits hold the tile being
+    // drawn and the lower 8 bits the tile that is fetched next
+    bg_shift_lo: u16,
+    bg_shift_hi: u16,
+    at_shift_lo: u16,
+    at_shift_hi: u16,
+
+    // true keeps the hardware limit of 8 sprites per scanline, false
+    // draws every sprite on it. This only changes what is displayed:
+    // evaluation, the overflow flag and sprite 0 hits still behave
+    // exactly like the hardware
+    pub sprite_limit: bool,
+
+    // sprites found by evaluation on this scanline for the next one.
+    // only the first 32 bytes exist on the hardware; the rest hold
+    // the sprites past the 8th when the sprite limit is removed
+    secondary_oam: [u8; MAX_SPRITES * 4],
+    sprite_count: usize,
+    sprite_zero_next: bool,
+
+    // sprites fetched for the scanline being drawn
+    sprites: [Sprite; MAX_SPRITES],
+    sprites_on_line: usize,
+    sprite_zero_on_line: bool,
+
+    // 256x240 pixels laid out as eee iiiiii
+    // eee: color emphasis bits of PPUMASK (blue, green, red)
+    // iiiiii: master palette index (0-63)
+    pub framebuffer: Vec<u16>,
+
+    // frame timing and emphasis bit order
+    pub region: Region,
+
+    pub model: PpuModel,
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        PPU {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 0x100],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            nametables: Nametables::new(),
+            palette: [0; 0x20],
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            nmi: false,
+            suppress_vblank: false,
+            frame_complete: false,
+            nt_latch: 0,
+            at_latch: 0,
+            bg_lo_latch: 0,
+            bg_hi_latch: 0,
+            bg_shift_lo: 0,
+            bg_shift_hi: 0,
+            at_shift_lo: 0,
+            at_shift_hi: 0,
+            sprite_limit: true,
+            secondary_oam: [0xFF; MAX_SPRITES * 4],
+            sprite_count: 0,
+            sprite_zero_next: false,
+            sprites: [Sprite::default(); MAX_SPRITES],
+            sprites_on_line: 0,
+            sprite_zero_on_line: false,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            region: Region::Ntsc,
+            model: PpuModel::Rp2C02,
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & 0x18 != 0
+    }
+
+    fn nmi_output(&self) -> bool {
+        self.ctrl & 0x80 != 0
+    }
+
+    fn in_vblank(&self) -> bool {
+        self.status & 0x80 != 0
+    }
+
+    /**
+     * $0000-$0FFF: Pattern table 0
+     * $1000-$1FFF: Pattern table 1
+     * $2000-$2FFF: Nametables
+     * $3000-$3EFF: Mirrors of $2000-$2EFF
+     * $3F00-$3F1F: Palette RAM indexes
+     * $3F20-$3FFF: Mirrors of $3F00-$3F1F
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/PPU_memory_map
+     */
+    pub fn read(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => cartridge.ppu_read(addr),
+            0x2000..=0x3EFF => self.nametables.read(addr, cartridge),
+            _ => self.palette[palette_index(addr)],
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8, cartridge: &mut Cartridge) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => cartridge.ppu_write(addr, value),
+            0x2000..=0x3EFF => self.nametables.write(addr, value, cartridge),
+            _ => self.palette[palette_index(addr)] = value & 0x3F,
+        }
+    }
+
+    /**
+     * CPU side register access, $2000-$2007 mirrored every 8 bytes
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/PPU_registers
+     */
+    pub fn cpu_read(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
+        match addr & 0x0007 {
+            // PPUSTATUS
+            2 => {
+                if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+                    self.suppress_vblank = true;
+                }
+
+                self.io_latch = match self.model.status_id() {
+                    Some(id) => (self.status & 0xE0) | id,
+                    None => (self.status & 0xE0) | (self.io_latch & 0x1F),
+                };
+                self.status &= !0x80;
+                self.w = false;
+            },
+            // OAMDATA
+            4 => self.io_latch = self.read_oam_data(),
+            // PPUDATA
+            7 => {
+                let addr = self.v & 0x3FFF;
+                if addr >= 0x3F00 {
+                    // palette reads are immediate, but the buffer
+                    // is filled with the nametable "underneath" them
+                    let value = self.read(addr, cartridge);
+                    self.read_buffer = self.read(addr - 0x1000, cartridge);
+                    self.io_latch = (self.io_latch & 0xC0) | value;
+                } else {
+                    self.io_latch = self.read_buffer;
+                    self.read_buffer = self.read(addr, cartridge);
+                }
+                self.increment_v_after_access();
+            },
+            // write-only registers
+            _ => {},
+        }
+
+        self.io_latch
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, value: u8, cartridge: &mut Cartridge) {
+        self.io_latch = value;
+
+        let register = match addr & 0x0007 {
+            0 if self.model.swaps_ctrl_mask() => 1,
+            1 if self.model.swaps_ctrl_mask() => 0,
+            register => register,
+        };
+
+        match register {
+            // PPUCTRL
+            0 => {
+                let was_enabled = self.nmi_output();
+                self.ctrl = value;
+                // t: ...GH.. ........ <- d: ......GH
+                self.t = (self.t & 0xF3FF) | ((value as u16 & 0x03) << 10);
+
+                // enabling NMI during vblank fires one immediately
+                if !was_enabled && self.nmi_output() && self.in_vblank() {
+                    self.nmi = true;
+                }
+            },
+            // PPUMASK
+            1 => self.mask = value,
+            // OAMADDR
+            3 => self.oam_addr = value,
+            // OAMDATA
+            4 => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            // PPUSCROLL
+            5 => {
+                if !self.w {
+                    // t: ....... ...ABCDE <- d: ABCDE...
+                    // x:              FGH <- d: .....FGH
+                    self.t = (self.t & 0xFFE0) | (value as u16 >> 3);
+                    self.x = value & 0x07;
+                } else {
+                    // t: FGH..AB CDE..... <- d: ABCDEFGH
+                    self.t = (self.t & 0x8C1F)
+                        | ((value as u16 & 0x07) << 12)
+                        | ((value as u16 & 0xF8) << 2);
+                }
+                self.w = !self.w;
+            },
+            // PPUADDR
+            6 => {
+                if !self.w {
+                    // t: .CDEFGH ........ <- d: ..CDEFGH
+                    // the highest bit of t is cleared
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                } else {
+                    // t: ....... ABCDEFGH <- d: ABCDEFGH
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            },
+            // PPUDATA
+            7 => {
+                self.write(self.v, value, cartridge);
+                self.increment_v_after_access();
+            },
+            // PPUSTATUS is read-only
+            _ => {},
+        }
+    }
+
+    fn read_oam_data(&self) -> u8 {
+        let rendering = self.rendering_enabled()
+            && (self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.region.pre_render_scanline());
+
+        // secondary OAM is being cleared, and the clear
+        // works by forcing OAM reads to return $FF
+        if rendering && (1..=64).contains(&self.dot) {
+            return 0xFF;
+        }
+
+        let value = self.oam[self.oam_addr as usize];
+
+        // bits 2-4 of the attribute byte do not exist
+        if self.oam_addr & 0x03 == 0x02 {
+            value & 0xE3
+        } else {
+            value
+        }
+    }
+
+    fn increment_v_after_access(&mut self) {
+        let rendering = self.rendering_enabled()
+            && (self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.region.pre_render_scanline());
+
+        if rendering {
+            // during rendering, $2007 accesses trigger both
+            // the coarse X and the Y increment at the same time
+            self.increment_coarse_x();
+            self.increment_y();
+        } else {
+            let step = if self.ctrl & 0x04 != 0 { 32 } else { 1 };
+            self.v = self.v.wrapping_add(step) & 0x7FFF;
+        }
+    }
+
+    // https://wiki.nesdev.org/w/index.php/PPU_scrolling#Coarse_X_increment
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            // switch horizontal nametable
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // https://wiki.nesdev.org/w/index.php/PPU_scrolling#Y_increment
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            // increment fine Y
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            // switch vertical nametable
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // coarse Y can be set out of bounds, in which case
+            // it wraps without switching nametables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    // v: ....A.. ...BCDEF <- t: ....A.. ...BCDEF
+    fn copy_horizontal(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    // v: GHIA.BC DEF..... <- t: GHIA.BC DEF.....
+    fn copy_vertical(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.bg_shift_lo = (self.bg_shift_lo & 0xFF00) | self.bg_lo_latch as u16;
+        self.bg_shift_hi = (self.bg_shift_hi & 0xFF00) | self.bg_hi_latch as u16;
+
+        // the attribute bits apply to the whole tile, so they are expanded to 8 bits
+        let spread = |bit: bool| if bit { 0x00FF } else { 0x0000 };
+        self.at_shift_lo = (self.at_shift_lo & 0xFF00) | spread(self.at_latch & 0x01 != 0);
+        self.at_shift_hi = (self.at_shift_hi & 0xFF00) | spread(self.at_latch & 0x02 != 0);
+    }
+
+    fn shift_background(&mut self) {
+        self.bg_shift_lo <<= 1;
+        self.bg_shift_hi <<= 1;
+        self.at_shift_lo <<= 1;
+        self.at_shift_hi <<= 1;
+    }
+
+    // the 8 dot memory access pattern for one background tile
+    // https://wiki.nesdev.org/w/index.php/PPU_rendering#Cycles_1-256
+    fn fetch_background(&mut self, cartridge: &mut Cartridge) {
+        match self.dot % 8 {
+            1 => {
+                self.load_background_shifters();
+                self.nt_latch = self.read(0x2000 | (self.v & 0x0FFF), cartridge);
+            },
+            3 => {
+                let addr = 0x23C0
+                    | (self.v & 0x0C00)
+                    | ((self.v >> 4) & 0x38)
+                    | ((self.v >> 2) & 0x07);
+                let attribute = self.read(addr, cartridge);
+
+                // each attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+                let shift = ((self.v >> 4) & 0x04) | (self.v & 0x02);
+                self.at_latch = (attribute >> shift) & 0x03;
+            },
+            5 => {
+                let addr = self.background_pattern_addr();
+                self.bg_lo_latch = self.read(addr, cartridge);
+            },
+            7 => {
+                let addr = self.background_pattern_addr() + 8;
+                self.bg_hi_latch = self.read(addr, cartridge);
+            },
+            0 => self.increment_coarse_x(),
+            _ => {},
+        }
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & 0x10 != 0 { 0x1000 } else { 0x0000 };
+        let fine_y = (self.v >> 12) & 0x07;
+        table + self.nt_latch as u16 * 16 + fine_y
+    }
+
+    fn background_pixel(&self) -> (u8, u8) {
+        if self.mask & 0x08 == 0 || (self.mask & 0x02 == 0 && self.dot <= 8) {
+            return (0, 0);
+        }
+
+        let bit = 0x8000 >> self.x;
+        let pixel = ((self.bg_shift_hi & bit != 0) as u8) << 1 | (self.bg_shift_lo & bit != 0) as u8;
+        let palette = ((self.at_shift_hi & bit != 0) as u8) << 1 | (self.at_shift_lo & bit != 0) as u8;
+        (pixel, palette)
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & 0x20 != 0 { 16 } else { 8 }
+    }
+
+    /**
+     * Sprite evaluation for the next scanline.
+     * Up to 8 sprites in range are copied to secondary OAM. After that the
+     * hardware keeps looking for a 9th one to set the overflow flag, but
+     * due to a bug it increments both the sprite index n and the byte
+     * index m, so it checks tile numbers, attributes and X positions as if
+     * they were Y coordinates, giving both false positives and negatives.
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/PPU_sprite_evaluation
+     */
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let scanline = self.scanline;
+        let in_range = |y: u8| scanline.wrapping_sub(y as u16) < height;
+
+        self.secondary_oam = [0xFF; MAX_SPRITES * 4];
+        self.sprite_count = 0;
+        self.sprite_zero_next = false;
+
+        let mut n = 0;
+        while n < 64 && self.sprite_count < SPRITES_PER_LINE {
+            let entry = &self.oam[n * 4..n * 4 + 4];
+            if in_range(entry[0]) {
+                let slot = self.sprite_count * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(entry);
+                if n == 0 {
+                    self.sprite_zero_next = true;
+                }
+                self.sprite_count += 1;
+            }
+            n += 1;
+        }
+
+        let first_unevaluated = n;
+
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status |= 0x20;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0x03;
+        }
+
+        if !self.sprite_limit {
+            for n in first_unevaluated..64 {
+                let entry = &self.oam[n * 4..n * 4 + 4];
+                if in_range(entry[0]) {
+                    let slot = self.sprite_count * 4;
+                    self.secondary_oam[slot..slot + 4].copy_from_slice(entry);
+                    self.sprite_count += 1;
+                }
+            }
+        }
+    }
+
+    fn sprite_pattern_addr(&self, tile: u8, attr: u8, row: u16) -> u16 {
+        let height = self.sprite_height();
+
+        // vertical flip
+        let row = if attr & 0x80 != 0 { height - 1 - (row & (height - 1)) } else { row & (height - 1) };
+
+        if height == 16 {
+            // 8x16 sprites take the pattern table from bit 0 of the tile
+            // and draw the top half from the even tile, the bottom from the odd one
+            let table = (tile as u16 & 0x01) * 0x1000;
+            let tile = (tile as u16 & 0xFE) + (row >> 3);
+            table + tile * 16 + (row & 0x07)
+        } else {
+            let table = if self.ctrl & 0x08 != 0 { 0x1000 } else { 0x0000 };
+            table + tile as u16 * 16 + row
+        }
+    }
+
+    // dots 257-320 fetch the patterns of the sprites for the next scanline,
+    // 8 dots per sprite. Unused slots still fetch tile $FF, which mappers
+    // watching the PPU address bus rely on.
+    fn fetch_sprites(&mut self, cartridge: &mut Cartridge) {
+        let step = (self.dot - 257) % 8;
+        let slot = ((self.dot - 257) / 8) as usize;
+        if step == 4 || step == 6 {
+            self.fetch_sprite(slot, step == 6, cartridge);
+        }
+
+        // the sprites past the 8th are fetched once the hardware is done
+        if self.dot == 320 {
+            for slot in SPRITES_PER_LINE..self.sprite_count {
+                self.fetch_sprite(slot, false, cartridge);
+                self.fetch_sprite(slot, true, cartridge);
+            }
+        }
+    }
+
+    fn fetch_sprite(&mut self, slot: usize, high: bool, cartridge: &mut Cartridge) {
+        let entry = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attr, x) = (entry[0], entry[1], entry[2], entry[3]);
+        let row = self.scanline.wrapping_sub(y as u16);
+
+        let mut addr = self.sprite_pattern_addr(tile, attr, row);
+        if high {
+            addr += 8;
+        }
+
+        let mut pattern = self.read(addr, cartridge);
+        if attr & 0x40 != 0 {
+            pattern = pattern.reverse_bits();
+        }
+
+        if slot >= self.sprite_count {
+            pattern = 0;
+        }
+
+        let sprite = &mut self.sprites[slot];
+        sprite.x = x;
+        sprite.attr = attr;
+        if high {
+            sprite.pattern_hi = pattern;
+        } else {
+            sprite.pattern_lo = pattern;
+        }
+    }
+
+    // returns (slot, pixel, palette, behind background) of the
+    // frontmost opaque sprite pixel at the current dot
+    fn sprite_pixel(&self) -> Option<(usize, u8, u8, bool)> {
+        let x = self.dot - 1;
+        if self.mask & 0x10 == 0 || (self.mask & 0x04 == 0 && x < 8) {
+            return None;
+        }
+
+        self.sprites[..self.sprites_on_line].iter().enumerate().find_map(|(slot, sprite)| {
+            let offset = x.wrapping_sub(sprite.x as u16);
+            if offset >= 8 {
+                return None;
+            }
+
+            let bit = 7 - offset;
+            let pixel = ((sprite.pattern_hi >> bit) & 0x01) << 1 | ((sprite.pattern_lo >> bit) & 0x01);
+            if pixel == 0 {
+                return None;
+            }
+
+            Some((slot, pixel, 4 + (sprite.attr & 0x03), sprite.attr & 0x20 != 0))
+        })
+    }
+
+    fn render_pixel(&mut self) {
+        let (bg_pixel, bg_palette) = self.background_pixel();
+        let sprite = self.sprite_pixel();
+
+        let (pixel, palette) = match sprite {
+            Some((slot, sprite_pixel, sprite_palette, behind)) => {
+                // sprite 0 hit: an opaque sprite 0 pixel overlaps an opaque
+                // background pixel. It never happens at x=255, nor in the
+                // left 8 pixels when either of them is clipped there.
+                let x = self.dot - 1;
+                let clipped = x < 8 && self.mask & 0x06 != 0x06;
+                if slot == 0 && self.sprite_zero_on_line && bg_pixel != 0 && x != 255 && !clipped {
+                    self.status |= 0x40;
+                }
+
+                if behind && bg_pixel != 0 {
+                    (bg_pixel, bg_palette)
+                } else {
+                    (sprite_pixel, sprite_palette)
+                }
+            },
+            None => (bg_pixel, bg_palette),
+        };
+
+        // transparent pixels show the universal background color at $3F00
+        let index = if pixel == 0 { 0 } else { (palette << 2) | pixel };
+        let mut color = self.palette[index as usize];
+
+        // greyscale keeps only the luminance column of the palette
+        if self.mask & 0x01 != 0 {
+            color &= 0x30;
+        }
+
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+        let mut emphasis = (self.mask as u16 & 0xE0) << 1;
+        if self.region.swaps_emphasis() {
+            // PPUMASK bit 5 is green and bit 6 red here,
+            // the framebuffer always keeps red in the lowest bit
+            emphasis = (emphasis & 0x100) | (emphasis & 0x80) >> 1 | (emphasis & 0x40) << 1;
+        }
+        self.framebuffer[y * SCREEN_WIDTH + x] = emphasis | (color & 0x3F) as u16;
+    }
+
+    /**
+     * One PPU dot.
+     * Scanlines 0-239 are visible, 240 is idle, 241-260 are vblank
+     * and 261 is the pre-render line which does the same memory accesses
+     * as a visible scanline to fill the shift registers for line 0.
+     * PAL has 70 lines of vblank (241-310), and the Dendy 50 more idle
+     * lines before its 20 lines of vblank (291-310).
+     *
+     * References:
+     * https://wiki.nesdev.org/w/index.php/PPU_rendering
+     * https://wiki.nesdev.org/w/images/4/4f/Ppu.svg
+     */
+    pub fn clock(&mut self, cartridge: &mut Cartridge) {
+        let visible = self.scanline < SCREEN_HEIGHT as u16;
+        let pre_render = self.scanline == self.region.pre_render_scanline();
+
+        if self.rendering_enabled() && (visible || pre_render) {
+            if (2..=257).contains(&self.dot) || (322..=337).contains(&self.dot) {
+                self.shift_background();
+            }
+
+            if (1..=256).contains(&self.dot) || (321..=336).contains(&self.dot) {
+                self.fetch_background(cartridge);
+            }
+
+            match self.dot {
+                256 => {
+                    self.increment_y();
+                    if visible {
+                        self.evaluate_sprites();
+                    }
+                },
+                257 => {
+                    self.load_background_shifters();
+                    self.copy_horizontal();
+                },
+                280..=304 if pre_render => self.copy_vertical(),
+
+                // unused nametable fetches at the end of the line
+                337 | 339 => {
+                    self.nt_latch = self.read(0x2000 | (self.v & 0x0FFF), cartridge);
+                },
+                _ => {},
+            }
+
+            if (257..=320).contains(&self.dot) {
+                // OAMADDR is reset during sprite fetches
+                self.oam_addr = 0;
+
+                if self.dot == 257 && pre_render {
+                    // no evaluation happens on the pre-render line
+                    self.sprite_count = 0;
+                    self.sprite_zero_next = false;
+                }
+                self.fetch_sprites(cartridge);
+            }
+        }
+
+        // the sprites fetched on the previous line are drawn on this one
+        if self.dot == 0 {
+            self.sprites_on_line = if self.rendering_enabled() { self.sprite_count } else { 0 };
+            self.sprite_zero_on_line = self.sprite_zero_next;
+        }
+
+        // the pixel is output after this dot's shift
+        if visible && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            if !self.suppress_vblank {
+                self.status |= 0x80;
+                if self.nmi_output() {
+                    self.nmi = true;
+                }
+            }
+            self.suppress_vblank = false;
+            self.frame_complete = true;
+        }
+
+        if pre_render && self.dot == 1 {
+            // clear vblank, sprite 0 hit and sprite overflow
+            self.status &= !0xE0;
+        }
+
+        self.dot += 1;
+
+        // the pre-render line is one dot shorter on odd frames when rendering
+        if pre_render && self.dot == DOTS - 1 && self.frame % 2 == 1 && self.rendering_enabled()
+            && self.region.skips_odd_dot() {
+            self.dot += 1;
+        }
+
+        if self.dot >= DOTS {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline >= self.region.scanlines() {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    // everything but the picture and the settings; the next frame redraws the picture
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.u8(&mut self.ctrl);
+        state.u8(&mut self.mask);
+        state.u8(&mut self.status);
+        state.u8(&mut self.oam_addr);
+        state.bytes(&mut self.oam);
+        state.u16(&mut self.v);
+        state.u16(&mut self.t);
+        state.u8(&mut self.x);
+        state.bool(&mut self.w);
+        state.u8(&mut self.read_buffer);
+        state.u8(&mut self.io_latch);
+        state.bytes(&mut self.nametables.ciram);
+        state.bytes(&mut self.palette);
+        state.u16(&mut self.scanline);
+        state.u16(&mut self.dot);
+        state.u64(&mut self.frame);
+        state.bool(&mut self.nmi);
+        state.bool(&mut self.suppress_vblank);
+        state.bool(&mut self.frame_complete);
+        state.u8(&mut self.nt_latch);
+        state.u8(&mut self.at_latch);
+        state.u8(&mut self.bg_lo_latch);
+        state.u8(&mut self.bg_hi_latch);
+        state.u16(&mut self.bg_shift_lo);
+        state.u16(&mut self.bg_shift_hi);
+        state.u16(&mut self.at_shift_lo);
+        state.u16(&mut self.at_shift_hi);
+        state.bytes(&mut self.secondary_oam);
+        state.usize(&mut self.sprite_count);
+        state.bool(&mut self.sprite_zero_next);
+        for sprite in self.sprites.iter_mut() {
+            state.u8(&mut sprite.x);
+            state.u8(&mut sprite.attr);
+            state.u8(&mut sprite.pattern_lo);
+            state.u8(&mut sprite.pattern_hi);
+        }
+        state.usize(&mut self.sprites_on_line);
+        state.bool(&mut self.sprite_zero_on_line);
+    }
+}
+
+// $3F10/$3F14/$3F18/$3F1C mirror $3F00/$3F04/$3F08/$3F0C
+fn palette_index(addr: u16) -> usize {
+    let index = addr & 0x001F;
+    let index = if index & 0x13 == 0x10 { index & 0x0F } else { index };
+    index as usize
+}
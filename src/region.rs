@@ -0,0 +1,112 @@
+// The console was sold in three timing variants. PAL consoles and the
+// Dendy famiclone both run off a 26.6 MHz master clock and draw 312
+// scanlines, but the Dendy divides its CPU clock like an NTSC console so
+// that NTSC games keep their CPU:PPU ratio and run at (nearly) full speed.
+//
+// References:
+// https://wiki.nesdev.org/w/index.php/Cycle_reference_chart
+// https://wiki.nesdev.org/w/index.php/Clock_rate
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub fn parse(name: &str) -> Option<Region> {
+        match name.to_ascii_lowercase().as_str() {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    // master clock frequency in Hz
+    pub fn master_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 236.25e6 / 11.0,
+            Region::Pal | Region::Dendy => 26_601_712.5,
+        }
+    }
+
+    pub fn cpu_clock_rate(self) -> f64 {
+        self.master_clock_rate() / self.cpu_divider() as f64
+    }
+
+    // master clocks per CPU cycle and per PPU dot.
+    // NTSC and Dendy give 3 dots per CPU cycle, PAL 3.2
+    pub fn cpu_divider(self) -> u8 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    pub fn ppu_divider(self) -> u8 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // the scanline vblank starts on. The Dendy keeps NTSC's 20 lines of
+    // vblank and pads the frame with 50 more idle lines before it instead
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(self) -> u16 {
+        self.scanlines() - 1
+    }
+
+    // only the NTSC PPU skips a dot on odd frames
+    pub fn skips_odd_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    // the 2C07 and the Dendy PPUs swap the red and green emphasis bits of PPUMASK
+    pub fn swaps_emphasis(self) -> bool {
+        self != Region::Ntsc
+    }
+
+    // CPU cycles at which the APU frame counter steps, for the 4-step and
+    // 5-step sequences. The last entries are where the sequence wraps.
+    // The Dendy APU is clocked like an NTSC one.
+    pub fn frame_counter_steps(self, five_step: bool) -> [u32; 6] {
+        match (self, five_step) {
+            (Region::Pal, false) => [8313, 16627, 24939, 33252, 33253, 33254],
+            (Region::Pal, true) => [8313, 16627, 24939, 33253, 41565, 41566],
+            (_, false) => [7457, 14913, 22371, 29828, 29829, 29830],
+            (_, true) => [7457, 14913, 22371, 29829, 37281, 37282],
+        }
+    }
+
+    // noise channel timer periods in CPU cycles
+    pub fn noise_periods(self) -> &'static [u16; 16] {
+        match self {
+            Region::Pal => &[4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778],
+            _ => &[4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068],
+        }
+    }
+
+    // DMC output rates in CPU cycles
+    pub fn dmc_rates(self) -> &'static [u16; 16] {
+        match self {
+            Region::Pal => &[398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50],
+            _ => &[428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54],
+        }
+    }
+}
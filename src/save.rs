@@ -0,0 +1,182 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cartridge::Cartridge;
+
+// Persistence of battery-backed PRG RAM, EEPROM and flash saves to a
+// .sav file next to the ROM. The save is loaded on startup, flushed
+// periodically while the game runs, on demand and on exit.
+pub struct SaveFile {
+    pub path: PathBuf,
+
+    // how often `autosave` writes pending changes to disk
+    pub interval: Duration,
+
+    last_flush: Instant,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> Self {
+        SaveFile {
+            path,
+            interval: Duration::from_secs(5),
+            last_flush: Instant::now(),
+        }
+    }
+
+    // game.nes -> game.sav
+    pub fn for_rom(rom_path: &Path) -> Self {
+        SaveFile::new(rom_path.with_extension("sav"))
+    }
+
+    // a missing save file is not an error; the game simply starts fresh
+    pub fn load(&self, cartridge: &mut Cartridge) -> Result<()> {
+        if cartridge.save_data().is_none() {
+            return Ok(());
+        }
+
+        match fs::read(&self.path) {
+            Ok(data) => {
+                cartridge.load_save_data(&data);
+                cartridge.clear_save_dirty();
+                Ok(())
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // write the save to disk if it changed since the last flush
+    pub fn flush(&mut self, cartridge: &mut Cartridge) -> Result<()> {
+        self.last_flush = Instant::now();
+
+        if !cartridge.save_dirty() {
+            return Ok(());
+        }
+
+        if let Some(data) = cartridge.save_data() {
+            write_atomic(&self.path, data)?;
+        }
+        cartridge.clear_save_dirty();
+        Ok(())
+    }
+
+    // call once per frame; flushes at most once every `interval`
+    pub fn autosave(&mut self, cartridge: &mut Cartridge) -> Result<()> {
+        if self.last_flush.elapsed() < self.interval {
+            return Ok(());
+        }
+        self.flush(cartridge)
+    }
+}
+
+// Write to a temporary file first and rename it over the old save,
+// so a crash midway leaves either the old or the new save intact,
+// never a half-written one.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::SaveFile;
+    use crate::cartridge::Cartridge;
+
+    // NROM with battery-backed PRG RAM
+    fn cartridge() -> Cartridge {
+        let mut rom = b"NES\x1A\x01\x01\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        Cartridge::from_bytes(&rom).unwrap()
+    }
+
+    fn save_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nesrs-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("game.sav")
+    }
+
+    #[test]
+    fn flush_writes_only_changes() {
+        let path = save_path("flush");
+        let mut save = SaveFile::new(path.clone());
+        let mut cartridge = cartridge();
+
+        save.flush(&mut cartridge).unwrap();
+        assert!(!path.exists());
+
+        cartridge.cpu_write(0x6000, 0x42);
+        save.flush(&mut cartridge).unwrap();
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0], 0x42);
+
+        // the temporary file is renamed over the save
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        fs::remove_file(&path).unwrap();
+        save.flush(&mut cartridge).unwrap();
+        assert!(!path.exists());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn autosave_waits_for_the_interval() {
+        let path = save_path("autosave");
+        let mut save = SaveFile::new(path.clone());
+        save.interval = Duration::from_secs(3600);
+        let mut cartridge = cartridge();
+
+        cartridge.cpu_write(0x6000, 0x42);
+        save.autosave(&mut cartridge).unwrap();
+        assert!(!path.exists());
+
+        save.interval = Duration::ZERO;
+        save.autosave(&mut cartridge).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn load() {
+        let path = save_path("load");
+        let save = SaveFile::new(path.clone());
+        let mut cartridge = cartridge();
+
+        // no save yet
+        save.load(&mut cartridge).unwrap();
+
+        fs::write(&path, [0x12, 0x34]).unwrap();
+        save.load(&mut cartridge).unwrap();
+        assert_eq!(cartridge.cpu_read(0x6000), 0x12);
+        assert_eq!(cartridge.cpu_read(0x6001), 0x34);
+        assert!(!cartridge.save_dirty());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
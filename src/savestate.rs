@@ -0,0 +1,199 @@
+use std::io::{Error, ErrorKind, Result};
+
+// Savestates: a snapshot of everything in the console that changes while
+// it runs, so it can be put back exactly as it was, e.g. to start a movie
+// from. Settings such as the region or the palette and the host side of
+// the input, which buttons are held, are not part of it.
+//
+// Every chip saves and loads itself through the same `savestate` method,
+// called with a `Savestate` that either writes each field out or reads
+// it back in, so the two directions can't get out of step.
+//
+// The layout is the fields one after another, little endian, after an
+// 8 byte signature and the MD5 of the ROM they were taken with. It is
+// only meant to be read back by the same version of the emulator.
+pub struct Savestate {
+    data: Vec<u8>,
+
+    // where the next field is read from, None while saving
+    pos: Option<usize>,
+
+    // a field was read past the end of the data, or memory had another size
+    truncated: bool,
+}
+
+const SIGNATURE: &[u8; 8] = b"NESRSST\x01";
+
+impl Savestate {
+    pub fn save() -> Self {
+        Savestate {
+            data: Vec::new(),
+            pos: None,
+            truncated: false,
+        }
+    }
+
+    pub fn load(data: Vec<u8>) -> Self {
+        Savestate {
+            data,
+            pos: Some(0),
+            truncated: false,
+        }
+    }
+
+    pub fn loading(&self) -> bool {
+        self.pos.is_some()
+    }
+
+    // The signature and ROM checksum. Loading fails when they don't match
+    pub fn header(&mut self, rom_checksum: [u8; 16]) -> Result<()> {
+        let mut signature = *SIGNATURE;
+        let mut checksum = rom_checksum;
+        self.bytes(&mut signature);
+        self.bytes(&mut checksum);
+
+        if signature != *SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not a savestate"));
+        }
+        if checksum != rom_checksum {
+            return Err(Error::new(ErrorKind::InvalidData, "the savestate was taken with a different ROM"));
+        }
+        Ok(())
+    }
+
+    // the saved state, or whether all of it was read back
+    pub fn finish(self) -> Result<Vec<u8>> {
+        match self.pos {
+            Some(pos) if self.truncated || pos != self.data.len() => {
+                Err(Error::new(ErrorKind::InvalidData, "the savestate doesn't match this console"))
+            },
+            _ => Ok(self.data),
+        }
+    }
+
+    pub fn bytes(&mut self, value: &mut [u8]) {
+        let pos = match self.pos {
+            Some(pos) => pos,
+            None => {
+                self.data.extend_from_slice(value);
+                return;
+            },
+        };
+
+        match self.data.get(pos..pos + value.len()) {
+            Some(data) => value.copy_from_slice(data),
+            None => self.truncated = true,
+        }
+        self.pos = Some(pos + value.len());
+    }
+
+    // a length, then the contents. The length has to match when loading,
+    // since the memory sizes are given by the cartridge
+    pub fn memory(&mut self, value: &mut [u8]) {
+        let mut len = value.len() as u32;
+        self.u32(&mut len);
+        if len as usize != value.len() {
+            self.truncated = true;
+            return;
+        }
+        self.bytes(value);
+    }
+
+    pub fn u8(&mut self, value: &mut u8) {
+        let mut bytes = [*value];
+        self.bytes(&mut bytes);
+        *value = bytes[0];
+    }
+
+    pub fn bool(&mut self, value: &mut bool) {
+        let mut byte = *value as u8;
+        self.u8(&mut byte);
+        *value = byte != 0;
+    }
+
+    pub fn u16(&mut self, value: &mut u16) {
+        let mut bytes = value.to_le_bytes();
+        self.bytes(&mut bytes);
+        *value = u16::from_le_bytes(bytes);
+    }
+
+    pub fn u32(&mut self, value: &mut u32) {
+        let mut bytes = value.to_le_bytes();
+        self.bytes(&mut bytes);
+        *value = u32::from_le_bytes(bytes);
+    }
+
+    pub fn u64(&mut self, value: &mut u64) {
+        let mut bytes = value.to_le_bytes();
+        self.bytes(&mut bytes);
+        *value = u64::from_le_bytes(bytes);
+    }
+
+    pub fn usize(&mut self, value: &mut usize) {
+        let mut value32 = *value as u32;
+        self.u32(&mut value32);
+        *value = value32 as usize;
+    }
+
+    // a presence byte, then the value
+    pub fn option_u8(&mut self, value: &mut Option<u8>) {
+        let mut some = value.is_some();
+        let mut inner = value.unwrap_or(0);
+        self.bool(&mut some);
+        self.u8(&mut inner);
+        *value = Some(inner).filter(|_| some);
+    }
+
+    pub fn option_u16(&mut self, value: &mut Option<u16>) {
+        let mut some = value.is_some();
+        let mut inner = value.unwrap_or(0);
+        self.bool(&mut some);
+        self.u16(&mut inner);
+        *value = Some(inner).filter(|_| some);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Savestate;
+
+    fn fields(state: &mut Savestate, values: &mut (u8, bool, u16, u64, Option<u8>, Vec<u8>)) {
+        state.u8(&mut values.0);
+        state.bool(&mut values.1);
+        state.u16(&mut values.2);
+        state.u64(&mut values.3);
+        state.option_u8(&mut values.4);
+        state.memory(&mut values.5);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut saved = (0x12, true, 0x3456, 0x789A_BCDE_F012_3456, Some(0x78), vec![1, 2, 3]);
+        let mut state = Savestate::save();
+        state.header([0xAA; 16]).unwrap();
+        fields(&mut state, &mut saved);
+        let data = state.finish().unwrap();
+        assert_eq!(data.len(), 24 + 1 + 1 + 2 + 8 + 2 + 4 + 3);
+
+        let mut loaded = (0, false, 0, 0, None, vec![0; 3]);
+        let mut state = Savestate::load(data.clone());
+        state.header([0xAA; 16]).unwrap();
+        fields(&mut state, &mut loaded);
+        state.finish().unwrap();
+        assert_eq!(loaded, saved);
+
+        // another ROM
+        assert!(Savestate::load(data.clone()).header([0xBB; 16]).is_err());
+
+        // memory of another size, and cut short
+        let mut state = Savestate::load(data.clone());
+        state.header([0xAA; 16]).unwrap();
+        fields(&mut state, &mut (0, false, 0, 0, None, vec![0; 4]));
+        assert!(state.finish().is_err());
+
+        let mut state = Savestate::load(data[..data.len() - 1].to_vec());
+        state.header([0xAA; 16]).unwrap();
+        fields(&mut state, &mut loaded);
+        assert!(state.finish().is_err());
+    }
+}
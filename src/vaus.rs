@@ -0,0 +1,82 @@
+use crate::savestate::Savestate;
+
+/**
+ * Arkanoid "Vaus" paddle, a potentiometer read through a shift register
+ * that is loaded on the strobe write to $4016 and read MSB first, inverted.
+ *
+ * NES, usually in port 2, $4016/$4017 read:
+ *   4: fire button - 1 while pressed
+ *   3: potentiometer, 8 bits
+ * Famicom, on the expansion port:
+ *   $4016 read bit 1: fire button
+ *   $4017 read bit 1: potentiometer, 9 bits - the position and a 0 after it
+ *
+ * Arkanoid expects positions of about $62 (left) to $F2 (right).
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Arkanoid_controller
+ */
+pub struct Vaus {
+    pub position: u8,
+    pub button: bool,
+
+    // what is left to shift out, in the top bits
+    shift: u16,
+    strobe: bool,
+}
+
+impl Vaus {
+    pub fn new() -> Self {
+        Vaus {
+            position: 0x80,
+            button: false,
+            shift: 0,
+            strobe: false,
+        }
+    }
+
+    // the Famicom unit shifts out one bit more
+    fn reload(&mut self) {
+        self.shift = !((self.position as u16) << 8);
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    fn next_bit(&mut self) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let bit = (self.shift >> 15) as u8;
+        self.shift <<= 1;
+        bit
+    }
+
+    // bits 3 and 4 of the port the NES paddle is in
+    pub fn read_nes(&mut self) -> u8 {
+        (self.button as u8) << 4 | self.next_bit() << 3
+    }
+
+    // bit 1 of $4016 (port 0) or $4017 (port 1)
+    pub fn read_famicom(&mut self, port: usize) -> u8 {
+        if port == 0 {
+            (self.button as u8) << 1
+        } else {
+            self.next_bit() << 1
+        }
+    }
+
+    pub fn power(&mut self) {
+        self.shift = 0;
+        self.strobe = false;
+    }
+
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.u16(&mut self.shift);
+        state.bool(&mut self.strobe);
+    }
+}
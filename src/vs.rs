@@ -0,0 +1,146 @@
+use crate::savestate::Savestate;
+
+// Checks that some Vs. System boards add on top of the PPU ID, so that
+// a game only runs on the cabinet it was sold for
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum VsProtection {
+    None,
+    // a counter read from $5E01, reset by reading $5E00
+    RbiBaseball,
+    TkoBoxing,
+    // a latch toggled by reading $5567
+    SuperXevious,
+}
+
+impl VsProtection {
+    pub fn parse(name: &str) -> Option<VsProtection> {
+        match name {
+            "none" => Some(VsProtection::None),
+            "rbi" => Some(VsProtection::RbiBaseball),
+            "tko" => Some(VsProtection::TkoBoxing),
+            "xevious" => Some(VsProtection::SuperXevious),
+            _ => None,
+        }
+    }
+
+    // high nybble of byte 13 of a NES 2.0 header for Vs. System games.
+    // Vs. Ice Climber Japan and the Dual System boards run as a plain Unisystem
+    pub fn from_nes2(hardware: u8) -> VsProtection {
+        match hardware {
+            1 => VsProtection::RbiBaseball,
+            2 => VsProtection::TkoBoxing,
+            3 => VsProtection::SuperXevious,
+            _ => VsProtection::None,
+        }
+    }
+}
+
+const TKO_BOXING_DATA: [u8; 32] = [
+    0xFF, 0xBF, 0xB7, 0x97, 0x97, 0x17, 0x57, 0x4F, 0x6F, 0x6B, 0xEB, 0xA9, 0xB1, 0x90, 0x94, 0x14,
+    0x56, 0x4E, 0x6F, 0x6B, 0xEB, 0xA9, 0xB1, 0x90, 0xD4, 0x5C, 0x3E, 0x26, 0x87, 0x83, 0x13, 0x00,
+];
+
+// how long a coin keeps the coin switch closed
+const COIN_FRAMES: u8 = 4;
+
+/**
+ * Cabinet hardware of the Vs. UniSystem arcade boards.
+ *
+ * $4016 read:
+ *   7: 0 on the main CPU
+ *   6: coin 2
+ *   5: coin 1
+ *   4-3: DIP switches 2 and 1
+ *   2: service button
+ *   0: controller data
+ * $4017 read:
+ *   7-2: DIP switches 8-3
+ *   0: controller data
+ * $4016 write:
+ *   2: bank select on mapper 99 boards
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Vs._System
+ * https://wiki.nesdev.org/w/index.php/INES_Mapper_099
+ */
+pub struct VsSystem {
+    pub protection: VsProtection,
+
+    // switch 1 is bit 0, switch 8 is bit 7
+    pub dip_switches: u8,
+
+    pub service: bool,
+
+    // frames left that each coin switch stays closed
+    coin_timers: [u8; 2],
+
+    protection_counter: u8,
+}
+
+impl VsSystem {
+    pub fn new(protection: VsProtection, dip_switches: u8) -> Self {
+        VsSystem {
+            protection,
+            dip_switches,
+            service: false,
+            coin_timers: [0; 2],
+            protection_counter: 0,
+        }
+    }
+
+    // drop a coin in slot 0 or 1
+    pub fn insert_coin(&mut self, slot: usize) {
+        self.coin_timers[slot] = COIN_FRAMES;
+    }
+
+    pub fn end_frame(&mut self) {
+        for timer in self.coin_timers.iter_mut() {
+            *timer = timer.saturating_sub(1);
+        }
+    }
+
+    // the cabinet bits of $4016 or $4017, without the controller data
+    pub fn read_port(&self, addr: u16) -> u8 {
+        if addr == 0x4016 {
+            let coin = |slot: usize| if self.coin_timers[slot] > 0 { 1 } else { 0 };
+            coin(1) << 6 | coin(0) << 5 | (self.dip_switches & 0x03) << 3 | (self.service as u8) << 2
+        } else {
+            self.dip_switches & 0xFC
+        }
+    }
+
+    // protection chips answer reads in $4020-$5FFF
+    pub fn protection_read(&mut self, addr: u16) -> Option<u8> {
+        match (self.protection, addr) {
+            // the reset read itself returns whatever the cartridge does
+            (VsProtection::RbiBaseball | VsProtection::TkoBoxing, 0x5E00) => {
+                self.protection_counter = 0;
+                None
+            },
+            (VsProtection::RbiBaseball, 0x5E01) => {
+                let count = self.protection_counter;
+                self.protection_counter = self.protection_counter.wrapping_add(1);
+                Some(if count == 9 { 0x6F } else { 0xB4 })
+            },
+            (VsProtection::TkoBoxing, 0x5E01) => {
+                let value = TKO_BOXING_DATA[(self.protection_counter & 0x1F) as usize];
+                self.protection_counter = self.protection_counter.wrapping_add(1);
+                Some(value)
+            },
+            (VsProtection::SuperXevious, 0x54FF) => Some(0x05),
+            (VsProtection::SuperXevious, 0x5678) => Some(if self.protection_counter != 0 { 0x00 } else { 0x01 }),
+            (VsProtection::SuperXevious, 0x578F) => Some(if self.protection_counter != 0 { 0xD1 } else { 0x89 }),
+            (VsProtection::SuperXevious, 0x5567) => {
+                self.protection_counter ^= 1;
+                Some(if self.protection_counter != 0 { 0x37 } else { 0x3E })
+            },
+            _ => None,
+        }
+    }
+
+    // the coin timers and protection; the switches are settings
+    pub fn savestate(&mut self, state: &mut Savestate) {
+        state.bytes(&mut self.coin_timers);
+        state.u8(&mut self.protection_counter);
+    }
+}
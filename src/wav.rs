@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::apu::APU;
+use crate::audio::{self, Channel};
+
+/**
+ * 16-bit PCM WAV file, written as samples arrive. The sizes in the
+ * header are only known at the end, so `finish` goes back to fill them in.
+ *
+ * 0-3:   "RIFF"
+ * 4-7:   file size - 8
+ * 8-11:  "WAVE"
+ * 12-35: "fmt " chunk - format 1 (PCM), channels, sample rate,
+ *        byte rate, block align, bits per sample
+ * 36-39: "data"
+ * 40-43: data size
+ * 44-:   little-endian samples, interleaved
+ *
+ * References:
+ * http://soundfile.sapp.org/doc/WaveFormat/
+ */
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let block_align = channels * 2;
+        file.write_all(b"RIFF")?;
+        file.write_all(&36u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file,
+            data_size: 0,
+        })
+    }
+
+    pub fn write(&mut self, samples: &[i16]) -> Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+// song.wav -> song.triangle.wav
+pub fn stem_path(path: &Path, channel: Channel) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.wav", stem, channel.name()))
+}
+
+// Records the mixed audio, and optionally each channel on its own
+// next to it, one frame at a time
+pub struct AudioRecorder {
+    mix: WavWriter,
+    stems: Vec<WavWriter>,
+
+    // the first error, reported by `finish` so that emulation can go on
+    error: Option<Error>,
+}
+
+impl AudioRecorder {
+    pub fn create(path: &Path, sample_rate: u32, stems: bool) -> Result<Self> {
+        let mix = WavWriter::create(path, sample_rate, 1)?;
+        let stems = if stems {
+            Channel::ALL.iter()
+                .map(|&channel| WavWriter::create(&stem_path(path, channel), sample_rate, 1))
+                .collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(AudioRecorder {
+            mix,
+            stems,
+            error: None,
+        })
+    }
+
+    pub fn records_stems(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    // called after each frame with the APU's output still in place
+    pub fn write_frame(&mut self, apu: &APU) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let mut result = self.mix.write(&audio::to_i16(apu.output.last_frame()));
+        for (writer, stem) in self.stems.iter_mut().zip(apu.stems.iter()) {
+            result = result.and_then(|_| writer.write(&audio::to_i16(stem.last_frame())));
+        }
+        self.error = result.err();
+    }
+
+    pub fn finish(self) -> Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        self.mix.finish()?;
+        for writer in self.stems {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
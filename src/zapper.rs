@@ -0,0 +1,71 @@
+use crate::ppu::{DOTS, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// how far around the aimed pixel the sensor sees
+const RADIUS: i32 = 3;
+
+// the photodiode stays lit for about this many scanlines after
+// the beam draws something bright in front of it
+const SENSE_SCANLINES: u32 = 24;
+
+/**
+ * NES Zapper light gun, usually in port 2.
+ *
+ * $4016/$4017 read:
+ *   4: trigger - 1 while pulled
+ *   3: light sensed - 0 while the sensor sees light
+ *
+ * The sensor only sees the CRT beam as it passes, so the framebuffer is
+ * checked against the PPU's position: a bright pixel near the aim counts
+ * if it was drawn during the last few scanlines. Pixels the beam hasn't
+ * reached yet still hold the last frame and have long faded.
+ *
+ * References:
+ * https://wiki.nesdev.org/w/index.php/Zapper
+ */
+#[derive(Default)]
+pub struct Zapper {
+    // the pixel aimed at, None when pointing away from the screen
+    pub aim: Option<(u16, u16)>,
+    pub trigger: bool,
+}
+
+impl Zapper {
+    pub fn read(&self, ppu: &PPU) -> u8 {
+        let light = if self.senses_light(ppu) { 0x00 } else { 0x08 };
+        (self.trigger as u8) << 4 | light
+    }
+
+    fn senses_light(&self, ppu: &PPU) -> bool {
+        let (aim_x, aim_y) = match self.aim {
+            Some(aim) => (aim.0 as i32, aim.1 as i32),
+            None => return false,
+        };
+
+        let frame_dots = ppu.region.scanlines() as u32 * DOTS as u32;
+        let now = ppu.scanline as u32 * DOTS as u32 + ppu.dot as u32;
+
+        for y in (aim_y - RADIUS).max(0)..=(aim_y + RADIUS).min(SCREEN_HEIGHT as i32 - 1) {
+            for x in (aim_x - RADIUS).max(0)..=(aim_x + RADIUS).min(SCREEN_WIDTH as i32 - 1) {
+                // pixel x is drawn on dot x + 1
+                let drawn = y as u32 * DOTS as u32 + x as u32 + 1;
+                let age = (now + frame_dots - drawn) % frame_dots;
+                if age >= SENSE_SCANLINES * DOTS as u32 {
+                    continue;
+                }
+
+                if is_bright(ppu.framebuffer[y as usize * SCREEN_WIDTH + x as usize]) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+// The light colors of the master palette: the two top luminance rows,
+// leaving out the dark greys and blacks of columns $D-$F
+fn is_bright(pixel: u16) -> bool {
+    let color = pixel & 0x3F;
+    color >= 0x20 && color & 0x0F < 0x0D
+}
@@ -0,0 +1,214 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::hash;
+use crate::inflate;
+
+/**
+ * Read-only zip archives, stored or deflated, which is all BizHawk and
+ * Mesen write their movies with.
+ *
+ * The central directory at the end lists every file:
+ *   end of central directory record ("PK\x05\x06"):
+ *     10: number of entries, 16: offset of the central directory
+ *   central directory entry ("PK\x01\x02"):
+ *     10: method (0 stored, 8 deflate), 16: CRC-32, 20: compressed size,
+ *     24: size, 28: name length, 30: extra length, 32: comment length,
+ *     42: offset of the local header, 46: name
+ *   local header ("PK\x03\x04"):
+ *     26: name length, 28: extra length, 30: name, then extra, then data
+ *
+ * References:
+ * https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+ */
+pub struct ZipArchive<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+
+    // offset of the local header
+    header: usize,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).ok_or_else(|| invalid("truncated zip file"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| invalid("truncated zip file"))
+}
+
+impl<'a> ZipArchive<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        // the end record is followed by a comment of up to 64 KB
+        let end = (0..data.len().saturating_sub(21)).rev()
+            .take(0x10000 + 22)
+            .find(|&offset| data[offset..].starts_with(b"PK\x05\x06"))
+            .ok_or_else(|| invalid("not a zip file"))?;
+
+        let count = u16_at(data, end + 10)? as usize;
+        let mut offset = u32_at(data, end + 16)? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            if !data.get(offset..).is_some_and(|entry| entry.starts_with(b"PK\x01\x02")) {
+                return Err(invalid("corrupt zip central directory"));
+            }
+
+            let name_length = u16_at(data, offset + 28)? as usize;
+            let extra_length = u16_at(data, offset + 30)? as usize;
+            let comment_length = u16_at(data, offset + 32)? as usize;
+            let name = data.get(offset + 46..offset + 46 + name_length).ok_or_else(|| invalid("truncated zip file"))?;
+
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(data, offset + 10)?,
+                crc: u32_at(data, offset + 16)?,
+                compressed_size: u32_at(data, offset + 20)? as usize,
+                size: u32_at(data, offset + 24)? as usize,
+                header: u32_at(data, offset + 42)? as usize,
+            });
+            offset += 46 + name_length + extra_length + comment_length;
+        }
+
+        Ok(ZipArchive { data, entries })
+    }
+
+    // the contents of the file `name`, None if there is no such file
+    pub fn read(&self, name: &str) -> Option<Result<Vec<u8>>> {
+        let entry = self.entries.iter().find(|entry| entry.name == name)?;
+        Some(self.extract(entry))
+    }
+
+    fn extract(&self, entry: &Entry) -> Result<Vec<u8>> {
+        let header = entry.header;
+        if !self.data.get(header..).is_some_and(|local| local.starts_with(b"PK\x03\x04")) {
+            return Err(invalid("corrupt zip local header"));
+        }
+        let start = header + 30 + u16_at(self.data, header + 26)? as usize + u16_at(self.data, header + 28)? as usize;
+        let compressed = self.data.get(start..start + entry.compressed_size).ok_or_else(|| invalid("truncated zip file"))?;
+
+        let contents = match entry.method {
+            0 => compressed.to_vec(),
+            8 => inflate::inflate(compressed)?,
+            _ => return Err(invalid("unsupported zip compression method")),
+        };
+
+        if contents.len() != entry.size || hash::crc32(&contents) != entry.crc {
+            return Err(invalid("zip file failed its CRC check"));
+        }
+        Ok(contents)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::ZipArchive;
+    use crate::hash;
+
+    // A zip of (name, method, stored data, contents) entries,
+    // with a local header for each and the central directory after them
+    pub fn zip(files: &[(&str, u16, &[u8], &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+
+        for &(name, method, stored, contents) in files {
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&[0; 4]); // time, date
+            fields.extend_from_slice(&hash::crc32(contents).to_le_bytes());
+            fields.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0; 2]); // extra length
+
+            directory.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00\x00\x00");
+            directory.extend_from_slice(&fields);
+            directory.extend_from_slice(&[0; 10]); // comment length, disk, attributes
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+
+            data.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00");
+            data.extend_from_slice(&fields);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(stored);
+        }
+
+        let offset = data.len() as u32;
+        let count = files.len() as u16;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]); // comment length
+        data
+    }
+
+    // text files, stored
+    pub fn text_zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let files: Vec<_> = files.iter().map(|&(name, text)| (name, 0, text.as_bytes(), text.as_bytes())).collect();
+        zip(&files)
+    }
+
+    #[test]
+    fn stored_entries() {
+        let data = text_zip(&[("a.txt", "first"), ("dir/b.txt", "second file")]);
+        let archive = ZipArchive::parse(&data).unwrap();
+        assert_eq!(archive.read("a.txt").unwrap().unwrap(), b"first");
+        assert_eq!(archive.read("dir/b.txt").unwrap().unwrap(), b"second file");
+        assert!(archive.read("b.txt").is_none());
+    }
+
+    #[test]
+    fn deflated_entry() {
+        // "Hello, Hello, Hello!" with the fixed codes
+        let deflated = [0xF3, 0x48, 0xCD, 0xC9, 0xC9, 0xD7, 0x51, 0xF0, 0x40, 0xA2, 0x14, 0x01];
+        let data = zip(&[("hello.txt", 8, &deflated, b"Hello, Hello, Hello!")]);
+        let archive = ZipArchive::parse(&data).unwrap();
+        assert_eq!(archive.read("hello.txt").unwrap().unwrap(), b"Hello, Hello, Hello!");
+    }
+
+    #[test]
+    fn archive_comment() {
+        // the end record is found before a comment
+        let mut data = text_zip(&[("a.txt", "first")]);
+        let len = data.len();
+        data[len - 2] = 7;
+        data.extend_from_slice(b"comment");
+        let archive = ZipArchive::parse(&data).unwrap();
+        assert_eq!(archive.read("a.txt").unwrap().unwrap(), b"first");
+    }
+
+    #[test]
+    fn invalid_archives() {
+        assert!(ZipArchive::parse(b"not a zip file at all, but long enough").is_err());
+
+        // contents that don't match the CRC or size, and an unknown method
+        let data = zip(&[("bad.txt", 0, b"data", b"date")]);
+        assert!(ZipArchive::parse(&data).unwrap().read("bad.txt").unwrap().is_err());
+        let data = zip(&[("short.txt", 0, b"data", b"data!")]);
+        assert!(ZipArchive::parse(&data).unwrap().read("short.txt").unwrap().is_err());
+        let data = zip(&[("lzma.txt", 14, b"data", b"data")]);
+        assert!(ZipArchive::parse(&data).unwrap().read("lzma.txt").unwrap().is_err());
+
+        // a central directory that points past the end
+        let mut data = text_zip(&[("a.txt", "first")]);
+        let len = data.len();
+        data[len - 6] = 0xFF;
+        assert!(ZipArchive::parse(&data).is_err());
+    }
+}